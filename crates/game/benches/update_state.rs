@@ -0,0 +1,353 @@
+//! Benchmarks of standard simulation scenarios, run with `just bench`.
+//!
+//! Every `update_state` benchmark iteration is [`TICKS_PER_ITERATION`] ticks of a freshly prepared
+//! world, warmed up so that its cells are already moving. Throughput is per updated chunk.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use game::*;
+use std::hint::black_box;
+
+const SEED: u64 = 0;
+const CHUNK: i32 = CHUNK_SIZE as i32;
+/// Ticks of the `update_state` benchmarks in every iteration
+const TICKS_PER_ITERATION: usize = 5;
+
+/// Creates the world of the scenario
+type Scenario = fn(&CellsTemplate) -> WorldState;
+
+fn fill(
+    world: &mut WorldState,
+    cells_template: &CellsTemplate,
+    label: &str,
+    min: (i32, i32),
+    max: (i32, i32),
+) {
+    let cell_meta = cells_template.get_cell_meta_by_label(label).unwrap();
+    gen_rect(
+        world,
+        cells_template,
+        GlobalCellPos::new(min.0, min.1),
+        GlobalCellPos::new(max.0, max.1),
+        cell_meta,
+    )
+    .unwrap();
+}
+
+/// Cells created at tick 0 are processed from the next one, skip the ticks where nothing moves yet
+fn warm_up(world: &mut WorldState, cells_template: &CellsTemplate, ticks: usize) {
+    for _ in 0..ticks {
+        world.update_state(cells_template).unwrap();
+    }
+}
+
+/// Update the world for several ticks, returns the number of updated chunks
+fn update_ticks(world: &mut WorldState, cells_template: &CellsTemplate) -> usize {
+    (0..TICKS_PER_ITERATION)
+        .map(|_| world.update_state(cells_template).unwrap())
+        .sum()
+}
+
+/// Chunk full of sand above the stone floor
+fn falling_sand(cells_template: &CellsTemplate) -> WorldState {
+    let mut world = WorldState::with_seed(SEED);
+    fill(
+        &mut world,
+        cells_template,
+        CELL_STONE_LABEL,
+        (-CHUNK, 0),
+        (CHUNK * 2, 4),
+    );
+    fill(
+        &mut world,
+        cells_template,
+        CELL_SAND_LABEL,
+        (0, CHUNK),
+        (CHUNK, CHUNK * 2),
+    );
+    warm_up(&mut world, cells_template, 10);
+    world
+}
+
+/// Stone basin two chunks wide and two chunks high
+fn water_basin(cells_template: &CellsTemplate) -> WorldState {
+    let mut world = WorldState::with_seed(SEED);
+    fill(
+        &mut world,
+        cells_template,
+        CELL_STONE_LABEL,
+        (0, 0),
+        (CHUNK * 2, 4),
+    );
+    fill(
+        &mut world,
+        cells_template,
+        CELL_STONE_LABEL,
+        (0, 4),
+        (4, CHUNK * 2),
+    );
+    fill(
+        &mut world,
+        cells_template,
+        CELL_STONE_LABEL,
+        (CHUNK * 2 - 4, 4),
+        (CHUNK * 2, CHUNK * 2),
+    );
+    world
+}
+
+/// Stone basin with a block of water falling into it
+fn water_pool(cells_template: &CellsTemplate) -> WorldState {
+    let mut world = water_basin(cells_template);
+    fill(
+        &mut world,
+        cells_template,
+        CELL_WATER_LABEL,
+        (4, CHUNK / 2),
+        (CHUNK, CHUNK * 3 / 2),
+    );
+    warm_up(&mut world, cells_template, 10);
+    world
+}
+
+/// Block of sand falling into a water pool, sand soaks the water and sinks through it. Moves many
+/// cells by swapping them, mostly measures reading and writing cells of the chunks.
+fn sand_into_water(cells_template: &CellsTemplate) -> WorldState {
+    let mut world = water_basin(cells_template);
+    fill(
+        &mut world,
+        cells_template,
+        CELL_WATER_LABEL,
+        (4, 4),
+        (CHUNK * 2 - 4, CHUNK / 2),
+    );
+    fill(
+        &mut world,
+        cells_template,
+        CELL_SAND_LABEL,
+        (CHUNK / 2, CHUNK / 2 + 8),
+        (CHUNK * 3 / 2, CHUNK),
+    );
+    warm_up(&mut world, cells_template, 20);
+    world
+}
+
+/// Thousands of particles flying above the stone floor
+fn particles(cells_template: &CellsTemplate) -> WorldState {
+    let mut world = WorldState::with_seed(SEED);
+    fill(
+        &mut world,
+        cells_template,
+        CELL_STONE_LABEL,
+        (-CHUNK, 0),
+        (CHUNK * 2, 4),
+    );
+
+    let sand = cells_template
+        .get_cell_meta_by_label(CELL_SAND_LABEL)
+        .unwrap();
+    for y in 0..50 {
+        for x in 0..100 {
+            let pos = GlobalCellPos::new(x * 2 - CHUNK / 2, CHUNK + y * 2);
+            world
+                .add_particle_rand_vel(pos, sand, cells_template)
+                .unwrap();
+        }
+    }
+    warm_up(&mut world, cells_template, 10);
+    world
+}
+
+fn bench_update_state(c: &mut Criterion) {
+    let cells_template = default_cells();
+    let scenarios: [(&str, Scenario); 4] = [
+        ("falling_sand", falling_sand),
+        ("water_pool", water_pool),
+        ("sand_into_water", sand_into_water),
+        ("particles", particles),
+    ];
+
+    let mut group = c.benchmark_group("update_state");
+    group.sample_size(20);
+
+    for (name, setup) in scenarios {
+        // the same seed always updates the same chunks
+        let chunks_updated = update_ticks(&mut setup(&cells_template), &cells_template);
+        group.throughput(Throughput::Elements(chunks_updated as u64));
+
+        group.bench_function(name, |b| {
+            b.iter_batched(
+                || setup(&cells_template),
+                |mut world| black_box(update_ticks(&mut world, &cells_template)),
+                BatchSize::PerIteration,
+            )
+        });
+    }
+
+    group.finish();
+}
+
+/// Large world where nothing moves, measures cost of the chunks scheduling
+fn bench_idle_world(c: &mut Criterion) {
+    let cells_template = default_cells();
+
+    let mut world = WorldState::with_seed(SEED);
+    fill(
+        &mut world,
+        &cells_template,
+        CELL_STONE_LABEL,
+        (-CHUNK * 16, 0),
+        (CHUNK * 16, CHUNK * 4),
+    );
+    while world.update_state(&cells_template).unwrap() > 0 {}
+    let chunks = world.len() as u64;
+
+    let mut group = c.benchmark_group("update_state");
+    group.throughput(Throughput::Elements(chunks));
+    group.bench_function("idle_world", |b| {
+        b.iter(|| black_box(world.update_state(&cells_template).unwrap()))
+    });
+    group.finish();
+}
+
+/// Wet sand drying and wood burning, most of the time is spent in cell rules
+fn burning_wood(cells_template: &CellsTemplate) -> WorldState {
+    let mut world = WorldState::with_seed(SEED);
+    fill(
+        &mut world,
+        cells_template,
+        CELL_STONE_LABEL,
+        (-CHUNK, 0),
+        (CHUNK * 2, 4),
+    );
+    fill(
+        &mut world,
+        cells_template,
+        CELL_WET_SAND_LABEL,
+        (-CHUNK, 4),
+        (0, CHUNK),
+    );
+    fill(
+        &mut world,
+        cells_template,
+        CELL_WOOD_LABEL,
+        (0, 4),
+        (CHUNK * 2, CHUNK),
+    );
+    fill(
+        &mut world,
+        cells_template,
+        CELL_FIRE_LABEL,
+        (0, CHUNK),
+        (CHUNK * 2, CHUNK + 2),
+    );
+    // let the fire spread a bit
+    warm_up(&mut world, cells_template, 10);
+    world
+}
+
+/// Template without movement and heat, cells only run nested symmetry rules
+const MOSS_TEMPLATE: &str = r#"(cells: [
+    (label: "Vacuum", color: Plain((0, 0, 0, 0))),
+    (
+        label: "Moss",
+        color: Plain((40, 160, 40, 255)),
+        rule: FirstSuccess([
+            SymmetryDiagonal(SymmetryY(If(
+                condition: And([
+                    RelativeCell(pos: (x: 0, y: 1), cell_id: "Vacuum"),
+                    Chance(numerator: 1, denominator: 50),
+                ]),
+                action: InitCell(pos: (x: 0, y: 1), cell_id: "Moss"),
+                else_action: None,
+            ))),
+            SymmetryDiagonal(SymmetryX(If(
+                condition: And([
+                    RelativeCell(pos: (x: 1, y: 1), cell_id: "Moss"),
+                    RelativeCell(pos: (x: -1, y: -1), cell_id: "Moss"),
+                    Chance(numerator: 1, denominator: 200),
+                ]),
+                action: InitCell(pos: (x: 0, y: 0), cell_id: "Vacuum"),
+                else_action: None,
+            ))),
+        ]),
+    ),
+])"#;
+
+/// Four chunks of moss growing and dying
+fn moss(cells_template: &CellsTemplate) -> WorldState {
+    let mut world = WorldState::with_seed(SEED);
+    fill(
+        &mut world,
+        cells_template,
+        "Moss",
+        (0, 0),
+        (CHUNK * 2, CHUNK * 2),
+    );
+    warm_up(&mut world, cells_template, 1);
+    world
+}
+
+fn bench_rule_evaluator(c: &mut Criterion) {
+    let default_template = default_cells();
+    let moss_template = parse_cells_template(MOSS_TEMPLATE).unwrap();
+    let scenarios: [(&str, &CellsTemplate, Scenario); 2] = [
+        ("burning_wood", &default_template, burning_wood),
+        ("moss", &moss_template, moss),
+    ];
+
+    let mut group = c.benchmark_group("rule_evaluator");
+    group.sample_size(20);
+
+    for (name, cells_template, setup) in scenarios {
+        for (evaluator_name, evaluator) in [
+            ("tree", RuleEvaluator::Tree),
+            ("bytecode", RuleEvaluator::Bytecode),
+        ] {
+            group.bench_function(format!("{name}/{evaluator_name}"), |b| {
+                b.iter_batched(
+                    || {
+                        let mut world = setup(cells_template);
+                        world.set_rule_evaluator(evaluator);
+                        world
+                    },
+                    |mut world| black_box(world.update_state(cells_template).unwrap()),
+                    BatchSize::PerIteration,
+                )
+            });
+        }
+    }
+
+    group.finish();
+}
+
+fn bench_chunk_pixels(c: &mut Criterion) {
+    let cells_template = default_cells();
+
+    let mut world = particles(&cells_template);
+    fill(
+        &mut world,
+        &cells_template,
+        CELL_SAND_LABEL,
+        (0, CHUNK / 2),
+        (CHUNK, CHUNK),
+    );
+    world.update_state(&cells_template).unwrap();
+    let chunk = world.get_chunk(ChunkPos::new(0, 0)).unwrap();
+    let mut pixels = vec![[0; 4]; CHUNK_AREA];
+
+    let mut group = c.benchmark_group("chunk_texture");
+    group.throughput(Throughput::Elements(CHUNK_AREA as u64));
+    group.bench_function("write_pixels", |b| {
+        b.iter(|| chunk.write_pixels(&cells_template, black_box(&mut pixels)))
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_update_state,
+    bench_idle_world,
+    bench_rule_evaluator,
+    bench_chunk_pixels
+);
+criterion_main!(benches);
@@ -0,0 +1,176 @@
+//! Run the simulation without a window, e.g. on CI boxes without GPU.
+//!
+//! Usage: `sand-headless [options]` or `sand-headless check [template]`
+//!
+//! - `--template <path>` cells template, bundled one is used by default
+//! - `--world <path>` save to start from, otherwise world is generated with [`gen_world`]
+//! - `--seed <u64>` seed of the generated world
+//! - `--ticks <n>` amount of ticks to simulate (default 1000)
+//! - `--save <path>` write world save after the run
+//! - `--png <path>` write snapshot of the whole world after the run
+//! - `--stats <path>` write CSV with statistics of every tick
+//!
+//! `check` validates cells template (bundled one by default) and prints found problems, it fails
+//! if template has errors.
+
+use eyre::{bail, eyre, WrapErr};
+use game::*;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+const DEFAULT_TICKS: u32 = 1000;
+
+#[derive(Debug, Default)]
+struct Args {
+    template: Option<PathBuf>,
+    world: Option<PathBuf>,
+    seed: Option<u64>,
+    ticks: Option<u32>,
+    save: Option<PathBuf>,
+    png: Option<PathBuf>,
+    stats: Option<PathBuf>,
+}
+
+impl Args {
+    fn parse(mut args: impl Iterator<Item = String>) -> eyre::Result<Self> {
+        let mut result = Self::default();
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| eyre!("Missing value for {arg}"));
+
+            match arg.as_str() {
+                "--template" => result.template = Some(value()?.into()),
+                "--world" => result.world = Some(value()?.into()),
+                "--seed" => result.seed = Some(value()?.parse().wrap_err("Invalid seed")?),
+                "--ticks" => result.ticks = Some(value()?.parse().wrap_err("Invalid ticks")?),
+                "--save" => result.save = Some(value()?.into()),
+                "--png" => result.png = Some(value()?.into()),
+                "--stats" => result.stats = Some(value()?.into()),
+                _ => bail!("Unknown argument {arg:?}"),
+            }
+        }
+
+        if result.world.is_some() && result.seed.is_some() {
+            bail!("--seed can't be used with --world");
+        }
+
+        Ok(result)
+    }
+}
+
+struct TickStats {
+    tick: u32,
+    chunks_updated: usize,
+    chunks_loaded: usize,
+    duration: Duration,
+}
+
+fn main() -> eyre::Result<()> {
+    let mut args = std::env::args().skip(1).peekable();
+    if args.peek().map(String::as_str) == Some("check") {
+        args.next();
+        return check_template(args.next(), args.next());
+    }
+
+    let args = Args::parse(args)?;
+
+    let cells_template = match &args.template {
+        Some(path) => load_cells_template(path)?,
+        None => default_cells(),
+    };
+
+    let mut world = match &args.world {
+        Some(path) => load_world(path, &cells_template)?,
+        None => {
+            let mut world = match args.seed {
+                Some(seed) => WorldState::with_seed(seed),
+                None => WorldState::new(),
+            };
+            gen_world(&mut world, &cells_template)?;
+            world
+        }
+    };
+    println!("World seed: {}", world.seed());
+
+    let ticks = args.ticks.unwrap_or(DEFAULT_TICKS);
+    let mut stats = Vec::with_capacity(ticks as usize);
+    let start = Instant::now();
+
+    for _ in 0..ticks {
+        let tick = world.current_tick();
+        let tick_start = Instant::now();
+        let chunks_updated = world.update_state(&cells_template)?;
+
+        stats.push(TickStats {
+            tick,
+            chunks_updated,
+            chunks_loaded: world.len(),
+            duration: tick_start.elapsed(),
+        });
+    }
+
+    let total = start.elapsed();
+    let max_tick = stats.iter().map(|s| s.duration).max().unwrap_or_default();
+    println!(
+        "Simulated {ticks} ticks in {total:.2?} (avg {:.2?}, max {max_tick:.2?}), {} chunks",
+        total / ticks.max(1),
+        world.len(),
+    );
+
+    if let Some(path) = &args.stats {
+        write_stats(path, &stats)
+            .wrap_err_with(|| format!("Failed to write stats {}", path.display()))?;
+    }
+
+    if let Some(path) = &args.save {
+        save_world(path, &world, &cells_template)?;
+    }
+
+    if let Some(path) = &args.png {
+        WorldSnapshot::capture(&world, &cells_template)?.save_png(path)?;
+    }
+
+    Ok(())
+}
+
+fn write_stats(path: &Path, stats: &[TickStats]) -> eyre::Result<()> {
+    let mut writer = BufWriter::new(std::fs::File::create(path)?);
+
+    writeln!(writer, "tick,chunks_updated,chunks_loaded,duration_us")?;
+    for tick_stats in stats {
+        writeln!(
+            writer,
+            "{},{},{},{}",
+            tick_stats.tick,
+            tick_stats.chunks_updated,
+            tick_stats.chunks_loaded,
+            tick_stats.duration.as_micros(),
+        )?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+fn check_template(path: Option<String>, extra: Option<String>) -> eyre::Result<()> {
+    if let Some(extra) = extra {
+        bail!("Unexpected argument {extra:?}, usage: check [template]");
+    }
+
+    let cells_template = match &path {
+        Some(path) => load_cells_template(path)?,
+        None => parse_cells_template(DEFAULT_CELLS_TEMPLATE)?,
+    };
+
+    for warning in &cells_template.warnings {
+        println!("{warning}");
+    }
+    println!(
+        "{} cells, {} warnings",
+        cells_template.cells.len(),
+        cells_template.warnings.len()
+    );
+
+    Ok(())
+}
@@ -0,0 +1,106 @@
+use crate::*;
+use macroquad::prelude::*;
+use nohash_hasher::IntMap;
+
+/// Cache of chunk textures, a texture is rebuilt only when [`Chunk::generation`] changes and only
+/// changed rows are uploaded when possible (see [`Chunk::changed_rect_since`]).
+#[derive(Default)]
+pub struct ChunkRenderer {
+    textures: IntMap<ChunkPos, ChunkTexture>,
+}
+
+struct ChunkTexture {
+    texture: Texture2D,
+    image: Image,
+    generation: u64,
+}
+
+impl ChunkTexture {
+    /// Rewrite pixels of cells in `rect` and upload only rows containing them
+    fn update_rect(&mut self, chunk: &Chunk, cells_template: &CellsTemplate, rect: DirtyRect) {
+        let rect = rect.intersect(DirtyRect::CHUNK);
+        if rect.is_empty() {
+            return;
+        }
+
+        chunk.write_pixels_in_rect(cells_template, self.image.get_image_data_mut(), rect);
+
+        // rows go from top to bottom, cells from bottom to top
+        let first_row = CHUNK_SIZE - rect.max_y as usize;
+        let rows = (rect.max_y - rect.min_y) as usize;
+        let row_bytes = CHUNK_SIZE * 4;
+        let part = Image {
+            bytes: self.image.bytes[first_row * row_bytes..(first_row + rows) * row_bytes].to_vec(),
+            width: CHUNK_SIZE as u16,
+            height: rows as u16,
+        };
+        self.texture
+            .update_part(&part, 0, first_row as i32, CHUNK_SIZE as i32, rows as i32);
+    }
+}
+
+impl ChunkRenderer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Amount of cached textures
+    pub fn len(&self) -> usize {
+        self.textures.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.textures.is_empty()
+    }
+
+    /// Get texture of the chunk at `pos`, updating it if the chunk was changed.
+    pub fn get_texture(
+        &mut self,
+        pos: ChunkPos,
+        chunk: &Chunk,
+        cells_template: &CellsTemplate,
+    ) -> &Texture2D {
+        let chunk_texture = self.textures.entry(pos).or_insert_with(|| {
+            let mut image = Image::gen_image_color(
+                CHUNK_SIZE as u16,
+                CHUNK_SIZE as u16,
+                Color::from_rgba(0, 0, 0, 0),
+            );
+            chunk.write_pixels(cells_template, image.get_image_data_mut());
+            let texture = Texture2D::from_image(&image);
+            texture.set_filter(FilterMode::Nearest);
+
+            ChunkTexture {
+                texture,
+                image,
+                generation: chunk.generation(),
+            }
+        });
+
+        if chunk_texture.generation != chunk.generation() {
+            let changed_rect = chunk.changed_rect_since(chunk_texture.generation);
+            chunk_texture.generation = chunk.generation();
+
+            match changed_rect {
+                Some(rect) if rect.area() < CHUNK_AREA => {
+                    chunk_texture.update_rect(chunk, cells_template, rect)
+                }
+                _ => {
+                    chunk.write_pixels(cells_template, chunk_texture.image.get_image_data_mut());
+                    chunk_texture.texture.update(&chunk_texture.image);
+                }
+            }
+        }
+
+        &chunk_texture.texture
+    }
+
+    /// Drop textures of chunks for which `keep` returns false.
+    pub fn retain(&mut self, mut keep: impl FnMut(ChunkPos) -> bool) {
+        self.textures.retain(|&pos, _| keep(pos));
+    }
+
+    pub fn clear(&mut self) {
+        self.textures.clear();
+    }
+}
@@ -1,8 +1,17 @@
 use crate::*;
 use macroquad::prelude::*;
+use std::path::PathBuf;
+
+pub const DEFAULT_SAVE_PATH: &str = "world.sand";
+pub const DEFAULT_REPLAY_PATH: &str = "replay.ron";
+/// How often chunks residency is checked (in seconds)
+pub const RESIDENCY_CHECK_INTERVAL: f64 = 1.0;
+/// Template warnings shown on screen, `just check-template` lists all of them
+pub const MAX_TEMPLATE_WARNINGS_SHOWN: usize = 8;
 
 pub struct GameState {
     pub world: WorldState,
+    pub renderer: ChunkRenderer,
     pub selected_cell: usize,
     pub ticks_per_frame: u16,
     pub spawn_mode: SpawnMode,
@@ -11,6 +20,27 @@ pub struct GameState {
     pub camera: WorldCamera,
     pub camera_speed: f32,
     pub camera_fast_speed: f32,
+    /// Cells template file reloaded on change, see [`GameState::handle_template_reload`]
+    pub template_watcher: Option<TemplateWatcher>,
+    /// Error of the last template reload, previous template stays active while it's set
+    pub template_error: Option<String>,
+    /// World is saved to and loaded from this file with F5 and F9
+    pub save_path: PathBuf,
+    /// Result of the last save or load
+    pub save_status: Option<String>,
+    pub residency: ChunkResidency,
+    /// Time of the last [`GameState::handle_chunk_residency`] check
+    pub last_residency_check: f64,
+    /// Error of the last chunk paging, e.g. paged chunk couldn't be loaded back
+    pub paging_error: Option<String>,
+    /// Actions applied to the world since it was created, saved with F6. Stopped when the world
+    /// is loaded or the cells template is reloaded, see [`GameState::set_cells_template`]
+    pub recording: Option<Replay>,
+    pub replay_path: PathBuf,
+    /// Replay being played, user can't change the world while it's set
+    pub replay_player: Option<ReplayPlayer>,
+    /// Result of the last replay save or replay error
+    pub replay_status: Option<String>,
 
     pub last_chunks_drawn: usize,
     pub last_chunks_updated: usize,
@@ -28,18 +58,38 @@ macro_rules! is_pressed {
     };
 }
 
+impl Default for GameState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl GameState {
     pub fn new() -> Self {
-        let cells_template = default_cells();
-        let world = WorldState::new();
+        Self::with_cells_template(default_cells())
+    }
+
+    /// Load cells template from file and watch it for changes.
+    pub fn with_template_file(path: impl Into<PathBuf>) -> eyre::Result<Self> {
+        let mut template_watcher = TemplateWatcher::new(path);
+        let cells_template = template_watcher.load()?;
+
+        let mut state = Self::with_cells_template(cells_template);
+        state.template_watcher = Some(template_watcher);
+
+        Ok(state)
+    }
+
+    pub fn with_cells_template(cells_template: CellsTemplate) -> Self {
+        let mut world = WorldState::new();
+        set_temp_chunk_store(&mut world);
+        let recording = Replay::new(world.seed());
+
         Self {
-            cell_variants: cells_template
-                .cells
-                .iter()
-                .map(|cell| cell.label.clone())
-                .collect(),
+            cell_variants: get_cell_variants(&cells_template),
 
             world,
+            renderer: ChunkRenderer::new(),
 
             cells_template,
 
@@ -53,6 +103,18 @@ impl GameState {
             camera_speed: 100.0,
             camera_fast_speed: 500.0,
 
+            template_watcher: None,
+            template_error: None,
+            save_path: DEFAULT_SAVE_PATH.into(),
+            save_status: None,
+            residency: ChunkResidency::default(),
+            last_residency_check: 0.0,
+            paging_error: None,
+            recording: Some(recording),
+            replay_path: DEFAULT_REPLAY_PATH.into(),
+            replay_player: None,
+            replay_status: None,
+
             last_chunks_drawn: 0,
             last_chunks_updated: 0,
         }
@@ -77,10 +139,6 @@ impl GameState {
         let offset = self.camera.chunk_pos_to_screen_cord(chunk_pos);
         let chunk_size = self.camera.chunk_screen_size();
 
-        let chunk = self.world.ensure_chunk(chunk_pos, &self.cells_template);
-
-        let texture = chunk.get_texture(&self.cells_template);
-
         const BG_COLOR_1: Color = Color::new(0.4, 0.4, 0.4, 1.0);
         const BG_COLOR_2: Color = Color::new(0.7, 0.7, 0.7, 1.0);
         draw_rectangle(
@@ -112,6 +170,22 @@ impl GameState {
             BG_COLOR_1,
         );
 
+        let chunk = match self
+            .world
+            .get_or_load_chunk(chunk_pos, &self.cells_template)
+        {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => return,
+            Err(err) => {
+                self.paging_error = Some(format!("{err:?}"));
+                return;
+            }
+        };
+
+        let texture = self
+            .renderer
+            .get_texture(chunk_pos, chunk, &self.cells_template);
+
         draw_texture_ex(
             texture,
             offset.x,
@@ -124,6 +198,20 @@ impl GameState {
             },
         );
 
+        // cells processed on the next update
+        let rect = chunk.update_rect().intersect(DirtyRect::CHUNK);
+        if !rect.is_empty() {
+            let cell_size = chunk_size / CHUNK_SIZE as f32;
+            draw_rectangle_lines(
+                offset.x + rect.min_x as f32 * cell_size.x,
+                offset.y + (CHUNK_SIZE as i16 - rect.max_y) as f32 * cell_size.y,
+                (rect.max_x - rect.min_x) as f32 * cell_size.x,
+                (rect.max_y - rect.min_y) as f32 * cell_size.y,
+                1.0,
+                RED,
+            );
+        }
+
         let text_color = if chunk.should_update() { RED } else { WHITE };
         draw_text(
             &format!("{} {}", chunk_pos.x, chunk_pos.y),
@@ -136,13 +224,25 @@ impl GameState {
 
     pub fn on_frame(&mut self) {
         for _ in 0..self.ticks_per_frame {
-            self.last_chunks_updated = self.world.update_state(&self.cells_template);
+            self.apply_replay_events();
+            match self.world.update_state(&self.cells_template) {
+                Ok(chunks_updated) => self.last_chunks_updated = chunks_updated,
+                Err(err) => {
+                    self.paging_error = Some(format!("{err:?}"));
+                    break;
+                }
+            }
         }
+        self.apply_replay_events();
 
         self.camera.resize(vec2(screen_width(), screen_height()));
 
         let dt = get_frame_time();
 
+        self.handle_template_reload();
+        self.handle_save_load();
+        self.handle_save_replay();
+        self.handle_chunk_residency();
         self.handle_change_scale();
         self.handle_tick_speed_selection();
         self.handle_cell_selection();
@@ -151,6 +251,156 @@ impl GameState {
         self.handle_move_camera(dt);
     }
 
+    pub fn handle_template_reload(&mut self) {
+        let Some(template_watcher) = &mut self.template_watcher else {
+            return;
+        };
+
+        match template_watcher.poll() {
+            Some(Ok(cells_template)) => {
+                self.template_error = None;
+                self.set_cells_template(cells_template);
+            }
+            Some(Err(err)) => {
+                self.template_error = Some(format!("{err:?}"));
+            }
+            None => {}
+        }
+    }
+
+    pub fn handle_save_load(&mut self) {
+        if is_pressed!(F5) {
+            let result = save_world(&self.save_path, &self.world, &self.cells_template);
+            self.save_status = Some(match result {
+                Ok(()) => format!("Saved to {}", self.save_path.display()),
+                Err(err) => format!("{err:?}"),
+            });
+        }
+
+        if is_pressed!(F9) {
+            let result = load_world(&self.save_path, &self.cells_template);
+            self.save_status = Some(match result {
+                Ok(world) => {
+                    self.world = world;
+                    set_temp_chunk_store(&mut self.world);
+                    // loaded world can't be reproduced from the recorded actions
+                    self.recording = None;
+                    self.replay_player = None;
+                    format!("Loaded from {}", self.save_path.display())
+                }
+                Err(err) => format!("{err:?}"),
+            });
+        }
+    }
+
+    pub fn handle_save_replay(&mut self) {
+        if !is_pressed!(F6) {
+            return;
+        }
+
+        let Some(recording) = &mut self.recording else {
+            self.replay_status = Some("Nothing to save, recording was stopped".into());
+            return;
+        };
+
+        recording.end_tick = self.world.current_tick();
+        self.replay_status = Some(match recording.save(&self.replay_path) {
+            Ok(()) => format!("Saved to {}", self.replay_path.display()),
+            Err(err) => format!("{err:?}"),
+        });
+    }
+
+    /// Apply world changing action and record it.
+    pub fn apply_action(&mut self, action: ReplayAction) {
+        if let ReplayAction::SetTicksPerFrame(ticks_per_frame) = action {
+            self.ticks_per_frame = ticks_per_frame;
+        }
+
+        if let Err(err) = action.apply(&mut self.world, &self.cells_template) {
+            self.replay_status = Some(format!("{err:?}"));
+            return;
+        }
+
+        if let Some(recording) = &mut self.recording {
+            recording.record(self.world.current_tick(), action);
+        }
+    }
+
+    /// Reset the world and play the replay. Replay is recorded again, so it can be saved after
+    /// it's finished.
+    pub fn start_replay(&mut self, replay: Replay) {
+        self.world = WorldState::with_seed(replay.seed);
+        set_temp_chunk_store(&mut self.world);
+        self.recording = Some(Replay::new(replay.seed));
+        self.replay_player = Some(ReplayPlayer::new(replay));
+    }
+
+    fn apply_replay_events(&mut self) {
+        while let Some(action) = self
+            .replay_player
+            .as_mut()
+            .and_then(|player| player.next_due_action(self.world.current_tick()))
+        {
+            self.apply_action(action);
+        }
+
+        if let Some(replay_player) = &self.replay_player {
+            if replay_player.is_finished(&self.world) {
+                self.replay_status = Some("Replay finished".into());
+                self.replay_player = None;
+            }
+        }
+    }
+
+    /// Drop empty chunks and page out chunks far from the screen once per
+    /// [`RESIDENCY_CHECK_INTERVAL`] seconds.
+    pub fn handle_chunk_residency(&mut self) {
+        let time = get_time();
+        if time - self.last_residency_check < RESIDENCY_CHECK_INTERVAL {
+            return;
+        }
+        self.last_residency_check = time;
+
+        let (min, max) = self.camera.get_screen_chunks_area();
+        // residency depends on the camera, it must not change the recorded simulation
+        let residency = ChunkResidency {
+            lossless: self.recording.is_some() || self.replay_player.is_some(),
+            ..self.residency
+        };
+        if let Err(err) = self
+            .world
+            .update_residency(min, max, residency, &self.cells_template)
+        {
+            self.paging_error = Some(format!("{err:?}"));
+        }
+
+        let world = &self.world;
+        self.renderer.retain(|pos| world.get_chunk(pos).is_some());
+    }
+
+    /// Replace cells template, cells in the world are matched with the new template by label.
+    /// Replays don't record templates, so recording and playing a replay are stopped.
+    pub fn set_cells_template(&mut self, cells_template: CellsTemplate) {
+        if self.replay_player.take().is_some() {
+            self.replay_status = Some("Replay stopped, cells template was reloaded".into());
+        } else if self.recording.is_some() {
+            self.replay_status = Some("Recording stopped, cells template was reloaded".into());
+        }
+        self.recording = None;
+
+        self.world
+            .remap_cells(&self.cells_template, &cells_template);
+
+        let selected_cell = &self.cell_variants[self.selected_cell];
+        self.selected_cell = cells_template
+            .get_cell_meta_by_label(selected_cell)
+            .map(|cell| cell.id as usize)
+            .unwrap_or(0);
+
+        self.cell_variants = get_cell_variants(&cells_template);
+        self.cells_template = cells_template;
+    }
+
     pub fn handle_move_camera(&mut self, dt: f32) {
         let mut camera_move = Vec2::ZERO;
 
@@ -181,11 +431,13 @@ impl GameState {
 
     pub fn handle_tick_speed_selection(&mut self) {
         if is_pressed!(Up) {
-            self.ticks_per_frame += 1;
+            self.apply_action(ReplayAction::SetTicksPerFrame(self.ticks_per_frame + 1));
         }
 
         if is_pressed!(Down) {
-            self.ticks_per_frame = self.ticks_per_frame.saturating_sub(1);
+            self.apply_action(ReplayAction::SetTicksPerFrame(
+                self.ticks_per_frame.saturating_sub(1),
+            ));
         }
     }
 
@@ -221,6 +473,10 @@ impl GameState {
     }
 
     pub fn handle_spawn_cells(&mut self) {
+        if self.replay_player.is_some() {
+            return;
+        }
+
         let condition = match self.spawn_mode {
             SpawnMode::Single => is_mouse_button_pressed(MouseButton::Left),
             SpawnMode::Brush => is_mouse_button_down(MouseButton::Left),
@@ -241,32 +497,21 @@ impl GameState {
             .find(|cell| cell.label == *selected_cell_name)
             .expect("Cell not found");
 
-        match self.spawn_mode {
-            SpawnMode::Single => {
-                if spawn_particles {
-                    self.world
-                        .add_particle_rand_vel(position, cell, &self.cells_template);
-                } else {
-                    self.world
-                        .set_cell(position, cell.init(), &self.cells_template);
-                }
-            }
-            SpawnMode::Brush => {
-                let radius = 1;
-                for y in -radius..=radius {
-                    for x in -radius..=radius {
-                        let position = position + RelativePos::new(x, y);
-                        if spawn_particles {
-                            self.world
-                                .add_particle_rand_vel(position, cell, &self.cells_template);
-                        } else {
-                            self.world
-                                .set_cell(position, cell.init(), &self.cells_template);
-                        }
-                    }
-                }
-            }
-        }
+        let pos = (position.x(), position.y());
+        let label = cell.label.clone();
+
+        let action = match self.spawn_mode {
+            SpawnMode::Single if spawn_particles => ReplayAction::AddParticleRandVel { pos, label },
+            SpawnMode::Single => ReplayAction::SetCell { pos, label },
+            SpawnMode::Brush => ReplayAction::Brush {
+                pos,
+                radius: 1,
+                label,
+                particles: spawn_particles,
+            },
+        };
+
+        self.apply_action(action);
     }
 
     pub fn draw_debug_text(&self) {
@@ -308,8 +553,18 @@ impl GameState {
 
         draw_debug_line!("Chunks updated: {}", self.last_chunks_updated);
 
+        draw_debug_line!("World seed: {}", self.world.seed());
+
         draw_debug_line!("Chunks loaded: {}", self.world.len());
 
+        draw_debug_line!("Chunks paged: {}", self.world.paged_len());
+        draw_debug_line!(
+            "Chunks memory: {:.1} MiB ({} cells with registers)",
+            self.world.memory_usage() as f64 / (1024.0 * 1024.0),
+            self.world.cells_with_registers()
+        );
+        draw_debug_line!("Textures cached: {}", self.renderer.len());
+
         let (min, max) = self.camera.get_screen_chunks_area();
         draw_debug_line!(
             "Screen chunks: ({}, {}) - ({}, {})",
@@ -325,6 +580,64 @@ impl GameState {
         // chunk and cell position
         draw_debug_line!("Chunk pos: ({}, {})", mouse_pos.chunk.x, mouse_pos.chunk.y);
         draw_debug_line!("Cell pos: ({}, {})", mouse_pos.cell.x, mouse_pos.cell.y);
+        if let Some(chunk) = self.world.get_chunk(mouse_pos.chunk) {
+            let cell = chunk.get_cell(mouse_pos.cell);
+            draw_debug_line!(
+                "Cell: {} ({:.1} deg)",
+                cell.meta(&self.cells_template).label,
+                cell.temperature.degrees()
+            );
+        }
+
+        match &self.save_status {
+            Some(save_status) => {
+                draw_debug_line!("Save (F5/F9): {save_status}");
+            }
+            None => {
+                draw_debug_line!("Save (F5/F9): {}", self.save_path.display());
+            }
+        }
+
+        match (&self.replay_player, &self.replay_status) {
+            (Some(replay_player), _) => {
+                draw_debug_line!(
+                    "Replay: tick {} of {}",
+                    self.world.current_tick(),
+                    replay_player.replay.end_tick
+                );
+            }
+            (None, Some(replay_status)) => {
+                draw_debug_line!("Replay (F6): {replay_status}");
+            }
+            (None, None) => {
+                draw_debug_line!("Replay (F6): {}", self.replay_path.display());
+            }
+        }
+
+        if let Some(paging_error) = &self.paging_error {
+            for line in paging_error.lines() {
+                draw_text_shadow(line, x, next_y!(), regular_font_size, RED);
+            }
+        }
+
+        if let Some(template_error) = &self.template_error {
+            for line in template_error.lines() {
+                draw_text_shadow(line, x, next_y!(), regular_font_size, RED);
+            }
+        }
+
+        let warnings = &self.cells_template.warnings;
+        for warning in warnings.iter().take(MAX_TEMPLATE_WARNINGS_SHOWN) {
+            let warning = warning.to_string();
+            draw_text_shadow(&warning, x, next_y!(), regular_font_size, YELLOW);
+        }
+        if warnings.len() > MAX_TEMPLATE_WARNINGS_SHOWN {
+            let more = format!(
+                "{} more template warnings, run `just check-template` to list them",
+                warnings.len() - MAX_TEMPLATE_WARNINGS_SHOWN
+            );
+            draw_text_shadow(&more, x, next_y!(), regular_font_size, YELLOW);
+        }
     }
 
     pub fn handle_change_scale(&mut self) {
@@ -334,7 +647,26 @@ impl GameState {
             let scale = self.camera.cell_size.powi(2);
             let scale = scale + (mouse_wheel * 0.1);
             let new_size = scale.sqrt();
-            self.camera.cell_size = new_size.max(0.6).min(10.0);
+            self.camera.cell_size = new_size.clamp(0.6, 10.0);
         }
     }
 }
+
+fn get_cell_variants(cells_template: &CellsTemplate) -> Vec<String> {
+    cells_template
+        .cells
+        .iter()
+        .filter(|cell| cell.id != cells_template.particle_placeholder)
+        .map(|cell| cell.label.clone())
+        .collect()
+}
+
+/// Page chunks to a region file in the temp directory, chunks stay in memory if it can't be created
+fn set_temp_chunk_store(world: &mut WorldState) {
+    let path = std::env::temp_dir().join(format!("sand-{}.region", std::process::id()));
+
+    match ChunkStore::create(path) {
+        Ok(chunk_store) => world.set_chunk_store(chunk_store),
+        Err(err) => eprintln!("Chunks paging is disabled: {err:?}"),
+    }
+}
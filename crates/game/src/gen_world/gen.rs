@@ -1,6 +1,6 @@
 use crate::*;
 
-pub fn gen_world(world: &mut WorldState, cells_template: &CellsTemplate) {
+pub fn gen_world(world: &mut WorldState, cells_template: &CellsTemplate) -> eyre::Result<()> {
     let sand_meta = cells_template.get_cell_meta_by_label("Sand").unwrap();
     let stone_meta = cells_template.get_cell_meta_by_label("Stone").unwrap();
 
@@ -10,7 +10,7 @@ pub fn gen_world(world: &mut WorldState, cells_template: &CellsTemplate) {
         GlobalCellPos::new(-1500, 0),
         GlobalCellPos::new(1500, 3),
         stone_meta,
-    );
+    )?;
 
     gen_rect(
         world,
@@ -18,7 +18,7 @@ pub fn gen_world(world: &mut WorldState, cells_template: &CellsTemplate) {
         GlobalCellPos::new(200, 10),
         GlobalCellPos::new(300, 510),
         sand_meta,
-    );
+    )
 }
 
 pub fn gen_rect(
@@ -27,11 +27,13 @@ pub fn gen_rect(
     start: GlobalCellPos,
     end: GlobalCellPos,
     cell_meta: &CellMeta,
-) {
+) -> eyre::Result<()> {
     for y in start.y()..end.y() {
         for x in start.x()..end.x() {
             let pos = GlobalCellPos::new(x, y);
-            world.set_cell(pos, cell_meta.init(), cells_template);
+            world.set_cell(pos, cell_meta.init(), cells_template)?;
         }
     }
+
+    Ok(())
 }
@@ -1,11 +1,25 @@
+#[cfg(feature = "macroquad")]
+mod chunk_renderer;
+#[cfg(feature = "macroquad")]
 mod draw_text_shadow;
+#[cfg(feature = "macroquad")]
 mod game_state;
 mod gen_world;
+mod replay;
+mod template_watcher;
 mod world;
+#[cfg(feature = "macroquad")]
 mod world_camera;
 
+#[cfg(feature = "macroquad")]
+pub use chunk_renderer::*;
+#[cfg(feature = "macroquad")]
 pub use draw_text_shadow::*;
+#[cfg(feature = "macroquad")]
 pub use game_state::*;
 pub use gen_world::*;
+pub use replay::*;
+pub use template_watcher::*;
 pub use world::*;
+#[cfg(feature = "macroquad")]
 pub use world_camera::*;
@@ -9,11 +9,33 @@ fn window_conf() -> Conf {
     }
 }
 
+/// Usage: `game [template.ron] [--replay replay.ron]`
 #[macroquad::main(window_conf)]
 async fn main() {
-    let mut state = GameState::new();
+    let mut template_path = None;
+    let mut replay_path = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--replay" => replay_path = Some(args.next().expect("Missing replay path")),
+            _ => template_path = Some(arg),
+        }
+    }
+
+    let mut state = match template_path {
+        Some(template_path) => {
+            GameState::with_template_file(template_path).expect("Failed to load cells template")
+        }
+        None => GameState::new(),
+    };
 
-    gen_world(&mut state.world, &state.cells_template);
+    match replay_path {
+        Some(replay_path) => {
+            state.start_replay(Replay::load(replay_path).expect("Failed to load replay"))
+        }
+        None => state.apply_action(ReplayAction::GenWorld),
+    }
 
     loop {
         if is_key_pressed(KeyCode::Escape) {
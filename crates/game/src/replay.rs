@@ -0,0 +1,263 @@
+use crate::*;
+use eyre::{eyre, WrapErr};
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Action changing the world, recorded to [`Replay`].
+///
+/// Cells are referenced by label, so replay stays valid if ids in the template change.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ReplayAction {
+    /// Generate default world, see [`gen_world`]
+    GenWorld,
+    SetCell {
+        pos: (i32, i32),
+        label: String,
+    },
+    AddParticle {
+        pos: (i32, i32),
+        vel: (f32, f32),
+        label: String,
+    },
+    /// Add particle with velocity from the world random generator
+    AddParticleRandVel {
+        pos: (i32, i32),
+        label: String,
+    },
+    /// Spawn cells or particles in the square around `pos`
+    Brush {
+        pos: (i32, i32),
+        radius: i8,
+        label: String,
+        particles: bool,
+    },
+    /// Doesn't change the world, but keeps replay speed in the window the same as recorded
+    SetTicksPerFrame(u16),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReplayEvent {
+    /// Action is applied before this tick is simulated
+    pub tick: u32,
+    pub action: ReplayAction,
+}
+
+/// Recorded session: world seed and every action changing the world.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Replay {
+    pub seed: u64,
+    /// Tick at which recording was stopped
+    pub end_tick: u32,
+    pub events: Vec<ReplayEvent>,
+}
+
+impl ReplayAction {
+    /// Apply action to the world. [`ReplayAction::SetTicksPerFrame`] is ignored.
+    pub fn apply(
+        &self,
+        world: &mut WorldState,
+        cells_template: &CellsTemplate,
+    ) -> eyre::Result<()> {
+        let get_cell_meta = |label: &str| {
+            cells_template
+                .get_cell_meta_by_label(label)
+                .ok_or_else(|| eyre!("Unknown cell label {label:?}"))
+        };
+
+        match self {
+            ReplayAction::GenWorld => gen_world(world, cells_template)?,
+            ReplayAction::SetCell { pos, label } => {
+                let cell_meta = get_cell_meta(label)?;
+                world.set_cell(to_global_pos(*pos), cell_meta.init(), cells_template)?;
+            }
+            ReplayAction::AddParticle { pos, vel, label } => {
+                let cell_meta = get_cell_meta(label)?;
+                world.add_particle(
+                    to_global_pos(*pos),
+                    Vec2::new(vel.0, vel.1),
+                    cell_meta,
+                    cells_template,
+                )?;
+            }
+            ReplayAction::AddParticleRandVel { pos, label } => {
+                let cell_meta = get_cell_meta(label)?;
+                world.add_particle_rand_vel(to_global_pos(*pos), cell_meta, cells_template)?;
+            }
+            ReplayAction::Brush {
+                pos,
+                radius,
+                label,
+                particles,
+            } => {
+                let cell_meta = get_cell_meta(label)?;
+                let center = to_global_pos(*pos);
+
+                for y in -radius..=*radius {
+                    for x in -radius..=*radius {
+                        let pos = center + RelativePos::new(x, y);
+                        if *particles {
+                            world.add_particle_rand_vel(pos, cell_meta, cells_template)?;
+                        } else {
+                            world.set_cell(pos, cell_meta.init(), cells_template)?;
+                        }
+                    }
+                }
+            }
+            ReplayAction::SetTicksPerFrame(_) => {}
+        }
+
+        Ok(())
+    }
+}
+
+fn to_global_pos((x, y): (i32, i32)) -> GlobalCellPos {
+    GlobalCellPos::new(x, y)
+}
+
+impl Replay {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            end_tick: 0,
+            events: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, tick: u32, action: ReplayAction) {
+        self.events.push(ReplayEvent { tick, action });
+        self.end_tick = self.end_tick.max(tick);
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> eyre::Result<()> {
+        let path = path.as_ref();
+        let source = ron::ser::to_string_pretty(self, Default::default())?;
+
+        std::fs::write(path, source)
+            .wrap_err_with(|| format!("Failed to save replay to {}", path.display()))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> eyre::Result<Self> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("Failed to read replay {}", path.display()))?;
+
+        ron::from_str(&source).wrap_err_with(|| format!("Invalid replay {}", path.display()))
+    }
+
+    /// Re-run the whole replay without a window.
+    pub fn run(&self, cells_template: &CellsTemplate) -> eyre::Result<WorldState> {
+        let mut world = WorldState::with_seed(self.seed);
+        let mut player = ReplayPlayer::new(self.clone());
+
+        loop {
+            while let Some(action) = player.next_due_action(world.current_tick()) {
+                action.apply(&mut world, cells_template).wrap_err_with(|| {
+                    format!(
+                        "Failed to apply replay event at tick {}",
+                        world.current_tick()
+                    )
+                })?;
+            }
+
+            if world.current_tick() >= self.end_tick {
+                break;
+            }
+            world.update_state(cells_template)?;
+        }
+
+        Ok(world)
+    }
+}
+
+/// Applies replay events as the world reaches their ticks.
+pub struct ReplayPlayer {
+    pub replay: Replay,
+    next_event: usize,
+}
+
+impl ReplayPlayer {
+    pub fn new(mut replay: Replay) -> Self {
+        // events with the same tick must keep the recorded order
+        replay.events.sort_by_key(|event| event.tick);
+
+        Self {
+            replay,
+            next_event: 0,
+        }
+    }
+
+    pub fn is_finished(&self, world: &WorldState) -> bool {
+        self.next_event >= self.replay.events.len() && world.current_tick() >= self.replay.end_tick
+    }
+
+    /// Take the next event action that should be applied before `current_tick` is simulated.
+    pub fn next_due_action(&mut self, current_tick: u32) -> Option<ReplayAction> {
+        let event = self
+            .replay
+            .events
+            .get(self.next_event)
+            .filter(|event| event.tick <= current_tick)?;
+        self.next_event += 1;
+
+        Some(event.action.clone())
+    }
+}
+
+#[test]
+fn test_replay_reproduces_world() {
+    let cells_template = default_cells();
+
+    // record session
+    let mut replay = Replay::new(42);
+    let mut world = WorldState::with_seed(replay.seed);
+    let mut actions = vec![
+        (0, ReplayAction::GenWorld),
+        (
+            3,
+            ReplayAction::Brush {
+                pos: (250, 600),
+                radius: 2,
+                label: CELL_WATER_LABEL.into(),
+                particles: true,
+            },
+        ),
+        (
+            3,
+            ReplayAction::SetCell {
+                pos: (100, 20),
+                label: CELL_SAND_LABEL.into(),
+            },
+        ),
+        (
+            10,
+            ReplayAction::AddParticleRandVel {
+                pos: (-5, 40),
+                label: CELL_SAND_LABEL.into(),
+            },
+        ),
+    ]
+    .into_iter()
+    .peekable();
+
+    while world.current_tick() < 30 {
+        while let Some((_, action)) = actions.next_if(|(tick, _)| *tick == world.current_tick()) {
+            action.apply(&mut world, &cells_template).unwrap();
+            replay.record(world.current_tick(), action);
+        }
+        world.update_state(&cells_template).unwrap();
+    }
+    replay.end_tick = world.current_tick();
+
+    // replay it from file
+    let source = ron::ser::to_string(&replay).unwrap();
+    let loaded_replay: Replay = ron::from_str(&source).unwrap();
+    let replayed_world = loaded_replay.run(&cells_template).unwrap();
+
+    let mut expected = Vec::new();
+    write_world(&mut expected, &world, &cells_template).unwrap();
+    let mut actual = Vec::new();
+    write_world(&mut actual, &replayed_world, &cells_template).unwrap();
+
+    assert!(expected == actual);
+}
@@ -0,0 +1,44 @@
+use crate::*;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// Watches cells template file and reloads it when it's modified.
+pub struct TemplateWatcher {
+    pub path: PathBuf,
+    last_modified: Option<SystemTime>,
+}
+
+impl TemplateWatcher {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let mut watcher = Self {
+            path: path.into(),
+            last_modified: None,
+        };
+        watcher.last_modified = watcher.modified();
+
+        watcher
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        std::fs::metadata(&self.path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+
+    /// Load template from the file regardless of modification time.
+    pub fn load(&mut self) -> eyre::Result<CellsTemplate> {
+        self.last_modified = self.modified();
+
+        load_cells_template(&self.path)
+    }
+
+    /// Reload template if the file was modified since the last check.
+    pub fn poll(&mut self) -> Option<eyre::Result<CellsTemplate>> {
+        let modified = self.modified();
+        if modified.is_none() || modified == self.last_modified {
+            return None;
+        }
+
+        Some(self.load())
+    }
+}
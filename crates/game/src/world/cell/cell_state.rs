@@ -10,17 +10,25 @@ pub const CELL_REGISTER_SYSTEM: usize = CELL_REGISTERS_COUNT - 1;
 pub const CELL_REGISTER_SYSTEM_FLAGS: usize = 0;
 pub const CELL_REGISTER_SYSTEM_BRIGHTNESS_VALUE: usize = 1;
 pub const CELL_REGISTER_SYSTEM_FLAG_IS_BRIGHTNESS_SET: u8 = 1 << 0;
+pub const CELL_REGISTER_SYSTEM_FLAG_IS_AGE_SET: u8 = 1 << 1;
 
-/// Register used to track cell's age if it's enabled, may be used for other purposes, but will be
-/// incremented every tick
+/// Register used to track cell's age if it's enabled (see [`CellMeta::count_age`]). Reset when cell
+/// is initialized, templates can only read it (by index or as [`CELL_REGISTER_AGE_NAME`]).
 pub const CELL_REGISTER_AGE: usize = CELL_REGISTERS_COUNT - 2;
+/// Name of [`CELL_REGISTER_AGE`] in templates
+pub const CELL_REGISTER_AGE_NAME: &str = "age";
+/// Registers before [`CELL_REGISTER_AGE`] are free to use by templates
+pub const CELL_FREE_REGISTERS_COUNT: usize = CELL_REGISTER_AGE;
 
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Cell {
     pub id: CellId,
     /// Tick at which cell was last updated
     pub last_update: u32,
+    /// Tick at which [`CELL_REGISTER_AGE`] was last updated, see [`Cell::update_age`]
+    pub age_tick: u32,
     pub registers: [u32; CELL_REGISTERS_COUNT],
+    pub temperature: Temperature,
 }
 
 impl Cell {
@@ -34,6 +42,28 @@ impl Cell {
         cells_template.get_cell_meta(self.id).color
     }
 
+    /// Increment [`CELL_REGISTER_AGE`] by amount of ticks passed since the previous call, so age is
+    /// counted once per tick even if cell was moved or skipped. Returns `true` if cell was changed.
+    #[inline(always)]
+    pub fn update_age(&mut self, current_tick: u32) -> bool {
+        let mut system_reg = self.registers[CELL_REGISTER_SYSTEM].to_le_bytes();
+
+        if system_reg[CELL_REGISTER_SYSTEM_FLAGS] & CELL_REGISTER_SYSTEM_FLAG_IS_AGE_SET == 0 {
+            system_reg[CELL_REGISTER_SYSTEM_FLAGS] |= CELL_REGISTER_SYSTEM_FLAG_IS_AGE_SET;
+            self.registers[CELL_REGISTER_SYSTEM] = u32::from_le_bytes(system_reg);
+        } else if self.age_tick != current_tick {
+            let passed = current_tick.wrapping_sub(self.age_tick);
+            self.registers[CELL_REGISTER_AGE] =
+                self.registers[CELL_REGISTER_AGE].saturating_add(passed);
+        } else {
+            return false;
+        }
+
+        self.age_tick = current_tick;
+
+        true
+    }
+
     #[inline(always)]
     pub fn meta<'a>(&self, config: &'a CellsTemplate) -> &'a CellMeta {
         config.get_cell_meta(self.id)
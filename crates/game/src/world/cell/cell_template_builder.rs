@@ -1,5 +1,5 @@
 use crate::*;
-use eyre::ContextCompat;
+use eyre::{bail, ContextCompat};
 use nohash_hasher::IntMap;
 use std::collections::HashMap;
 
@@ -9,6 +9,12 @@ pub struct CellTemplateBuilder {
     pub cells: IntMap<CellId, CellMeta>,
 }
 
+impl Default for CellTemplateBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl CellTemplateBuilder {
     pub fn new() -> Self {
         Self {
@@ -35,6 +41,13 @@ impl CellTemplateBuilder {
     }
 
     pub fn build(mut self) -> eyre::Result<CellsTemplate> {
+        if self
+            .id_by_label
+            .contains_key(CELL_PARTICLE_PLACEHOLDER_LABEL)
+        {
+            bail!("Label {CELL_PARTICLE_PLACEHOLDER_LABEL:?} is reserved");
+        }
+
         let cells_amount = self.cells.len();
         let mut cells: Vec<CellMeta> = Vec::with_capacity(self.cells.len());
 
@@ -43,6 +56,44 @@ impl CellTemplateBuilder {
             cells.push(cell);
         }
 
-        Ok(CellsTemplate { cells })
+        let mut diagnostics = validate_cells(&cells);
+        let errors = diagnostics.iter().filter(|d| d.is_error()).count();
+        if errors > 0 {
+            // warnings are reported too, they may explain the errors
+            diagnostics.sort_by_key(|diagnostic| diagnostic.severity);
+            let diagnostics: Vec<String> = diagnostics.iter().map(ToString::to_string).collect();
+            bail!("Found {errors} errors:\n{}", diagnostics.join("\n"));
+        }
+
+        for cell in &mut cells {
+            cell.program = RuleProgram::compile(&cell.rule);
+        }
+
+        let update_radius = cells
+            .iter()
+            .map(|cell| cell.program.max_offset())
+            .fold(1, i16::max);
+
+        // if any cell reads the age of its neighbors, all of them have to be kept updated
+        let age_reads = |cell: &CellMeta| cell.program.register_reads(CELL_REGISTER_AGE as u8);
+        let age_read_by_neighbors = cells.iter().any(|cell| {
+            age_reads(cell)
+                .iter()
+                .any(|&pos| pos != RelativePos::self_pos())
+        });
+        for cell in &mut cells {
+            cell.age_is_read =
+                age_read_by_neighbors || age_reads(cell).contains(&RelativePos::self_pos());
+        }
+
+        let particle_placeholder = cells.len() as CellId;
+        cells.push(CellMeta::particle_placeholder(particle_placeholder));
+
+        Ok(CellsTemplate {
+            cells,
+            warnings: diagnostics,
+            update_radius,
+            particle_placeholder,
+        })
     }
 }
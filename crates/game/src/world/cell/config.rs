@@ -1,9 +1,18 @@
 use crate::*;
-use macroquad::math::Vec2;
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct CellsTemplate {
     pub cells: Vec<CellMeta>,
+    /// Non-fatal problems found when the template was built, see [`validate_cells`]
+    pub warnings: Vec<TemplateDiagnostic>,
+    /// How far change of a cell affects other cells: the farthest position used by rules, but at
+    /// least 1 for movement and heat transfer. Cells this far from the changed ones are updated.
+    pub update_radius: i16,
+    /// Technical cell placed where a particle is, so cells and other particles can't move into it.
+    /// Added to every template as the last cell, see [`CELL_PARTICLE_PLACEHOLDER_LABEL`].
+    pub particle_placeholder: CellId,
 }
 
 impl CellsTemplate {
@@ -14,6 +23,11 @@ impl CellsTemplate {
     pub fn get_cell_meta_by_label(&self, label: &str) -> Option<&CellMeta> {
         self.cells.iter().find(|cell| cell.label == label)
     }
+
+    /// Check if any cell conducts heat, otherwise heat transfer is skipped.
+    pub fn has_heat_transfer(&self) -> bool {
+        self.cells.iter().any(|cell| cell.thermal_conductivity > 0)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -22,25 +36,161 @@ pub struct CellMeta {
     pub color: CellColor,
     pub label: String,
     pub rule: CellRule,
-    /// If true, AGE register will be incremented on each tick.
+    /// [`CellMeta::rule`] compiled for [`RuleEvaluator::Bytecode`],
+    /// set by [`CellTemplateBuilder::build`] after validation
+    pub program: RuleProgram,
+    /// If true, [`CELL_REGISTER_AGE`] will be incremented on each tick
     pub count_age: bool,
+    /// Age of the cell is read by rules, set by [`CellTemplateBuilder::build`]. Only such cells
+    /// counting age are processed every tick and keep their chunk updating, age of the others
+    /// catches up when they are processed (see [`Cell::update_age`]).
+    pub age_is_read: bool,
     /// Gravity in particle mode
     pub particle_gravity: Vec2,
+    /// Terminal velocity in particle mode (cells per second)
+    pub particle_max_speed: f32,
+    /// Part of the speed towards the obstacle kept by a particle when it bounces off (0..=1),
+    /// particles slower than [`PARTICLE_SETTLE_SPEED`] after the hit turn back into the cell
+    pub particle_restitution: f32,
+    /// Part of the speed along the obstacle lost by a particle when it hits it (0..=1)
+    pub particle_friction: f32,
+    /// Probability (0..=1) that a particle hitting an obstacle splashes
+    /// [`CellMeta::particle_splash_count`] fragments, see [`Particle::is_fragment`]
+    pub particle_splash_chance: f32,
+    pub particle_splash_count: u8,
+    /// Particles older than this amount of ticks disappear, 0 means they live until they settle
+    pub particle_lifetime: u32,
     pub replaceable_by_particles: bool,
     pub initial_register_values: [u32; CELL_REGISTERS_COUNT],
+    /// Named registers of the cell, used by the template to reference registers by name
+    pub registers: Vec<RegisterMeta>,
+    pub initial_temperature: Temperature,
+    /// How much heat is needed to change temperature, cells with bigger capacity change
+    /// temperature slower
+    pub heat_capacity: u16,
+    /// How fast heat is transferred to neighbors (out of 256), the smaller value of two cells is
+    /// used. Cells with zero conductivity are perfect insulators.
+    pub thermal_conductivity: u8,
+    /// Built-in movement applied before the [`CellMeta::rule`]
+    pub movement: CellMovement,
+    /// Cells that move down displace cells with lower density, rising cells displace cells with
+    /// higher density. Relative to Vacuum (0), gases lighter than it rise.
+    pub density: i16,
 }
 
 impl CellMeta {
+    /// See [`CellsTemplate::particle_placeholder`]. It's solid and has no rule, the cell it
+    /// replaced is restored when the particle moves away.
+    pub fn particle_placeholder(id: CellId) -> Self {
+        let rule = CellRule::Idle;
+        Self {
+            id,
+            color: CellColor::Plain([0, 0, 0, 0]),
+            label: CELL_PARTICLE_PLACEHOLDER_LABEL.to_string(),
+            program: RuleProgram::compile(&rule),
+            rule,
+            count_age: false,
+            age_is_read: false,
+            particle_gravity: Vec2::ZERO,
+            particle_max_speed: 0.0,
+            particle_restitution: 0.0,
+            particle_friction: 1.0,
+            particle_splash_chance: 0.0,
+            particle_splash_count: 0,
+            particle_lifetime: 0,
+            replaceable_by_particles: false,
+            initial_register_values: [0; CELL_REGISTERS_COUNT],
+            registers: Vec::new(),
+            initial_temperature: Temperature::ROOM,
+            heat_capacity: 100,
+            thermal_conductivity: 0,
+            movement: CellMovement::Solid,
+            density: 0,
+        }
+    }
+
     pub fn init(&self) -> Cell {
         Cell {
             id: self.id,
             last_update: 0,
+            age_tick: 0,
             registers: self.initial_register_values,
+            temperature: self.initial_temperature,
+        }
+    }
+
+    /// Get named register by index
+    pub fn get_register(&self, index: u8) -> Option<&RegisterMeta> {
+        self.registers
+            .iter()
+            .find(|register| register.index == index)
+    }
+
+    /// Bits of the values that fit the register, all bits if its type isn't declared
+    #[inline(always)]
+    pub fn register_mask(&self, index: u8) -> u32 {
+        self.get_register(index)
+            .map_or(u32::MAX, |register| register.ty.max_value())
+    }
+}
+
+/// Named register of the cell. See [`CellMeta::registers`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegisterMeta {
+    pub name: String,
+    pub index: u8,
+    pub ty: RegisterType,
+}
+
+/// Type of the value stored in a named register. Registers are always 32 bits wide, the type
+/// limits which values can be written by the template and computed values are wrapped to it, e.g.
+/// incrementing a `Bool` register set to 1 resets it to 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RegisterType {
+    Bool,
+    /// Unsigned integer of given amount of bits (1..=32)
+    Uint(u8),
+}
+
+impl RegisterType {
+    pub fn bits(self) -> u32 {
+        match self {
+            RegisterType::Bool => 1,
+            RegisterType::Uint(bits) => bits as u32,
         }
     }
+
+    /// Biggest value that fits the register
+    pub fn max_value(self) -> u32 {
+        u32::MAX >> (32 - self.bits().clamp(1, 32))
+    }
+}
+
+/// Built-in movement of the cell, moved cells swap with the displaced ones (see
+/// [`CellMeta::density`]).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum CellMovement {
+    /// Never moves and can't be displaced
+    #[default]
+    Solid,
+    /// Never moves but can be displaced, e.g. Vacuum
+    Empty,
+    /// Falls down and diagonally down
+    Powder,
+    /// Falls like powder and flows to the sides
+    Liquid,
+    /// Rises up and diagonally up, spreads to the sides
+    Gas,
+}
+
+impl CellMovement {
+    #[inline(always)]
+    pub fn is_displaceable(self) -> bool {
+        self != CellMovement::Solid
+    }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum CellColor {
     Plain([u8; 4]),
     /// Randomize base color by adding random value to brightness. Second parameter is max
@@ -49,30 +199,42 @@ pub enum CellColor {
 }
 
 impl CellColor {
+    /// Assign random brightness to the cell if it's not assigned yet. This is done during the
+    /// simulation, so drawing never affects the state of the world. Returns `true` if cell was
+    /// changed.
     #[inline(always)]
-    pub fn calculate(&self, chunk: &mut Chunk, cell_index: usize) -> [u8; 4] {
-        match self {
-            CellColor::Plain(color) => *color,
-            CellColor::RandomizeBrightness(base_color, max_value) => {
-                let mut cell = chunk.get_by_index(cell_index);
+    pub fn init_cell(&self, cell: &mut Cell, random_value: impl FnOnce() -> u64) -> bool {
+        let CellColor::RandomizeBrightness(_, max_value) = self else {
+            return false;
+        };
 
-                let mut system_reg = cell.registers[CELL_REGISTER_SYSTEM].to_le_bytes();
-                let mut brightness = system_reg[CELL_REGISTER_SYSTEM_BRIGHTNESS_VALUE];
-                let brightness_node_set = system_reg[CELL_REGISTER_SYSTEM_FLAGS]
-                    & CELL_REGISTER_SYSTEM_FLAG_IS_BRIGHTNESS_SET
-                    == 0;
+        let mut system_reg = cell.registers[CELL_REGISTER_SYSTEM].to_le_bytes();
+        if system_reg[CELL_REGISTER_SYSTEM_FLAGS] & CELL_REGISTER_SYSTEM_FLAG_IS_BRIGHTNESS_SET != 0
+        {
+            return false;
+        }
 
-                if brightness_node_set {
-                    let random_value = chunk.get_random_value(cell_index);
-                    brightness = (random_value % *max_value as u64) as u8;
+        system_reg[CELL_REGISTER_SYSTEM_BRIGHTNESS_VALUE] =
+            (random_value() % (*max_value).max(1) as u64) as u8;
+        system_reg[CELL_REGISTER_SYSTEM_FLAGS] |= CELL_REGISTER_SYSTEM_FLAG_IS_BRIGHTNESS_SET;
+        cell.registers[CELL_REGISTER_SYSTEM] = u32::from_le_bytes(system_reg);
 
-                    system_reg[CELL_REGISTER_SYSTEM_BRIGHTNESS_VALUE] = brightness;
-                    system_reg[CELL_REGISTER_SYSTEM_FLAGS] |=
-                        CELL_REGISTER_SYSTEM_FLAG_IS_BRIGHTNESS_SET;
+        true
+    }
 
-                    cell.registers[CELL_REGISTER_SYSTEM] = u32::from_le_bytes(system_reg);
-                    chunk.set_by_index(cell_index, cell);
-                }
+    #[inline(always)]
+    pub fn calculate(&self, cell: Cell) -> [u8; 4] {
+        self.calculate_from_system_register(cell.registers[CELL_REGISTER_SYSTEM])
+    }
+
+    /// Same as [`CellColor::calculate`], color depends only on [`CELL_REGISTER_SYSTEM`]
+    #[inline(always)]
+    pub fn calculate_from_system_register(&self, system_register: u32) -> [u8; 4] {
+        match self {
+            CellColor::Plain(color) => *color,
+            CellColor::RandomizeBrightness(base_color, _) => {
+                let system_reg = system_register.to_le_bytes();
+                let brightness = system_reg[CELL_REGISTER_SYSTEM_BRIGHTNESS_VALUE];
 
                 let mut color = *base_color;
                 color[0] = color[0].saturating_add(brightness);
@@ -85,107 +247,154 @@ impl CellColor {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
-pub enum CellRule {
+/// Cell behaviour rule.
+///
+/// `Id` is the way other cells are referenced: [`CellId`] at runtime or label ([`String`]) in
+/// template files. Same for `Reg`, the way registers are referenced: index at runtime or
+/// [`RegisterRef`] in template files.
+#[derive(Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum CellRule<Id = CellId, Reg = u8> {
     /// Do nothing, always succeed.
     ///
     /// NOTE: can be used to randomly stop processing if used in [`CellRule::FirstSuccess`]
+    #[default]
     Idle,
     /// If this `condition` is met, `action` will be executed
     If {
-        condition: RuleCondition,
-        action: Box<CellRule>,
-        else_action: Option<Box<CellRule>>,
+        condition: RuleCondition<Id, Reg>,
+        action: Box<CellRule<Id, Reg>>,
+        else_action: Option<Box<CellRule<Id, Reg>>>,
     },
-    /// Swap current cell with cell at position if it has specific id
+    /// Swap current cell with cell at position if it has specific id. Cells occupied by particles
+    /// can't be matched.
     SwapWithIds {
         pos: RelativePos,
-        match_ids: Vec<CellId>,
+        match_ids: Vec<Id>,
     },
     /// Even if rule applied, continue processing other rules.
-    ApplyAndContinue(Box<CellRule>),
+    ApplyAndContinue(Box<CellRule<Id, Reg>>),
     /// Rules will be checked in order they are provided and first matching rule will be executed.
-    FirstSuccess(Vec<CellRule>),
+    FirstSuccess(Vec<CellRule<Id, Reg>>),
     /// Pair of rules will be checked in random order and first matching rule will be executed.
-    RandomPair(Box<(CellRule, CellRule)>),
+    RandomPair(Box<(CellRule<Id, Reg>, CellRule<Id, Reg>)>),
+    /// Apply `rule` with probability `numerator / denominator`, fail otherwise. Used for slow
+    /// processes like drying or erosion, see [`RuleCondition::Chance`].
+    Chance {
+        numerator: u32,
+        denominator: u32,
+        rule: Box<CellRule<Id, Reg>>,
+    },
     /// Try to apply same rule twice: as is and mirrored by X axis. Randomly choose which one to
     /// apply first.
-    SymmetryX(Box<CellRule>),
+    SymmetryX(Box<CellRule<Id, Reg>>),
     /// Same as [`CellRule::SymmetryX`] but mirrored by Y axis.
-    SymmetryY(Box<CellRule>),
+    SymmetryY(Box<CellRule<Id, Reg>>),
     /// Same as [`CellRule::SymmetryX`] but instead of mirroring by X axis, swap X and Y
     /// coordinates.
-    SymmetryDiagonal(Box<CellRule>),
+    SymmetryDiagonal(Box<CellRule<Id, Reg>>),
     /// apply underlying rule as is or mirrored by X axis depending on condition
     MirrorXIf {
-        condition: RuleCondition,
-        rule: Box<CellRule>,
+        condition: RuleCondition<Id, Reg>,
+        rule: Box<CellRule<Id, Reg>>,
     },
     /// same as [`CellRule::MirrorXIf`] but mirrored by Y axis
     MirrorYIf {
-        condition: RuleCondition,
-        rule: Box<CellRule>,
+        condition: RuleCondition<Id, Reg>,
+        rule: Box<CellRule<Id, Reg>>,
     },
     /// same as [`CellRule::MirrorXIf`] but swap X and Y coordinates instead of mirroring by X axis
     MirrorDiagonalIf {
-        condition: RuleCondition,
-        rule: Box<CellRule>,
+        condition: RuleCondition<Id, Reg>,
+        rule: Box<CellRule<Id, Reg>>,
     },
 
     /// Execute all rules even if some some of them succeed.
-    TryAll(Vec<CellRule>),
+    TryAll(Vec<CellRule<Id, Reg>>),
     /// Set cell to specific id and initialize it.
     InitCell {
         pos: RelativePos,
-        cell_id: CellId,
+        cell_id: Id,
+    },
+    /// Same as [`CellRule::InitCell`] but keep the temperature of the replaced cell, used for
+    /// phase transitions (e.g. ice melting into water).
+    TransformCell {
+        pos: RelativePos,
+        cell_id: Id,
     },
+    /// Swap current cell with cell at position. Always succeeds, but cells occupied by particles
+    /// are never swapped (see [`CellsTemplate::particle_placeholder`]).
     SwapWith {
         pos: RelativePos,
     },
+    /// Turn the cell at `pos` into a particle flying with `vel` plus a random value in
+    /// `-spread..=spread` along each axis (cells per second), symmetry rules mirror the velocity.
+    /// The cell is replaced with the first cell of the template. Fails if the cell is replaceable
+    /// by particles (e.g. Vacuum) or is already a particle.
+    LaunchParticle {
+        pos: RelativePos,
+        vel: RelativeVelocity,
+        #[serde(default)]
+        spread: u16,
+    },
     IncrementRegister {
-        register: u8,
+        register: Reg,
         pos: RelativePos,
     },
     DecrementRegister {
-        register: u8,
+        register: Reg,
         pos: RelativePos,
     },
     SetRegister {
-        register: u8,
+        register: Reg,
         value: u32,
         pos: RelativePos,
     },
     SerRegisterRandomMasked {
-        register: u8,
+        register: Reg,
         mask: u32,
         pos: RelativePos,
     },
+    /// Set register of the cell at `pos` to `value`, which is evaluated relative to the current
+    /// cell before the write.
+    SetRegisterExpr {
+        register: Reg,
+        value: ConditionArg<Reg>,
+        pos: RelativePos,
+    },
     MoveRegister {
-        source_register: u8,
+        source_register: Reg,
         source_cell: RelativePos,
-        target_register: u8,
+        target_register: Reg,
         target_cell: RelativePos,
     },
 }
 
-impl CellRule {
-    pub fn random_pair(first: CellRule, second: CellRule) -> Self {
+impl<Id> CellRule<Id> {
+    pub fn random_pair(first: CellRule<Id>, second: CellRule<Id>) -> Self {
         CellRule::RandomPair(Box::new((first, second)))
     }
-    pub fn apply_and_continue(rule: CellRule) -> Self {
+    /// Apply `rule` with probability `numerator / denominator`
+    pub fn chance(numerator: u32, denominator: u32, rule: CellRule<Id>) -> Self {
+        CellRule::Chance {
+            numerator,
+            denominator,
+            rule: Box::new(rule),
+        }
+    }
+    pub fn apply_and_continue(rule: CellRule<Id>) -> Self {
         CellRule::ApplyAndContinue(Box::new(rule))
     }
-    pub fn symmetry_x(rule: CellRule) -> Self {
+    pub fn symmetry_x(rule: CellRule<Id>) -> Self {
         CellRule::SymmetryX(Box::new(rule))
     }
-    pub fn symmetry_y(rule: CellRule) -> Self {
+    pub fn symmetry_y(rule: CellRule<Id>) -> Self {
         CellRule::SymmetryY(Box::new(rule))
     }
-    pub fn symmetry_diagonal(rule: CellRule) -> Self {
+    pub fn symmetry_diagonal(rule: CellRule<Id>) -> Self {
         CellRule::SymmetryDiagonal(Box::new(rule))
     }
 
-    pub fn if_then(condition: RuleCondition, action: CellRule) -> Self {
+    pub fn if_then(condition: RuleCondition<Id>, action: CellRule<Id>) -> Self {
         CellRule::If {
             condition,
             action: Box::new(action),
@@ -193,7 +402,11 @@ impl CellRule {
         }
     }
 
-    pub fn if_else(condition: RuleCondition, action: CellRule, else_action: CellRule) -> Self {
+    pub fn if_else(
+        condition: RuleCondition<Id>,
+        action: CellRule<Id>,
+        else_action: CellRule<Id>,
+    ) -> Self {
         CellRule::If {
             condition,
             action: Box::new(action),
@@ -201,19 +414,19 @@ impl CellRule {
         }
     }
 
-    pub fn mirror_x_if(condition: RuleCondition, rule: CellRule) -> Self {
+    pub fn mirror_x_if(condition: RuleCondition<Id>, rule: CellRule<Id>) -> Self {
         CellRule::MirrorXIf {
             condition,
             rule: Box::new(rule),
         }
     }
-    pub fn mirror_y_if(condition: RuleCondition, rule: CellRule) -> Self {
+    pub fn mirror_y_if(condition: RuleCondition<Id>, rule: CellRule<Id>) -> Self {
         CellRule::MirrorYIf {
             condition,
             rule: Box::new(rule),
         }
     }
-    pub fn mirror_diagonal_if(condition: RuleCondition, rule: CellRule) -> Self {
+    pub fn mirror_diagonal_if(condition: RuleCondition<Id>, rule: CellRule<Id>) -> Self {
         CellRule::MirrorDiagonalIf {
             condition,
             rule: Box::new(rule),
@@ -229,43 +442,68 @@ impl CellRule {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
-pub enum RuleCondition {
-    And(Vec<RuleCondition>),
-    Or(Vec<RuleCondition>),
-    Not(Box<RuleCondition>),
+/// Condition of [`CellRule::If`] and similar rules. See [`CellRule`] for the meaning of `Id` and
+/// `Reg`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum RuleCondition<Id = CellId, Reg = u8> {
+    And(Vec<RuleCondition<Id, Reg>>),
+    Or(Vec<RuleCondition<Id, Reg>>),
+    Not(Box<RuleCondition<Id, Reg>>),
     /// Check if cell at position has specific id
     RelativeCell {
         pos: RelativePos,
-        cell_id: CellId,
+        cell_id: Id,
     },
     /// Check if cell at position does not have specific id
     RelativeCellNot {
         pos: RelativePos,
-        cell_id: CellId,
+        cell_id: Id,
     },
     /// Check if cell at position has id from list
     RelativeCellIn {
         pos: RelativePos,
-        cell_id_list: Vec<CellId>,
+        cell_id_list: Vec<Id>,
     },
     /// Check if cell at position does not have id from list
     RelativeCellNotIn {
         pos: RelativePos,
-        cell_id_list: Vec<CellId>,
+        cell_id_list: Vec<Id>,
     },
     BinaryOp {
         op: ConditionBinaryOp,
-        a: ConditionArg,
-        b: ConditionArg,
+        a: ConditionArg<Reg>,
+        b: ConditionArg<Reg>,
+    },
+    /// Met with probability `numerator / denominator`, drawn from the random state of the current
+    /// cell (see [`Chunk::get_random_value`]). Failed roll keeps the chunk updated, so the process
+    /// isn't stopped by the chunk going idle.
+    Chance {
+        numerator: u32,
+        denominator: u32,
+    },
+    /// Compare temperature of the cell at position with `value` (temperature is the left operand)
+    Temperature {
+        pos: RelativePos,
+        op: ConditionBinaryOp,
+        value: Temperature,
     },
     Always,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
-pub enum ConditionArg {
-    Register { pos: RelativePos, register: u8 },
+/// Value used by [`RuleCondition::BinaryOp`] and [`CellRule::SetRegisterExpr`]: constant, register
+/// of a cell relative to the current one or arithmetic on other values.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum ConditionArg<Reg = u8> {
+    Register {
+        pos: RelativePos,
+        register: Reg,
+    },
     Value(u32),
+    Op {
+        op: ArithmeticOp,
+        a: Box<ConditionArg<Reg>>,
+        b: Box<ConditionArg<Reg>>,
+    },
 }
 
 impl ConditionArg {
@@ -283,9 +521,45 @@ impl ConditionArg {
     pub fn value(value: u32) -> Self {
         ConditionArg::Value(value)
     }
+
+    pub fn op(op: ArithmeticOp, a: ConditionArg, b: ConditionArg) -> Self {
+        ConditionArg::Op {
+            op,
+            a: Box::new(a),
+            b: Box::new(b),
+        }
+    }
+}
+
+/// Operation of [`ConditionArg::Op`]. Registers are unsigned: addition, subtraction and
+/// multiplication saturate, division and remainder by zero give zero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum ArithmeticOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Min,
+    Max,
+}
+
+impl ArithmeticOp {
+    #[inline(always)]
+    pub fn apply(self, a: u32, b: u32) -> u32 {
+        match self {
+            ArithmeticOp::Add => a.saturating_add(b),
+            ArithmeticOp::Sub => a.saturating_sub(b),
+            ArithmeticOp::Mul => a.saturating_mul(b),
+            ArithmeticOp::Div => a.checked_div(b).unwrap_or(0),
+            ArithmeticOp::Rem => a.checked_rem(b).unwrap_or(0),
+            ArithmeticOp::Min => a.min(b),
+            ArithmeticOp::Max => a.max(b),
+        }
+    }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum ConditionBinaryOp {
     Eq,
     NotEq,
@@ -295,7 +569,21 @@ pub enum ConditionBinaryOp {
     GreaterEq,
 }
 
-impl RuleCondition {
+impl ConditionBinaryOp {
+    #[inline(always)]
+    pub fn apply<T: Ord>(self, a: T, b: T) -> bool {
+        match self {
+            ConditionBinaryOp::Eq => a == b,
+            ConditionBinaryOp::NotEq => a != b,
+            ConditionBinaryOp::Less => a < b,
+            ConditionBinaryOp::LessEq => a <= b,
+            ConditionBinaryOp::Greater => a > b,
+            ConditionBinaryOp::GreaterEq => a >= b,
+        }
+    }
+}
+
+impl<Id> RuleCondition<Id> {
     pub const fn reg_non_zero(register: u8) -> Self {
         RuleCondition::BinaryOp {
             op: ConditionBinaryOp::NotEq,
@@ -340,6 +628,61 @@ impl RuleCondition {
         }
     }
 
+    /// Check if [`CELL_REGISTER_AGE`] of the current cell is at least `ticks`
+    pub const fn age_greater_eq(ticks: u32) -> Self {
+        RuleCondition::BinaryOp {
+            op: ConditionBinaryOp::GreaterEq,
+            a: ConditionArg::Register {
+                pos: RelativePos::self_pos(),
+                register: CELL_REGISTER_AGE as u8,
+            },
+            b: ConditionArg::Value(ticks),
+        }
+    }
+
+    /// Check if [`CELL_REGISTER_AGE`] of the current cell is less than `ticks`
+    pub const fn age_less(ticks: u32) -> Self {
+        RuleCondition::BinaryOp {
+            op: ConditionBinaryOp::Less,
+            a: ConditionArg::Register {
+                pos: RelativePos::self_pos(),
+                register: CELL_REGISTER_AGE as u8,
+            },
+            b: ConditionArg::Value(ticks),
+        }
+    }
+
+    /// Met with probability `1 / denominator`
+    pub const fn one_in(denominator: u32) -> Self {
+        RuleCondition::chance(1, denominator)
+    }
+
+    /// Met with probability `numerator / denominator`
+    pub const fn chance(numerator: u32, denominator: u32) -> Self {
+        RuleCondition::Chance {
+            numerator,
+            denominator,
+        }
+    }
+
+    /// Check if temperature of the current cell is at least `value`
+    pub const fn temperature_greater_eq(value: Temperature) -> Self {
+        RuleCondition::Temperature {
+            pos: RelativePos::self_pos(),
+            op: ConditionBinaryOp::GreaterEq,
+            value,
+        }
+    }
+
+    /// Check if temperature of the current cell is less than `value`
+    pub const fn temperature_less(value: Temperature) -> Self {
+        RuleCondition::Temperature {
+            pos: RelativePos::self_pos(),
+            op: ConditionBinaryOp::Less,
+            value,
+        }
+    }
+
     pub const fn reg_eq(register: u8, value: u32) -> Self {
         RuleCondition::BinaryOp {
             op: ConditionBinaryOp::Eq,
@@ -3,9 +3,17 @@ mod cell_template_builder;
 mod cells_template;
 mod config;
 mod pos;
+mod rule_program;
+mod temperature;
+mod template_file;
+mod template_validation;
 
 pub use cell_state::*;
 pub use cell_template_builder::*;
 pub use cells_template::*;
 pub use config::*;
 pub use pos::*;
+pub use rule_program::*;
+pub use temperature::*;
+pub use template_file::*;
+pub use template_validation::*;
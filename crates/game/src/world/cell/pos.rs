@@ -1,5 +1,5 @@
 use crate::*;
-use macroquad::math::Vec2;
+use glam::Vec2;
 
 pub type CellCord = u16;
 
@@ -0,0 +1,989 @@
+use crate::*;
+
+/// Largest allowed [`RuleProgram::compiled_len_bound`], checked by [`validate_cells`]. Nested
+/// symmetry rules grow the program exponentially.
+pub const RULE_PROGRAM_MAX_LEN: usize = 1 << 16;
+
+/// [`CellRule`] compiled into a flat list of instructions, see [`RuleProgram::compile`].
+///
+/// Symmetry rules are expanded into copies of the inner rule with transformed positions, so the
+/// interpreter doesn't track transformations. Random values are drawn in the same order as by the
+/// tree interpreter, both give exactly the same simulation.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RuleProgram {
+    pub ops: Vec<RuleOp>,
+    /// Cell id lists referenced by [`RuleOp::CellIn`] and [`RuleOp::SwapWithIds`]
+    pub id_lists: Vec<Vec<CellId>>,
+    /// Arithmetic expressions referenced by [`Operand::Expr`], positions are already transformed
+    pub exprs: Vec<ConditionArg>,
+}
+
+/// Instruction of [`RuleProgram`].
+///
+/// Conditions set the flag checked by [`RuleOp::JumpIfFalse`], actions always succeed. Positions
+/// are relative to the current cell with the symmetry transformations applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleOp {
+    Jump(u32),
+    JumpIfFalse(u32),
+    /// Draw random value of the cell and jump if it's odd, used by [`CellRule::RandomPair`] and
+    /// symmetry rules to choose the order
+    JumpIfRandomOdd(u32),
+    /// Stop execution, the value is the result of the rule
+    Return(bool),
+
+    CellIs {
+        pos: RelativePos,
+        cell_id: CellId,
+    },
+    CellIn {
+        pos: RelativePos,
+        list: u32,
+    },
+    Compare {
+        op: ConditionBinaryOp,
+        a: Operand,
+        b: Operand,
+    },
+    Chance {
+        numerator: u32,
+        denominator: u32,
+    },
+    Temperature {
+        pos: RelativePos,
+        op: ConditionBinaryOp,
+        value: Temperature,
+    },
+
+    /// Sets the flag if cells were swapped
+    SwapWithIds {
+        pos: RelativePos,
+        list: u32,
+    },
+    InitCell {
+        pos: RelativePos,
+        cell_id: CellId,
+    },
+    TransformCell {
+        pos: RelativePos,
+        cell_id: CellId,
+    },
+    SwapWith {
+        pos: RelativePos,
+    },
+    /// Sets the flag if the particle was launched
+    LaunchParticle {
+        pos: RelativePos,
+        vel: RelativeVelocity,
+        spread: u16,
+    },
+    IncrementRegister {
+        pos: RelativePos,
+        register: u8,
+    },
+    DecrementRegister {
+        pos: RelativePos,
+        register: u8,
+    },
+    SetRegister {
+        pos: RelativePos,
+        register: u8,
+        value: u32,
+    },
+    SetRegisterRandomMasked {
+        pos: RelativePos,
+        register: u8,
+        mask: u32,
+    },
+    SetRegisterExpr {
+        pos: RelativePos,
+        register: u8,
+        value: Operand,
+    },
+    MoveRegister {
+        source_pos: RelativePos,
+        source_register: u8,
+        target_pos: RelativePos,
+        target_register: u8,
+    },
+}
+
+/// Value of [`RuleOp`], common cases of [`ConditionArg`] are stored inline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Value(u32),
+    Register {
+        pos: RelativePos,
+        register: u8,
+    },
+    /// Index in [`RuleProgram::exprs`]
+    Expr(u32),
+}
+
+impl RuleProgram {
+    /// Compile the rule. It must be valid (see [`validate_cells`]), e.g. register indices are not
+    /// checked by the interpreter.
+    pub fn compile(rule: &CellRule) -> Self {
+        let mut compiler = Compiler::default();
+        let success = compiler.new_label();
+        let failure = compiler.new_label();
+
+        compiler.rule(rule, RelativeTransformation::identity(), success, failure);
+        compiler.place_label(success);
+        compiler.program.ops.push(RuleOp::Return(true));
+        compiler.place_label(failure);
+        compiler.program.ops.push(RuleOp::Return(false));
+
+        compiler.finish()
+    }
+
+    /// Positions of cells whose `register` is read by the program
+    pub fn register_reads(&self, register: u8) -> Vec<RelativePos> {
+        fn arg_reads(arg: &ConditionArg, register: u8, reads: &mut Vec<RelativePos>) {
+            match arg {
+                ConditionArg::Register { pos, register: r } if *r == register => reads.push(*pos),
+                ConditionArg::Op { a, b, .. } => {
+                    arg_reads(a, register, reads);
+                    arg_reads(b, register, reads);
+                }
+                ConditionArg::Register { .. } | ConditionArg::Value(_) => {}
+            }
+        }
+
+        let mut reads = Vec::new();
+        let operand_reads = |operand: Operand, reads: &mut Vec<RelativePos>| match operand {
+            Operand::Register { pos, register: r } if r == register => reads.push(pos),
+            // expressions are checked separately
+            Operand::Register { .. } | Operand::Value(_) | Operand::Expr(_) => {}
+        };
+
+        for op in &self.ops {
+            match *op {
+                RuleOp::Compare { a, b, .. } => {
+                    operand_reads(a, &mut reads);
+                    operand_reads(b, &mut reads);
+                }
+                RuleOp::SetRegisterExpr { value, .. } => operand_reads(value, &mut reads),
+                RuleOp::MoveRegister {
+                    source_pos,
+                    source_register,
+                    ..
+                } if source_register == register => reads.push(source_pos),
+                RuleOp::IncrementRegister { pos, register: r }
+                | RuleOp::DecrementRegister { pos, register: r }
+                    if r == register =>
+                {
+                    reads.push(pos)
+                }
+                _ => {}
+            }
+        }
+        for expr in &self.exprs {
+            arg_reads(expr, register, &mut reads);
+        }
+
+        reads
+    }
+
+    /// Upper bound of the number of instructions [`RuleProgram::compile`] emits for `rule`,
+    /// computed without compiling it.
+    ///
+    /// Both branches of [`CellRule::RandomPair`] are emitted in both orders, so each symmetry rule
+    /// quadruples the size of the rule nested in it.
+    pub fn compiled_len_bound(rule: &CellRule) -> usize {
+        fn condition_len(condition: &RuleCondition) -> usize {
+            match condition {
+                RuleCondition::And(conditions) | RuleCondition::Or(conditions) => conditions
+                    .iter()
+                    .map(condition_len)
+                    .fold(1, usize::saturating_add),
+                RuleCondition::Not(condition) => condition_len(condition),
+                RuleCondition::Always => 1,
+                _ => 3,
+            }
+        }
+
+        fn rule_len(rule: &CellRule) -> usize {
+            match rule {
+                CellRule::Idle => 1,
+                CellRule::If {
+                    condition,
+                    action,
+                    else_action,
+                } => condition_len(condition)
+                    .saturating_add(rule_len(action))
+                    .saturating_add(else_action.as_deref().map_or(1, rule_len)),
+                CellRule::SwapWithIds { .. } | CellRule::LaunchParticle { .. } => 3,
+                CellRule::ApplyAndContinue(rule) => rule_len(rule).saturating_add(1),
+                CellRule::FirstSuccess(rules) | CellRule::TryAll(rules) => {
+                    rules.iter().map(rule_len).fold(1, usize::saturating_add)
+                }
+                CellRule::RandomPair(pair) => rule_len(&pair.0)
+                    .saturating_add(rule_len(&pair.1))
+                    .saturating_mul(2)
+                    .saturating_add(1),
+                CellRule::Chance { rule, .. } => rule_len(rule).saturating_add(2),
+                CellRule::SymmetryX(rule)
+                | CellRule::SymmetryY(rule)
+                | CellRule::SymmetryDiagonal(rule) => {
+                    rule_len(rule).saturating_mul(4).saturating_add(1)
+                }
+                CellRule::MirrorXIf { condition, rule }
+                | CellRule::MirrorYIf { condition, rule }
+                | CellRule::MirrorDiagonalIf { condition, rule } => {
+                    condition_len(condition).saturating_add(rule_len(rule))
+                }
+                CellRule::InitCell { .. }
+                | CellRule::TransformCell { .. }
+                | CellRule::SwapWith { .. }
+                | CellRule::IncrementRegister { .. }
+                | CellRule::DecrementRegister { .. }
+                | CellRule::SetRegister { .. }
+                | CellRule::SerRegisterRandomMasked { .. }
+                | CellRule::SetRegisterExpr { .. }
+                | CellRule::MoveRegister { .. } => 2,
+            }
+        }
+
+        // return instructions for the success and the failure
+        rule_len(rule).saturating_add(2)
+    }
+
+    /// Largest distance (along any axis) from the current cell to cells used by the program
+    pub fn max_offset(&self) -> i16 {
+        fn offset(pos: RelativePos) -> i16 {
+            (pos.x as i16).abs().max((pos.y as i16).abs())
+        }
+
+        fn arg_offset(arg: &ConditionArg) -> i16 {
+            match arg {
+                ConditionArg::Register { pos, .. } => offset(*pos),
+                ConditionArg::Value(_) => 0,
+                ConditionArg::Op { a, b, .. } => arg_offset(a).max(arg_offset(b)),
+            }
+        }
+
+        fn operand_offset(operand: Operand) -> i16 {
+            match operand {
+                Operand::Register { pos, .. } => offset(pos),
+                // expressions are checked separately
+                Operand::Value(_) | Operand::Expr(_) => 0,
+            }
+        }
+
+        let ops = self.ops.iter().map(|op| match *op {
+            RuleOp::Jump(_)
+            | RuleOp::JumpIfFalse(_)
+            | RuleOp::JumpIfRandomOdd(_)
+            | RuleOp::Return(_)
+            | RuleOp::Chance { .. } => 0,
+            RuleOp::Compare { a, b, .. } => operand_offset(a).max(operand_offset(b)),
+            RuleOp::SetRegisterExpr { pos, value, .. } => offset(pos).max(operand_offset(value)),
+            RuleOp::MoveRegister {
+                source_pos,
+                target_pos,
+                ..
+            } => offset(source_pos).max(offset(target_pos)),
+            RuleOp::CellIs { pos, .. }
+            | RuleOp::CellIn { pos, .. }
+            | RuleOp::Temperature { pos, .. }
+            | RuleOp::SwapWithIds { pos, .. }
+            | RuleOp::InitCell { pos, .. }
+            | RuleOp::TransformCell { pos, .. }
+            | RuleOp::SwapWith { pos }
+            | RuleOp::LaunchParticle { pos, .. }
+            | RuleOp::IncrementRegister { pos, .. }
+            | RuleOp::DecrementRegister { pos, .. }
+            | RuleOp::SetRegister { pos, .. }
+            | RuleOp::SetRegisterRandomMasked { pos, .. } => offset(pos),
+        });
+
+        ops.chain(self.exprs.iter().map(arg_offset))
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// Label is an index in [`Compiler::labels`], jumps point to labels until [`Compiler::finish`]
+type Label = u32;
+
+#[derive(Default)]
+struct Compiler {
+    program: RuleProgram,
+    /// Address of every label, set when the label is placed
+    labels: Vec<Option<u32>>,
+}
+
+impl Compiler {
+    fn new_label(&mut self) -> Label {
+        self.labels.push(None);
+        (self.labels.len() - 1) as Label
+    }
+
+    fn place_label(&mut self, label: Label) {
+        self.labels[label as usize] = Some(self.program.ops.len() as u32);
+    }
+
+    fn emit(&mut self, op: RuleOp) {
+        self.program.ops.push(op);
+    }
+
+    /// Replace labels with addresses of instructions. Jumps to unconditional jumps are threaded
+    /// to the final target, jumps to returns are replaced with returns and jumps to the next
+    /// instruction are removed.
+    fn finish(mut self) -> RuleProgram {
+        let address = |label: u32| self.labels[label as usize].expect("Label is not placed");
+
+        for op in &mut self.program.ops {
+            match op {
+                RuleOp::Jump(target)
+                | RuleOp::JumpIfFalse(target)
+                | RuleOp::JumpIfRandomOdd(target) => *target = address(*target),
+                _ => {}
+            }
+        }
+
+        let ops = self.program.ops.clone();
+        let final_target = |mut target: u32| {
+            // compiled rules have no loops, so the chain ends
+            while let RuleOp::Jump(next) = ops[target as usize] {
+                target = next;
+            }
+            target
+        };
+
+        for op in &mut self.program.ops {
+            match op {
+                RuleOp::Jump(target) => {
+                    let target = final_target(*target);
+                    *op = match ops[target as usize] {
+                        RuleOp::Return(result) => RuleOp::Return(result),
+                        _ => RuleOp::Jump(target),
+                    };
+                }
+                RuleOp::JumpIfFalse(target) | RuleOp::JumpIfRandomOdd(target) => {
+                    *target = final_target(*target);
+                }
+                _ => {}
+            }
+        }
+
+        // remove jumps to the next instruction, address of every instruction is shifted by the
+        // number of removed ones before it
+        let ops = std::mem::take(&mut self.program.ops);
+        let is_removed = |(address, op): (usize, &RuleOp)| *op == RuleOp::Jump(address as u32 + 1);
+        let mut new_addresses = Vec::with_capacity(ops.len() + 1);
+        let mut removed = 0;
+        for (address, op) in ops.iter().enumerate() {
+            new_addresses.push((address - removed) as u32);
+            removed += is_removed((address, op)) as usize;
+        }
+        new_addresses.push((ops.len() - removed) as u32);
+
+        for (address, mut op) in ops.into_iter().enumerate() {
+            if is_removed((address, &op)) {
+                continue;
+            }
+            if let RuleOp::Jump(target)
+            | RuleOp::JumpIfFalse(target)
+            | RuleOp::JumpIfRandomOdd(target) = &mut op
+            {
+                *target = new_addresses[*target as usize];
+            }
+            self.program.ops.push(op);
+        }
+
+        self.program
+    }
+
+    fn id_list(&mut self, ids: &[CellId]) -> u32 {
+        self.program.id_lists.push(ids.to_vec());
+        (self.program.id_lists.len() - 1) as u32
+    }
+
+    /// Emit code of `rule` jumping to `success` or `failure` depending on the result
+    fn rule(&mut self, rule: &CellRule, t: RelativeTransformation, success: Label, failure: Label) {
+        match rule {
+            CellRule::Idle => self.emit(RuleOp::Jump(success)),
+            CellRule::If {
+                condition,
+                action,
+                else_action,
+            } => {
+                let then_label = self.new_label();
+                let else_label = self.new_label();
+
+                self.condition(condition, t, then_label, else_label);
+                self.place_label(then_label);
+                self.rule(action, t, success, failure);
+                self.place_label(else_label);
+                match else_action {
+                    Some(else_action) => self.rule(else_action, t, success, failure),
+                    None => self.emit(RuleOp::Jump(failure)),
+                }
+            }
+            CellRule::SwapWithIds { pos, match_ids } => {
+                let list = self.id_list(match_ids);
+                self.emit(RuleOp::SwapWithIds {
+                    pos: pos.transform(t),
+                    list,
+                });
+                self.emit(RuleOp::JumpIfFalse(failure));
+                self.emit(RuleOp::Jump(success));
+            }
+            CellRule::ApplyAndContinue(rule) => {
+                let after = self.new_label();
+                self.rule(rule, t, after, after);
+                self.place_label(after);
+                self.emit(RuleOp::Jump(failure));
+            }
+            CellRule::FirstSuccess(rules) => {
+                for rule in rules {
+                    let next = self.new_label();
+                    self.rule(rule, t, success, next);
+                    self.place_label(next);
+                }
+                self.emit(RuleOp::Jump(failure));
+            }
+            CellRule::TryAll(rules) => {
+                for rule in rules {
+                    let next = self.new_label();
+                    self.rule(rule, t, next, next);
+                    self.place_label(next);
+                }
+                self.emit(RuleOp::Jump(success));
+            }
+            CellRule::RandomPair(pair) => {
+                let (a, b) = pair.as_ref();
+                self.random_pair((a, t), (b, t), success, failure);
+            }
+            CellRule::Chance {
+                numerator,
+                denominator,
+                rule,
+            } => {
+                self.emit(RuleOp::Chance {
+                    numerator: *numerator,
+                    denominator: *denominator,
+                });
+                self.emit(RuleOp::JumpIfFalse(failure));
+                self.rule(rule, t, success, failure);
+            }
+            CellRule::SymmetryX(rule) => {
+                self.random_pair((rule, t), (rule, t.mirror_x()), success, failure);
+            }
+            CellRule::SymmetryY(rule) => {
+                self.random_pair((rule, t), (rule, t.mirror_y()), success, failure);
+            }
+            CellRule::SymmetryDiagonal(rule) => {
+                self.random_pair((rule, t), (rule, t.mirror_diagonal()), success, failure);
+            }
+            CellRule::MirrorXIf { condition, rule } => {
+                self.mirror_if(condition, rule, t, t.mirror_x(), success, failure);
+            }
+            CellRule::MirrorYIf { condition, rule } => {
+                self.mirror_if(condition, rule, t, t.mirror_y(), success, failure);
+            }
+            CellRule::MirrorDiagonalIf { condition, rule } => {
+                self.mirror_if(condition, rule, t, t.mirror_diagonal(), success, failure);
+            }
+            CellRule::InitCell { pos, cell_id } => {
+                self.action(
+                    RuleOp::InitCell {
+                        pos: pos.transform(t),
+                        cell_id: *cell_id,
+                    },
+                    success,
+                );
+            }
+            CellRule::TransformCell { pos, cell_id } => {
+                self.action(
+                    RuleOp::TransformCell {
+                        pos: pos.transform(t),
+                        cell_id: *cell_id,
+                    },
+                    success,
+                );
+            }
+            CellRule::SwapWith { pos } => {
+                self.action(
+                    RuleOp::SwapWith {
+                        pos: pos.transform(t),
+                    },
+                    success,
+                );
+            }
+            CellRule::LaunchParticle { pos, vel, spread } => {
+                self.emit(RuleOp::LaunchParticle {
+                    pos: pos.transform(t),
+                    vel: vel.transform(t),
+                    spread: *spread,
+                });
+                self.emit(RuleOp::JumpIfFalse(failure));
+                self.emit(RuleOp::Jump(success));
+            }
+            CellRule::IncrementRegister { register, pos } => {
+                self.action(
+                    RuleOp::IncrementRegister {
+                        pos: pos.transform(t),
+                        register: *register,
+                    },
+                    success,
+                );
+            }
+            CellRule::DecrementRegister { register, pos } => {
+                self.action(
+                    RuleOp::DecrementRegister {
+                        pos: pos.transform(t),
+                        register: *register,
+                    },
+                    success,
+                );
+            }
+            CellRule::SetRegister {
+                register,
+                value,
+                pos,
+            } => {
+                self.action(
+                    RuleOp::SetRegister {
+                        pos: pos.transform(t),
+                        register: *register,
+                        value: *value,
+                    },
+                    success,
+                );
+            }
+            CellRule::SerRegisterRandomMasked {
+                register,
+                mask,
+                pos,
+            } => {
+                self.action(
+                    RuleOp::SetRegisterRandomMasked {
+                        pos: pos.transform(t),
+                        register: *register,
+                        mask: *mask,
+                    },
+                    success,
+                );
+            }
+            CellRule::SetRegisterExpr {
+                register,
+                value,
+                pos,
+            } => {
+                let value = self.operand(value, t);
+                self.action(
+                    RuleOp::SetRegisterExpr {
+                        pos: pos.transform(t),
+                        register: *register,
+                        value,
+                    },
+                    success,
+                );
+            }
+            CellRule::MoveRegister {
+                source_register,
+                source_cell,
+                target_register,
+                target_cell,
+            } => {
+                self.action(
+                    RuleOp::MoveRegister {
+                        source_pos: source_cell.transform(t),
+                        source_register: *source_register,
+                        target_pos: target_cell.transform(t),
+                        target_register: *target_register,
+                    },
+                    success,
+                );
+            }
+        }
+    }
+
+    fn action(&mut self, op: RuleOp, success: Label) {
+        self.emit(op);
+        self.emit(RuleOp::Jump(success));
+    }
+
+    /// Try rules in random order, same as [`ChunkUpdateContext`] does for [`CellRule::RandomPair`]
+    fn random_pair(
+        &mut self,
+        a: (&CellRule, RelativeTransformation),
+        b: (&CellRule, RelativeTransformation),
+        success: Label,
+        failure: Label,
+    ) {
+        let reversed = self.new_label();
+        let second_b = self.new_label();
+        let second_a = self.new_label();
+
+        self.emit(RuleOp::JumpIfRandomOdd(reversed));
+        self.rule(a.0, a.1, success, second_b);
+        self.place_label(second_b);
+        self.rule(b.0, b.1, success, failure);
+
+        self.place_label(reversed);
+        self.rule(b.0, b.1, success, second_a);
+        self.place_label(second_a);
+        self.rule(a.0, a.1, success, failure);
+    }
+
+    fn mirror_if(
+        &mut self,
+        condition: &RuleCondition,
+        rule: &CellRule,
+        t: RelativeTransformation,
+        mirrored: RelativeTransformation,
+        success: Label,
+        failure: Label,
+    ) {
+        let then_label = self.new_label();
+        self.condition(condition, t, then_label, failure);
+        self.place_label(then_label);
+        self.rule(rule, mirrored, success, failure);
+    }
+
+    /// Emit code of `condition` jumping to `on_true` or `on_false`, evaluated lazily like
+    /// [`RuleCondition::And`] and [`RuleCondition::Or`] are by the tree interpreter
+    fn condition(
+        &mut self,
+        condition: &RuleCondition,
+        t: RelativeTransformation,
+        on_true: Label,
+        on_false: Label,
+    ) {
+        match condition {
+            RuleCondition::And(conditions) => {
+                for condition in conditions {
+                    let next = self.new_label();
+                    self.condition(condition, t, next, on_false);
+                    self.place_label(next);
+                }
+                self.emit(RuleOp::Jump(on_true));
+            }
+            RuleCondition::Or(conditions) => {
+                for condition in conditions {
+                    let next = self.new_label();
+                    self.condition(condition, t, on_true, next);
+                    self.place_label(next);
+                }
+                self.emit(RuleOp::Jump(on_false));
+            }
+            RuleCondition::Not(condition) => self.condition(condition, t, on_false, on_true),
+            RuleCondition::RelativeCell { pos, cell_id } => {
+                let op = RuleOp::CellIs {
+                    pos: pos.transform(t),
+                    cell_id: *cell_id,
+                };
+                self.flag(op, on_true, on_false);
+            }
+            RuleCondition::RelativeCellNot { pos, cell_id } => {
+                let op = RuleOp::CellIs {
+                    pos: pos.transform(t),
+                    cell_id: *cell_id,
+                };
+                self.flag(op, on_false, on_true);
+            }
+            RuleCondition::RelativeCellIn { pos, cell_id_list } => {
+                let op = RuleOp::CellIn {
+                    pos: pos.transform(t),
+                    list: self.id_list(cell_id_list),
+                };
+                self.flag(op, on_true, on_false);
+            }
+            RuleCondition::RelativeCellNotIn { pos, cell_id_list } => {
+                let op = RuleOp::CellIn {
+                    pos: pos.transform(t),
+                    list: self.id_list(cell_id_list),
+                };
+                self.flag(op, on_false, on_true);
+            }
+            RuleCondition::BinaryOp { op, a, b } => {
+                let op = RuleOp::Compare {
+                    op: *op,
+                    a: self.operand(a, t),
+                    b: self.operand(b, t),
+                };
+                self.flag(op, on_true, on_false);
+            }
+            RuleCondition::Chance {
+                numerator,
+                denominator,
+            } => {
+                let op = RuleOp::Chance {
+                    numerator: *numerator,
+                    denominator: *denominator,
+                };
+                self.flag(op, on_true, on_false);
+            }
+            RuleCondition::Temperature { pos, op, value } => {
+                let op = RuleOp::Temperature {
+                    pos: pos.transform(t),
+                    op: *op,
+                    value: *value,
+                };
+                self.flag(op, on_true, on_false);
+            }
+            RuleCondition::Always => self.emit(RuleOp::Jump(on_true)),
+        }
+    }
+
+    /// Emit instruction setting the flag and jumps depending on it
+    fn flag(&mut self, op: RuleOp, on_true: Label, on_false: Label) {
+        self.emit(op);
+        self.emit(RuleOp::JumpIfFalse(on_false));
+        self.emit(RuleOp::Jump(on_true));
+    }
+
+    fn operand(&mut self, arg: &ConditionArg, t: RelativeTransformation) -> Operand {
+        match arg {
+            ConditionArg::Value(value) => Operand::Value(*value),
+            ConditionArg::Register { pos, register } => Operand::Register {
+                pos: pos.transform(t),
+                register: *register,
+            },
+            ConditionArg::Op { .. } => {
+                self.program.exprs.push(transform_arg(arg, t));
+                Operand::Expr((self.program.exprs.len() - 1) as u32)
+            }
+        }
+    }
+}
+
+fn transform_arg(arg: &ConditionArg, t: RelativeTransformation) -> ConditionArg {
+    match arg {
+        ConditionArg::Value(value) => ConditionArg::Value(*value),
+        ConditionArg::Register { pos, register } => ConditionArg::Register {
+            pos: pos.transform(t),
+            register: *register,
+        },
+        ConditionArg::Op { op, a, b } => {
+            ConditionArg::op(*op, transform_arg(a, t), transform_arg(b, t))
+        }
+    }
+}
+
+/// Run the same world with both evaluators and check that every cell and particle is the same
+/// after each tick
+#[cfg(test)]
+fn assert_evaluators_match(
+    template: &CellsTemplate,
+    setup: impl Fn(&mut WorldState),
+    ticks: usize,
+) {
+    let mut worlds = [RuleEvaluator::Tree, RuleEvaluator::Bytecode].map(|evaluator| {
+        let mut world = WorldState::with_seed(5);
+        world.set_rule_evaluator(evaluator);
+        setup(&mut world);
+        world
+    });
+
+    for tick in 0..ticks {
+        let [tree, bytecode] = &mut worlds;
+        let updated = tree.update_state(template).unwrap();
+        assert_eq!(
+            bytecode.update_state(template).unwrap(),
+            updated,
+            "tick {tick}"
+        );
+
+        let mut tree_chunks: Vec<_> = tree.chunks().collect();
+        let mut bytecode_chunks: Vec<_> = bytecode.chunks().collect();
+        tree_chunks.sort_by_key(|(pos, _)| *pos);
+        bytecode_chunks.sort_by_key(|(pos, _)| *pos);
+        assert_eq!(tree_chunks.len(), bytecode_chunks.len(), "tick {tick}");
+        for ((pos, a), (_, b)) in tree_chunks.iter().zip(&bytecode_chunks) {
+            assert!(a.cells() == b.cells(), "tick {tick}, chunk {pos:?}");
+            assert_eq!(a.particles, b.particles, "tick {tick}, chunk {pos:?}");
+        }
+    }
+}
+
+#[test]
+fn test_symmetry_expanded() {
+    let rule = CellRule::symmetry_x(CellRule::SwapWith {
+        pos: RelativePos::down_left(),
+    });
+    let program = RuleProgram::compile(&rule);
+
+    let swaps: Vec<_> = program
+        .ops
+        .iter()
+        .filter_map(|op| match op {
+            RuleOp::SwapWith { pos } => Some(*pos),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        swaps,
+        [
+            RelativePos::down_left(),
+            RelativePos::down_right(),
+            RelativePos::down_right(),
+            RelativePos::down_left(),
+        ]
+    );
+    assert!(!program.ops.iter().any(|op| matches!(op, RuleOp::Jump(_))));
+
+    assert_eq!(
+        RuleProgram::compile(&CellRule::Idle).ops[0],
+        RuleOp::Return(true)
+    );
+}
+
+#[test]
+fn test_bytecode_matches_tree_default_cells() {
+    let template = default_cells();
+    let meta = |label: &str| template.get_cell_meta_by_label(label).unwrap();
+    let chunk = CHUNK_SIZE as i32;
+
+    // sand and water falling on the wood floor set on fire, wet sand, steam and geyser splashes
+    // on the way
+    assert_evaluators_match(
+        &template,
+        |world| {
+            let rect = |world: &mut WorldState, min: (i32, i32), max: (i32, i32), label| {
+                gen_rect(
+                    world,
+                    &template,
+                    GlobalCellPos::new(min.0, min.1),
+                    GlobalCellPos::new(max.0, max.1),
+                    meta(label),
+                )
+                .unwrap();
+            };
+            rect(world, (-chunk, 0), (chunk * 2, 2), CELL_STONE_LABEL);
+            rect(world, (-chunk / 2, 2), (chunk, 6), CELL_WOOD_LABEL);
+            rect(world, (chunk, 2), (chunk + 20, 10), CELL_OIL_LABEL);
+            rect(world, (-10, 6), (-6, 8), CELL_FIRE_LABEL);
+            rect(world, (30, 6), (34, 7), CELL_GEYSER_LABEL);
+            rect(world, (0, chunk / 2), (20, chunk), CELL_SAND_LABEL);
+            rect(world, (10, chunk), (40, chunk + 20), CELL_WATER_LABEL);
+        },
+        300,
+    );
+}
+
+#[test]
+fn test_bytecode_matches_tree_all_rules() {
+    // every kind of rule and condition, nested symmetries and lazily evaluated conditions
+    let template = parse_cells_template(
+        r#"(cells: [
+            (label: "Vacuum", color: Plain((0, 0, 0, 0)), replaceable_by_particles: true),
+            (
+                label: "Walker",
+                color: Plain((1, 1, 1, 255)),
+                rule: FirstSuccess([
+                    ApplyAndContinue(IncrementRegister(register: 0, pos: (x: 0, y: 0))),
+                    If(
+                        condition: Or([
+                            BinaryOp(
+                                op: Greater,
+                                a: Op(
+                                    op: Rem,
+                                    a: Register(pos: (x: 0, y: 0), register: 0),
+                                    b: Value(7),
+                                ),
+                                b: Value(5),
+                            ),
+                            Chance(numerator: 1, denominator: 5),
+                        ]),
+                        action: TryAll([
+                            SerRegisterRandomMasked(register: 1, mask: 15, pos: (x: 0, y: 0)),
+                            DecrementRegister(register: 2, pos: (x: 0, y: 0)),
+                        ]),
+                        else_action: None,
+                    ),
+                    SymmetryDiagonal(SymmetryX(SymmetryY(SwapWithIds(
+                        pos: (x: 1, y: 0),
+                        match_ids: ["Vacuum"],
+                    )))),
+                    MirrorXIf(
+                        condition: Not(RelativeCellNotIn(
+                            pos: (x: 1, y: 1),
+                            cell_id_list: ["Walker", "Seed"],
+                        )),
+                        rule: RandomPair((
+                            MoveRegister(
+                                source_register: 1,
+                                source_cell: (x: 0, y: 0),
+                                target_register: 3,
+                                target_cell: (x: 1, y: 1),
+                            ),
+                            SetRegisterExpr(
+                                register: 3,
+                                value: Op(
+                                    op: Max,
+                                    a: Register(pos: (x: 1, y: 0), register: 1),
+                                    b: Value(3),
+                                ),
+                                pos: (x: 1, y: 1),
+                            ),
+                        )),
+                    ),
+                ]),
+            ),
+            (
+                label: "Seed",
+                color: Plain((2, 2, 2, 255)),
+                rule: SymmetryY(MirrorDiagonalIf(
+                    condition: And([
+                        RelativeCell(pos: (x: 0, y: 1), cell_id: "Vacuum"),
+                        RelativeCellNot(pos: (x: 1, y: 0), cell_id: "Seed"),
+                        Temperature(pos: (x: 0, y: 0), op: LessEq, value: 100),
+                    ]),
+                    rule: Chance(
+                        numerator: 1,
+                        denominator: 3,
+                        rule: FirstSuccess([
+                            If(
+                                condition: RelativeCellIn(
+                                    pos: (x: 0, y: -1),
+                                    cell_id_list: ["Walker"],
+                                ),
+                                action: TransformCell(pos: (x: 0, y: 1), cell_id: "Walker"),
+                                else_action: Some(InitCell(pos: (x: 0, y: 1), cell_id: "Seed")),
+                            ),
+                            SetRegister(register: 0, value: 1, pos: (x: 0, y: 0)),
+                        ]),
+                    ),
+                )),
+            ),
+            (
+                label: "Cannon",
+                color: Plain((3, 3, 3, 255)),
+                rule: SymmetryX(SymmetryDiagonal(If(
+                    condition: RelativeCell(pos: (x: 1, y: 1), cell_id: "Walker"),
+                    action: LaunchParticle(pos: (x: 1, y: 1), vel: (x: 20, y: 40), spread: 10),
+                    else_action: None,
+                ))),
+            ),
+        ])"#,
+    )
+    .unwrap();
+    let walker = template.get_cell_meta_by_label("Walker").unwrap();
+    let seed = template.get_cell_meta_by_label("Seed").unwrap();
+    let cannon = template.get_cell_meta_by_label("Cannon").unwrap();
+
+    for cell in &template.cells {
+        assert!(cell.program.ops.len() <= RuleProgram::compiled_len_bound(&cell.rule));
+    }
+
+    assert_evaluators_match(
+        &template,
+        |world| {
+            for i in 0..40 {
+                let pos = GlobalCellPos::new(i * 7 % 50 - 25, i * 3 % 30 - 15);
+                let meta = match i % 5 {
+                    0 => cannon,
+                    1 | 3 => seed,
+                    _ => walker,
+                };
+                world.set_cell(pos, meta.init(), &template).unwrap();
+            }
+        },
+        200,
+    );
+}
@@ -0,0 +1,117 @@
+use serde::{Deserialize, Serialize};
+
+/// Temperature of a cell in fixed point degrees Celsius, see [`Temperature::SCALE`].
+///
+/// Stored as integer so heat transfer is deterministic, templates use degrees as floats.
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(from = "f32", into = "f32")]
+pub struct Temperature(pub i32);
+
+impl Temperature {
+    /// Amount of units in one degree
+    pub const SCALE: i32 = 256;
+    /// Temperature of materials that don't specify it
+    pub const ROOM: Temperature = Temperature::from_degrees_int(20);
+    /// Lowest temperature allowed in templates, see [`validate_cells`](crate::validate_cells)
+    pub const MIN: Temperature = Temperature::from_degrees_int(-273);
+    /// Highest temperature allowed in templates
+    pub const MAX: Temperature = Temperature::from_degrees_int(1_000_000);
+
+    pub const fn from_degrees_int(degrees: i32) -> Self {
+        Self(degrees * Self::SCALE)
+    }
+
+    pub fn from_degrees(degrees: f32) -> Self {
+        Self((degrees * Self::SCALE as f32).round() as i32)
+    }
+
+    pub fn degrees(self) -> f32 {
+        self.0 as f32 / Self::SCALE as f32
+    }
+}
+
+impl From<f32> for Temperature {
+    fn from(degrees: f32) -> Self {
+        Self::from_degrees(degrees)
+    }
+}
+
+impl From<Temperature> for f32 {
+    fn from(temperature: Temperature) -> Self {
+        temperature.degrees()
+    }
+}
+
+/// Exchange heat between two cells, moving both temperatures towards their equilibrium. Heat is
+/// conserved: `a * capacity_a + b * capacity_b` stays the same up to rounding.
+///
+/// `conductivity` is the fraction of the way to the equilibrium (out of 256) done per call.
+/// Returns `true` if any of the temperatures changed.
+#[inline(always)]
+pub fn exchange_heat(
+    a: &mut Temperature,
+    capacity_a: u16,
+    b: &mut Temperature,
+    capacity_b: u16,
+    conductivity: u8,
+) -> bool {
+    if a == b || conductivity == 0 {
+        return false;
+    }
+
+    let capacity_a = capacity_a.max(1) as i64;
+    let capacity_b = capacity_b.max(1) as i64;
+    let diff = b.0 as i64 - a.0 as i64;
+
+    // heat moved from b to a, rounded towards zero so temperatures never overshoot. Product of
+    // extreme differences, capacities and conductivity doesn't fit i64, it's rare enough for i128
+    let factor = capacity_a * capacity_b * conductivity as i64;
+    let heat = match diff.checked_mul(factor) {
+        Some(product) => product / (capacity_a + capacity_b) / 256,
+        None => (diff as i128 * factor as i128 / (capacity_a + capacity_b) as i128 / 256) as i64,
+    };
+    let delta_a = heat / capacity_a;
+    let delta_b = heat / capacity_b;
+    if delta_a == 0 && delta_b == 0 {
+        return false;
+    }
+
+    // deltas may not fit i32, new temperatures are between the old ones
+    a.0 = (a.0 as i64 + delta_a) as i32;
+    b.0 = (b.0 as i64 - delta_b) as i32;
+
+    true
+}
+
+#[test]
+fn test_exchange_heat_conserves_energy() {
+    let mut a = Temperature::from_degrees_int(100);
+    let mut b = Temperature::from_degrees_int(0);
+    let energy = |a: Temperature, b: Temperature| a.0 as i64 * 10 + b.0 as i64 * 30;
+    let initial_energy = energy(a, b);
+
+    for _ in 0..1000 {
+        exchange_heat(&mut a, 10, &mut b, 30, 64);
+    }
+
+    assert!(a.degrees() - b.degrees() < 0.1);
+    assert!((a.degrees() - 25.0).abs() < 0.5);
+    // only rounding errors are lost, each at most one unit per cell per exchange
+    assert!((energy(a, b) - initial_energy).abs() < 40 * 1000);
+}
+
+#[test]
+fn test_exchange_heat_extreme_values() {
+    let mut a = Temperature::from_degrees_int(-40_000);
+    let mut b = Temperature::from_degrees_int(40_000);
+    assert!(exchange_heat(&mut a, u16::MAX, &mut b, u16::MAX, u8::MAX));
+    assert!(a.0 < 0 && a.0 > -40_000 * Temperature::SCALE);
+    assert!(b.0 > 0 && b.0 < 40_000 * Temperature::SCALE);
+
+    let mut a = Temperature(i32::MIN);
+    let mut b = Temperature(i32::MAX);
+    assert!(exchange_heat(&mut a, u16::MAX, &mut b, 1, u8::MAX));
+    assert!(a.0 > i32::MIN && a.0 <= b.0);
+}
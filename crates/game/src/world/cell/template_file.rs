@@ -0,0 +1,660 @@
+use crate::*;
+use eyre::{bail, ensure, eyre, WrapErr};
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Template shipped with the game, used by [`default_cells`].
+pub const DEFAULT_CELLS_TEMPLATE: &str = include_str!("../../../assets/cells.ron");
+
+/// Human-editable representation of [`CellsTemplate`].
+///
+/// Cells reference each other by label instead of [`CellId`]. Ids are assigned in the order cells
+/// are listed, so the first cell is the one new chunks are filled with.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CellsTemplateFile {
+    pub cells: Vec<CellMetaFile>,
+}
+
+/// Human-editable representation of [`CellMeta`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CellMetaFile {
+    pub label: String,
+    pub color: CellColor,
+    #[serde(default)]
+    pub rule: CellRule<String, RegisterRef>,
+    #[serde(default)]
+    pub count_age: bool,
+    #[serde(default = "default_particle_gravity")]
+    pub particle_gravity: (f32, f32),
+    /// Terminal velocity of particles in cells per second
+    #[serde(default = "default_particle_max_speed")]
+    pub particle_max_speed: f32,
+    /// Bounciness of particles, they stop at obstacles by default
+    #[serde(default)]
+    pub particle_restitution: f32,
+    /// Speed loss of particles sliding along obstacles, they stop by default
+    #[serde(default = "default_particle_friction")]
+    pub particle_friction: f32,
+    #[serde(default)]
+    pub particle_splash_chance: f32,
+    #[serde(default)]
+    pub particle_splash_count: u8,
+    /// Lifetime of particles in ticks, 0 for unlimited
+    #[serde(default)]
+    pub particle_lifetime: u32,
+    #[serde(default)]
+    pub replaceable_by_particles: bool,
+    /// Named registers, indices are assigned in the listed order starting from 0
+    #[serde(default)]
+    pub registers: Vec<RegisterFile>,
+    /// Initial values of registers by register index or name. Missing registers are set to 0.
+    #[serde(default)]
+    pub initial_register_values: BTreeMap<RegisterRef, u32>,
+    /// Initial temperature in degrees
+    #[serde(default = "default_initial_temperature")]
+    pub initial_temperature: Temperature,
+    #[serde(default = "default_heat_capacity")]
+    pub heat_capacity: u16,
+    /// Heat transfer speed (out of 256), cells don't exchange heat by default
+    #[serde(default)]
+    pub thermal_conductivity: u8,
+    #[serde(default)]
+    pub movement: CellMovement,
+    #[serde(default)]
+    pub density: i16,
+}
+
+/// Declaration of a named register, see [`RegisterMeta`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RegisterFile {
+    pub name: String,
+    pub ty: RegisterType,
+}
+
+/// Register referenced in template files: by index or by name. Names are looked up in the
+/// registers of the cell the rule belongs to, [`CELL_REGISTER_AGE_NAME`] is available to all cells.
+/// This holds for registers of other cells too, [`validate_cells`] warns if the cell there may be
+/// of another material.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RegisterRef {
+    Index(u8),
+    Name(String),
+}
+
+fn default_particle_gravity() -> (f32, f32) {
+    (0.0, -10.0)
+}
+
+fn default_particle_max_speed() -> f32 {
+    500.0
+}
+
+fn default_particle_friction() -> f32 {
+    1.0
+}
+
+fn default_initial_temperature() -> Temperature {
+    Temperature::ROOM
+}
+
+fn default_heat_capacity() -> u16 {
+    100
+}
+
+/// Parse cells template from RON source. See [`CellsTemplateFile`] for the format.
+pub fn parse_cells_template(source: &str) -> eyre::Result<CellsTemplate> {
+    let file: CellsTemplateFile =
+        ron::from_str(source).wrap_err("Failed to parse cells template")?;
+
+    file.build()
+}
+
+/// Load cells template from RON file. See [`CellsTemplateFile`] for the format.
+pub fn load_cells_template(path: impl AsRef<Path>) -> eyre::Result<CellsTemplate> {
+    let path = path.as_ref();
+    let source = std::fs::read_to_string(path)
+        .wrap_err_with(|| format!("Failed to read cells template {}", path.display()))?;
+
+    parse_cells_template(&source)
+        .wrap_err_with(|| format!("Invalid cells template {}", path.display()))
+}
+
+impl CellsTemplateFile {
+    pub fn build(&self) -> eyre::Result<CellsTemplate> {
+        let mut builder = CellTemplateBuilder::new();
+
+        for cell in &self.cells {
+            if builder.id_by_label.contains_key(&cell.label) {
+                bail!("Cell {:?} is defined more than once", cell.label);
+            }
+            builder.ensure_id_by_label(cell.label.as_str());
+        }
+
+        for cell in &self.cells {
+            let cell_meta = cell
+                .resolve(&builder)
+                .wrap_err_with(|| format!("Invalid cell {:?}", cell.label))?;
+            builder.add_cell(cell_meta);
+        }
+
+        builder.build()
+    }
+}
+
+impl CellMetaFile {
+    fn resolve(&self, builder: &CellTemplateBuilder) -> eyre::Result<CellMeta> {
+        let registers = self.resolve_registers()?;
+        let resolver = LabelResolver {
+            builder,
+            registers: &registers,
+        };
+
+        let mut initial_register_values = [0; CELL_REGISTERS_COUNT];
+        for (register, &value) in &self.initial_register_values {
+            let index = resolver.resolve_register(register, "initial_register_values")?;
+            initial_register_values[index as usize] = value;
+        }
+
+        let rule = resolver.resolve_rule(&self.rule, "rule")?;
+
+        Ok(CellMeta {
+            id: Default::default(),
+            color: self.color,
+            label: self.label.clone(),
+            // compiled by `CellTemplateBuilder::build` once the rules are validated
+            program: RuleProgram::default(),
+            rule,
+            count_age: self.count_age,
+            age_is_read: false,
+            particle_gravity: Vec2::new(self.particle_gravity.0, self.particle_gravity.1),
+            particle_max_speed: self.particle_max_speed,
+            particle_restitution: self.particle_restitution,
+            particle_friction: self.particle_friction,
+            particle_splash_chance: self.particle_splash_chance,
+            particle_splash_count: self.particle_splash_count,
+            particle_lifetime: self.particle_lifetime,
+            replaceable_by_particles: self.replaceable_by_particles,
+            initial_register_values,
+            registers,
+            initial_temperature: self.initial_temperature,
+            heat_capacity: self.heat_capacity,
+            thermal_conductivity: self.thermal_conductivity,
+            movement: self.movement,
+            density: self.density,
+        })
+    }
+
+    fn resolve_registers(&self) -> eyre::Result<Vec<RegisterMeta>> {
+        if self.registers.len() > CELL_FREE_REGISTERS_COUNT {
+            bail!(
+                "Too many registers: {}, at most {CELL_FREE_REGISTERS_COUNT} are available",
+                self.registers.len()
+            );
+        }
+
+        let mut registers: Vec<RegisterMeta> = Vec::with_capacity(self.registers.len());
+        for (index, register) in self.registers.iter().enumerate() {
+            if register.name == CELL_REGISTER_AGE_NAME
+                || registers.iter().any(|other| other.name == register.name)
+            {
+                bail!("Register {:?} is defined more than once", register.name);
+            }
+            if let RegisterType::Uint(bits) = register.ty {
+                ensure!(
+                    (1..=32).contains(&bits),
+                    "Register {:?} must have 1 to 32 bits, got {bits}",
+                    register.name
+                );
+            }
+
+            registers.push(RegisterMeta {
+                name: register.name.clone(),
+                index: index as u8,
+                ty: register.ty,
+            });
+        }
+
+        Ok(registers)
+    }
+}
+
+/// Replaces cell labels with ids and register names with indices, tracking path in the rule tree
+/// for error messages.
+struct LabelResolver<'a> {
+    builder: &'a CellTemplateBuilder,
+    /// Named registers of the cell the rule belongs to
+    registers: &'a [RegisterMeta],
+}
+
+impl LabelResolver<'_> {
+    fn resolve_register(&self, register: &RegisterRef, path: &str) -> eyre::Result<u8> {
+        match register {
+            // bounds are checked by `validate_cells`
+            RegisterRef::Index(index) => Ok(*index),
+            RegisterRef::Name(name) if name == CELL_REGISTER_AGE_NAME => {
+                Ok(CELL_REGISTER_AGE as u8)
+            }
+            RegisterRef::Name(name) => self
+                .registers
+                .iter()
+                .find(|register| register.name == *name)
+                .map(|register| register.index)
+                .ok_or_else(|| eyre!("Unknown register {name:?} at {path}")),
+        }
+    }
+
+    fn resolve_arg(
+        &self,
+        arg: &ConditionArg<RegisterRef>,
+        path: &str,
+    ) -> eyre::Result<ConditionArg> {
+        Ok(match arg {
+            ConditionArg::Register { pos, register } => ConditionArg::Register {
+                pos: *pos,
+                register: self.resolve_register(register, &format!("{path}.Register.register"))?,
+            },
+            ConditionArg::Value(value) => ConditionArg::Value(*value),
+            ConditionArg::Op { op, a, b } => ConditionArg::op(
+                *op,
+                self.resolve_arg(a, &format!("{path}.Op.a"))?,
+                self.resolve_arg(b, &format!("{path}.Op.b"))?,
+            ),
+        })
+    }
+
+    fn resolve_id(&self, label: &str, path: &str) -> eyre::Result<CellId> {
+        self.builder
+            .id_by_label
+            .get(label)
+            .copied()
+            .ok_or_else(|| eyre!("Unknown cell label {label:?} at {path}"))
+    }
+
+    fn resolve_ids(&self, labels: &[String], path: &str) -> eyre::Result<Vec<CellId>> {
+        labels
+            .iter()
+            .enumerate()
+            .map(|(i, label)| self.resolve_id(label, &format!("{path}[{i}]")))
+            .collect()
+    }
+
+    fn resolve_rules(
+        &self,
+        rules: &[CellRule<String, RegisterRef>],
+        path: &str,
+    ) -> eyre::Result<Vec<CellRule>> {
+        rules
+            .iter()
+            .enumerate()
+            .map(|(i, rule)| self.resolve_rule(rule, &format!("{path}[{i}]")))
+            .collect()
+    }
+
+    fn resolve_boxed_rule(
+        &self,
+        rule: &CellRule<String, RegisterRef>,
+        path: &str,
+    ) -> eyre::Result<Box<CellRule>> {
+        self.resolve_rule(rule, path).map(Box::new)
+    }
+
+    fn resolve_rule(
+        &self,
+        rule: &CellRule<String, RegisterRef>,
+        path: &str,
+    ) -> eyre::Result<CellRule> {
+        Ok(match rule {
+            CellRule::Idle => CellRule::Idle,
+            CellRule::If {
+                condition,
+                action,
+                else_action,
+            } => CellRule::If {
+                condition: self.resolve_condition(condition, &format!("{path}.If.condition"))?,
+                action: self.resolve_boxed_rule(action, &format!("{path}.If.action"))?,
+                else_action: else_action
+                    .as_ref()
+                    .map(|rule| self.resolve_boxed_rule(rule, &format!("{path}.If.else_action")))
+                    .transpose()?,
+            },
+            CellRule::SwapWithIds { pos, match_ids } => CellRule::SwapWithIds {
+                pos: *pos,
+                match_ids: self.resolve_ids(match_ids, &format!("{path}.SwapWithIds.match_ids"))?,
+            },
+            CellRule::Chance {
+                numerator,
+                denominator,
+                rule,
+            } => CellRule::Chance {
+                numerator: *numerator,
+                denominator: *denominator,
+                rule: self.resolve_boxed_rule(rule, &format!("{path}.Chance.rule"))?,
+            },
+            CellRule::ApplyAndContinue(rule) => CellRule::ApplyAndContinue(
+                self.resolve_boxed_rule(rule, &format!("{path}.ApplyAndContinue"))?,
+            ),
+            CellRule::FirstSuccess(rules) => {
+                CellRule::FirstSuccess(self.resolve_rules(rules, &format!("{path}.FirstSuccess"))?)
+            }
+            CellRule::RandomPair(pair) => {
+                let (a, b) = pair.as_ref();
+                CellRule::random_pair(
+                    self.resolve_rule(a, &format!("{path}.RandomPair.0"))?,
+                    self.resolve_rule(b, &format!("{path}.RandomPair.1"))?,
+                )
+            }
+            CellRule::SymmetryX(rule) => {
+                CellRule::SymmetryX(self.resolve_boxed_rule(rule, &format!("{path}.SymmetryX"))?)
+            }
+            CellRule::SymmetryY(rule) => {
+                CellRule::SymmetryY(self.resolve_boxed_rule(rule, &format!("{path}.SymmetryY"))?)
+            }
+            CellRule::SymmetryDiagonal(rule) => CellRule::SymmetryDiagonal(
+                self.resolve_boxed_rule(rule, &format!("{path}.SymmetryDiagonal"))?,
+            ),
+            CellRule::MirrorXIf { condition, rule } => CellRule::MirrorXIf {
+                condition: self
+                    .resolve_condition(condition, &format!("{path}.MirrorXIf.condition"))?,
+                rule: self.resolve_boxed_rule(rule, &format!("{path}.MirrorXIf.rule"))?,
+            },
+            CellRule::MirrorYIf { condition, rule } => CellRule::MirrorYIf {
+                condition: self
+                    .resolve_condition(condition, &format!("{path}.MirrorYIf.condition"))?,
+                rule: self.resolve_boxed_rule(rule, &format!("{path}.MirrorYIf.rule"))?,
+            },
+            CellRule::MirrorDiagonalIf { condition, rule } => CellRule::MirrorDiagonalIf {
+                condition: self
+                    .resolve_condition(condition, &format!("{path}.MirrorDiagonalIf.condition"))?,
+                rule: self.resolve_boxed_rule(rule, &format!("{path}.MirrorDiagonalIf.rule"))?,
+            },
+            CellRule::TryAll(rules) => {
+                CellRule::TryAll(self.resolve_rules(rules, &format!("{path}.TryAll"))?)
+            }
+            CellRule::InitCell { pos, cell_id } => CellRule::InitCell {
+                pos: *pos,
+                cell_id: self.resolve_id(cell_id, &format!("{path}.InitCell.cell_id"))?,
+            },
+            CellRule::TransformCell { pos, cell_id } => CellRule::TransformCell {
+                pos: *pos,
+                cell_id: self.resolve_id(cell_id, &format!("{path}.TransformCell.cell_id"))?,
+            },
+            CellRule::SwapWith { pos } => CellRule::SwapWith { pos: *pos },
+            CellRule::LaunchParticle { pos, vel, spread } => CellRule::LaunchParticle {
+                pos: *pos,
+                vel: *vel,
+                spread: *spread,
+            },
+            CellRule::IncrementRegister { register, pos } => CellRule::IncrementRegister {
+                register: self
+                    .resolve_register(register, &format!("{path}.IncrementRegister.register"))?,
+                pos: *pos,
+            },
+            CellRule::DecrementRegister { register, pos } => CellRule::DecrementRegister {
+                register: self
+                    .resolve_register(register, &format!("{path}.DecrementRegister.register"))?,
+                pos: *pos,
+            },
+            CellRule::SetRegister {
+                register,
+                value,
+                pos,
+            } => CellRule::SetRegister {
+                register: self
+                    .resolve_register(register, &format!("{path}.SetRegister.register"))?,
+                value: *value,
+                pos: *pos,
+            },
+            CellRule::SerRegisterRandomMasked {
+                register,
+                mask,
+                pos,
+            } => CellRule::SerRegisterRandomMasked {
+                register: self.resolve_register(
+                    register,
+                    &format!("{path}.SerRegisterRandomMasked.register"),
+                )?,
+                mask: *mask,
+                pos: *pos,
+            },
+            CellRule::SetRegisterExpr {
+                register,
+                value,
+                pos,
+            } => CellRule::SetRegisterExpr {
+                register: self
+                    .resolve_register(register, &format!("{path}.SetRegisterExpr.register"))?,
+                value: self.resolve_arg(value, &format!("{path}.SetRegisterExpr.value"))?,
+                pos: *pos,
+            },
+            CellRule::MoveRegister {
+                source_register,
+                source_cell,
+                target_register,
+                target_cell,
+            } => CellRule::MoveRegister {
+                source_register: self.resolve_register(
+                    source_register,
+                    &format!("{path}.MoveRegister.source_register"),
+                )?,
+                source_cell: *source_cell,
+                target_register: self.resolve_register(
+                    target_register,
+                    &format!("{path}.MoveRegister.target_register"),
+                )?,
+                target_cell: *target_cell,
+            },
+        })
+    }
+
+    fn resolve_conditions(
+        &self,
+        conditions: &[RuleCondition<String, RegisterRef>],
+        path: &str,
+    ) -> eyre::Result<Vec<RuleCondition>> {
+        conditions
+            .iter()
+            .enumerate()
+            .map(|(i, condition)| self.resolve_condition(condition, &format!("{path}[{i}]")))
+            .collect()
+    }
+
+    fn resolve_condition(
+        &self,
+        condition: &RuleCondition<String, RegisterRef>,
+        path: &str,
+    ) -> eyre::Result<RuleCondition> {
+        Ok(match condition {
+            RuleCondition::And(conditions) => {
+                RuleCondition::And(self.resolve_conditions(conditions, &format!("{path}.And"))?)
+            }
+            RuleCondition::Or(conditions) => {
+                RuleCondition::Or(self.resolve_conditions(conditions, &format!("{path}.Or"))?)
+            }
+            RuleCondition::Not(condition) => RuleCondition::Not(Box::new(
+                self.resolve_condition(condition, &format!("{path}.Not"))?,
+            )),
+            RuleCondition::RelativeCell { pos, cell_id } => RuleCondition::RelativeCell {
+                pos: *pos,
+                cell_id: self.resolve_id(cell_id, &format!("{path}.RelativeCell.cell_id"))?,
+            },
+            RuleCondition::RelativeCellNot { pos, cell_id } => RuleCondition::RelativeCellNot {
+                pos: *pos,
+                cell_id: self.resolve_id(cell_id, &format!("{path}.RelativeCellNot.cell_id"))?,
+            },
+            RuleCondition::RelativeCellIn { pos, cell_id_list } => RuleCondition::RelativeCellIn {
+                pos: *pos,
+                cell_id_list: self
+                    .resolve_ids(cell_id_list, &format!("{path}.RelativeCellIn.cell_id_list"))?,
+            },
+            RuleCondition::RelativeCellNotIn { pos, cell_id_list } => {
+                RuleCondition::RelativeCellNotIn {
+                    pos: *pos,
+                    cell_id_list: self.resolve_ids(
+                        cell_id_list,
+                        &format!("{path}.RelativeCellNotIn.cell_id_list"),
+                    )?,
+                }
+            }
+            RuleCondition::BinaryOp { op, a, b } => RuleCondition::BinaryOp {
+                op: *op,
+                a: self.resolve_arg(a, &format!("{path}.BinaryOp.a"))?,
+                b: self.resolve_arg(b, &format!("{path}.BinaryOp.b"))?,
+            },
+            RuleCondition::Chance {
+                numerator,
+                denominator,
+            } => RuleCondition::chance(*numerator, *denominator),
+            RuleCondition::Temperature { pos, op, value } => RuleCondition::Temperature {
+                pos: *pos,
+                op: *op,
+                value: *value,
+            },
+            RuleCondition::Always => RuleCondition::Always,
+        })
+    }
+}
+
+#[test]
+fn test_default_cells_template_is_valid() {
+    let template = parse_cells_template(DEFAULT_CELLS_TEMPLATE).unwrap();
+
+    assert_eq!(template.cells[0].label, CELL_VACUUM_LABEL);
+    for (id, cell) in template.cells.iter().enumerate() {
+        assert_eq!(cell.id, id as CellId);
+    }
+}
+
+#[test]
+fn test_unknown_label_error_has_rule_path() {
+    let source = r#"(
+        cells: [
+            (label: "Vacuum", color: Plain((0, 0, 0, 0))),
+            (
+                label: "Sand",
+                color: Plain((255, 255, 0, 255)),
+                rule: FirstSuccess([
+                    Idle,
+                    SwapWithIds(pos: (x: 0, y: -1), match_ids: ["Vacuum", "Watr"]),
+                ]),
+            ),
+        ],
+    )"#;
+
+    let error = format!("{:?}", parse_cells_template(source).unwrap_err());
+
+    assert!(error.contains("\"Sand\""), "{error}");
+    assert!(
+        error.contains("rule.FirstSuccess[1].SwapWithIds.match_ids[1]"),
+        "{error}"
+    );
+    assert!(error.contains("\"Watr\""), "{error}");
+}
+
+#[test]
+fn test_invalid_rule_is_not_compiled() {
+    let source = r#"(
+        cells: [
+            (label: "Vacuum", color: Plain((0, 0, 0, 0))),
+            (
+                label: "Sand",
+                color: Plain((255, 255, 0, 255)),
+                rule: SymmetryX(SwapWith(pos: (x: -128, y: 0))),
+            ),
+        ],
+    )"#;
+
+    let error = format!("{:?}", parse_cells_template(source).unwrap_err());
+
+    assert!(
+        error.contains("Position (-128, 0) is out of range"),
+        "{error}"
+    );
+}
+
+#[test]
+fn test_named_registers() {
+    let template = |registers: &str, rule: &str| {
+        format!(
+            r#"(cells: [
+                (label: "Vacuum", color: Plain((0, 0, 0, 0))),
+                (
+                    label: "Tank",
+                    color: Plain((1, 1, 1, 255)),
+                    registers: [{registers}],
+                    initial_register_values: {{"amount": 5}},
+                    rule: {rule},
+                ),
+            ])"#
+        )
+    };
+    let error = |registers: &str, rule: &str| {
+        format!(
+            "{:?}",
+            parse_cells_template(&template(registers, rule)).unwrap_err()
+        )
+    };
+    let registers = r#"(name: "flag", ty: Bool), (name: "amount", ty: Uint(4))"#;
+
+    let cells = parse_cells_template(&template(
+        registers,
+        r#"If(
+            condition: BinaryOp(op: Less, a: Register(pos: (x: 0, y: 0), register: "age"), b: Value(3)),
+            action: SetRegister(register: "amount", value: 15, pos: (x: 0, y: 0)),
+            else_action: None,
+        )"#,
+    ))
+    .unwrap();
+    let tank = cells.get_cell_meta_by_label("Tank").unwrap();
+    assert_eq!(tank.get_register(1).unwrap().name, "amount");
+    assert_eq!(tank.initial_register_values[1], 5);
+    assert_eq!(
+        tank.rule,
+        CellRule::if_then(
+            RuleCondition::age_less(3),
+            CellRule::SetRegister {
+                register: 1,
+                value: 15,
+                pos: RelativePos::self_pos(),
+            }
+        )
+    );
+
+    let unknown = error(
+        registers,
+        r#"TryAll([Idle, IncrementRegister(register: "amout", pos: (x: 0, y: 0))])"#,
+    );
+    assert!(unknown.contains("\"amout\""), "{unknown}");
+    assert!(
+        unknown.contains("rule.TryAll[1].IncrementRegister.register"),
+        "{unknown}"
+    );
+
+    let age = error(
+        registers,
+        r#"SetRegister(register: "age", value: 0, pos: (x: 0, y: 0))"#,
+    );
+    assert!(age.contains("age register"), "{age}");
+    let system = error(
+        registers,
+        r#"SetRegister(register: 13, value: 0, pos: (x: 1, y: 0))"#,
+    );
+    assert!(system.contains("system register"), "{system}");
+
+    let too_big = error(
+        registers,
+        r#"SetRegister(register: "amount", value: 16, pos: (x: 0, y: 0))"#,
+    );
+    assert!(
+        too_big.contains("doesn't fit register \"amount\""),
+        "{too_big}"
+    );
+
+    let duplicate = error(
+        r#"(name: "amount", ty: Bool), (name: "amount", ty: Uint(4))"#,
+        "Idle",
+    );
+    assert!(duplicate.contains("defined more than once"), "{duplicate}");
+}
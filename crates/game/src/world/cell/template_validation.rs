@@ -0,0 +1,832 @@
+use crate::*;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DiagnosticSeverity {
+    /// Template can't be used, e.g. rule would panic at runtime
+    Error,
+    /// Template works, but probably not as intended
+    Warning,
+}
+
+/// Problem found by [`validate_cells`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TemplateDiagnostic {
+    pub severity: DiagnosticSeverity,
+    /// Label of the cell
+    pub cell: String,
+    /// Path in the cell meta, e.g. `rule.FirstSuccess[1].If.action`
+    pub path: String,
+    pub message: String,
+}
+
+impl TemplateDiagnostic {
+    pub fn is_error(&self) -> bool {
+        self.severity == DiagnosticSeverity::Error
+    }
+}
+
+impl fmt::Display for TemplateDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            DiagnosticSeverity::Error => "error",
+            DiagnosticSeverity::Warning => "warning",
+        };
+
+        write!(
+            f,
+            "{severity}: cell {:?} at {}: {}",
+            self.cell, self.path, self.message
+        )
+    }
+}
+
+/// Check rules and registers of the cells for mistakes that would panic or silently misbehave at
+/// runtime. Cell ids must match indices in `cells`.
+pub fn validate_cells(cells: &[CellMeta]) -> Vec<TemplateDiagnostic> {
+    let mut diagnostics = Vec::new();
+
+    for cell in cells {
+        let mut validator = Validator {
+            cells,
+            cell,
+            known_cells: Vec::new(),
+            diagnostics: &mut diagnostics,
+        };
+        validator.check_cell();
+    }
+
+    diagnostics
+}
+
+struct Validator<'a> {
+    cells: &'a [CellMeta],
+    cell: &'a CellMeta,
+    /// Cells known to be at the positions while checking a nested rule, e.g. because the rule is
+    /// applied only if [`RuleCondition::RelativeCell`] holds
+    known_cells: Vec<(RelativePos, CellId)>,
+    diagnostics: &'a mut Vec<TemplateDiagnostic>,
+}
+
+impl<'a> Validator<'a> {
+    fn report(&mut self, severity: DiagnosticSeverity, path: &str, message: String) {
+        self.diagnostics.push(TemplateDiagnostic {
+            severity,
+            cell: self.cell.label.clone(),
+            path: path.to_string(),
+            message,
+        });
+    }
+
+    fn error(&mut self, path: &str, message: String) {
+        self.report(DiagnosticSeverity::Error, path, message);
+    }
+
+    fn warning(&mut self, path: &str, message: String) {
+        self.report(DiagnosticSeverity::Warning, path, message);
+    }
+
+    fn check_cell(&mut self) {
+        let cell = self.cell;
+
+        for (index, &value) in cell.initial_register_values.iter().enumerate() {
+            if value != 0 {
+                let path = format!("initial_register_values[{index}]");
+                self.check_register_write(index as u8, RelativePos::self_pos(), Some(value), &path);
+            }
+        }
+
+        self.check_temperature(cell.initial_temperature, "initial_temperature");
+        self.check_particle_properties();
+        self.check_rule(&cell.rule, "rule");
+
+        let len = RuleProgram::compiled_len_bound(&cell.rule);
+        if len > RULE_PROGRAM_MAX_LEN {
+            self.error(
+                "rule",
+                format!(
+                    "Rule compiles to up to {len} instructions, at most {RULE_PROGRAM_MAX_LEN} \
+                     are allowed. Every symmetry rule quadruples and `RandomPair` doubles the \
+                     size of the rules nested in it"
+                ),
+            );
+        }
+    }
+
+    fn check_particle_properties(&mut self) {
+        let cell = self.cell;
+        let fractions = [
+            ("particle_restitution", cell.particle_restitution),
+            ("particle_friction", cell.particle_friction),
+            ("particle_splash_chance", cell.particle_splash_chance),
+        ];
+        for (path, value) in fractions {
+            if !(0.0..=1.0).contains(&value) {
+                self.error(path, format!("Value {value} is out of range 0..=1"));
+            }
+        }
+
+        if cell.particle_splash_chance > 0.0 && cell.particle_splash_count == 0 {
+            self.warning(
+                "particle_splash_count",
+                "Particles splash, but `particle_splash_count` is 0".to_string(),
+            );
+        }
+    }
+
+    fn check_pos(&mut self, pos: RelativePos, path: &str) {
+        // positions are mirrored by symmetry rules, so `i8::MIN` would overflow
+        let is_valid = |value: i8| value != i8::MIN && value.unsigned_abs() as usize <= CHUNK_SIZE;
+        if !is_valid(pos.x) || !is_valid(pos.y) {
+            self.error(
+                path,
+                format!(
+                    "Position ({}, {}) is out of range, at most {CHUNK_SIZE} cells in each \
+                     direction are allowed",
+                    pos.x, pos.y
+                ),
+            );
+        }
+    }
+
+    fn check_temperature(&mut self, temperature: Temperature, path: &str) {
+        if !(Temperature::MIN..=Temperature::MAX).contains(&temperature) {
+            self.error(
+                path,
+                format!(
+                    "Temperature {} is out of range {}..={} degrees",
+                    temperature.degrees(),
+                    Temperature::MIN.degrees(),
+                    Temperature::MAX.degrees()
+                ),
+            );
+        }
+    }
+
+    fn check_id(&mut self, id: CellId, path: &str) {
+        if id as usize >= self.cells.len() {
+            self.error(path, format!("Cell id {id} doesn't exist"));
+        }
+    }
+
+    fn check_ids(&mut self, ids: &[CellId], path: &str) {
+        for (i, &id) in ids.iter().enumerate() {
+            self.check_id(id, &format!("{path}[{i}]"));
+        }
+    }
+
+    fn check_register_read(&mut self, register: u8, pos: RelativePos, path: &str) -> bool {
+        if register as usize >= CELL_REGISTERS_COUNT {
+            self.error(path, format!("Register {register} is out of bounds"));
+            return false;
+        }
+
+        if pos != RelativePos::self_pos() {
+            self.register_owner(register, pos, path);
+        }
+
+        if register as usize == CELL_REGISTER_AGE
+            && pos == RelativePos::self_pos()
+            && !self.cell.count_age
+        {
+            self.warning(
+                path,
+                "Age register is read, but `count_age` is disabled for the cell".to_string(),
+            );
+        }
+
+        true
+    }
+
+    fn check_register_write(
+        &mut self,
+        register: u8,
+        pos: RelativePos,
+        value: Option<u32>,
+        path: &str,
+    ) {
+        if register as usize >= CELL_REGISTERS_COUNT {
+            self.error(path, format!("Register {register} is out of bounds"));
+            return;
+        }
+
+        let index = register as usize;
+        if index == CELL_REGISTER_AGE {
+            self.error(path, format!("Write to the age register {register}"));
+        } else if index == CELL_REGISTER_SYSTEM {
+            self.error(path, format!("Write to the system register {register}"));
+        }
+
+        let Some(owner) = self.register_owner(register, pos, path) else {
+            return;
+        };
+        if let (Some(value), Some(register)) = (value, owner.get_register(register)) {
+            if value > register.ty.max_value() {
+                self.error(
+                    path,
+                    format!(
+                        "Value {value} doesn't fit register {:?} of type {:?}",
+                        register.name, register.ty
+                    ),
+                );
+            }
+        }
+    }
+
+    fn known_cell_at(&self, pos: RelativePos) -> Option<CellId> {
+        self.known_cells
+            .iter()
+            .rev()
+            .find(|(known_pos, _)| *known_pos == pos)
+            .map(|&(_, id)| id)
+    }
+
+    /// Find the cell whose register is accessed at `pos`. Register names are resolved against the
+    /// registers of the cell the rule belongs to, so access to a cell of another material is
+    /// reported.
+    fn register_owner(
+        &mut self,
+        register: u8,
+        pos: RelativePos,
+        path: &str,
+    ) -> Option<&'a CellMeta> {
+        if pos == RelativePos::self_pos() {
+            return Some(self.cell);
+        }
+        // age and system registers are the same for all cells
+        if register as usize >= CELL_FREE_REGISTERS_COUNT {
+            return None;
+        }
+
+        let Some(owner) = self
+            .known_cell_at(pos)
+            .and_then(|id| self.cells.get(id as usize))
+        else {
+            self.warning(
+                path,
+                format!(
+                    "Register {register} of the cell at ({}, {}) is used, but the cell there may \
+                     be of any material. Register names refer to the registers of {:?}",
+                    pos.x, pos.y, self.cell.label
+                ),
+            );
+            return None;
+        };
+
+        match (
+            self.cell.get_register(register),
+            owner.get_register(register),
+        ) {
+            (_, None) => self.warning(
+                path,
+                format!(
+                    "Register {register} isn't declared by {:?} at ({}, {})",
+                    owner.label, pos.x, pos.y
+                ),
+            ),
+            (Some(own), Some(other)) if own.name != other.name => self.warning(
+                path,
+                format!(
+                    "Register {register} is {:?} of {:?}, but {:?} of {:?} at ({}, {})",
+                    own.name, self.cell.label, other.name, owner.label, pos.x, pos.y
+                ),
+            ),
+            _ => {}
+        }
+
+        Some(owner)
+    }
+
+    fn check_chance(&mut self, numerator: u32, denominator: u32, path: &str) {
+        if denominator == 0 {
+            self.error(path, "Chance denominator must be positive".to_string());
+        } else if numerator == 0 {
+            self.warning(path, "Chance never succeeds".to_string());
+        } else if numerator >= denominator {
+            self.warning(path, "Chance always succeeds".to_string());
+        }
+    }
+
+    fn check_rules(&mut self, rules: &[CellRule], path: &str) {
+        for (i, rule) in rules.iter().enumerate() {
+            self.check_rule(rule, &format!("{path}[{i}]"));
+        }
+    }
+
+    /// Positions in the rule are mirrored, cells known at the original positions don't apply
+    fn check_mirrored_rule(&mut self, rule: &CellRule, path: &str) {
+        let known_cells = std::mem::take(&mut self.known_cells);
+        self.check_rule(rule, path);
+        self.known_cells = known_cells;
+    }
+
+    fn check_rule(&mut self, rule: &CellRule, path: &str) {
+        match rule {
+            CellRule::Idle => {}
+            CellRule::If {
+                condition,
+                action,
+                else_action,
+            } => {
+                self.check_condition(condition, &format!("{path}.If.condition"));
+                let known_len = self.known_cells.len();
+                known_cells_of(condition, &mut self.known_cells);
+                self.check_rule(action, &format!("{path}.If.action"));
+                self.known_cells.truncate(known_len);
+                if let Some(else_action) = else_action {
+                    self.check_rule(else_action, &format!("{path}.If.else_action"));
+                }
+            }
+            CellRule::SwapWithIds { pos, match_ids } => {
+                self.check_pos(*pos, &format!("{path}.SwapWithIds.pos"));
+                self.check_ids(match_ids, &format!("{path}.SwapWithIds.match_ids"));
+            }
+            CellRule::Chance {
+                numerator,
+                denominator,
+                rule,
+            } => {
+                self.check_chance(*numerator, *denominator, &format!("{path}.Chance"));
+                self.check_rule(rule, &format!("{path}.Chance.rule"));
+            }
+            CellRule::ApplyAndContinue(rule) => {
+                self.check_rule(rule, &format!("{path}.ApplyAndContinue"));
+            }
+            CellRule::FirstSuccess(rules) => {
+                let path = format!("{path}.FirstSuccess");
+                self.check_rules(rules, &path);
+
+                if let Some(i) = rules.iter().position(always_succeeds) {
+                    if i + 1 < rules.len() {
+                        self.warning(
+                            &format!("{path}[{}]", i + 1),
+                            format!("Rule is unreachable, rule {i} always succeeds"),
+                        );
+                    }
+                }
+            }
+            CellRule::TryAll(rules) => {
+                let known_len = self.known_cells.len();
+                for (i, rule) in rules.iter().enumerate() {
+                    self.check_rule(rule, &format!("{path}.TryAll[{i}]"));
+                    match rule {
+                        // rules after it see the new cell
+                        CellRule::InitCell { pos, cell_id }
+                        | CellRule::TransformCell { pos, cell_id } => {
+                            self.known_cells.push((*pos, *cell_id));
+                        }
+                        CellRule::IncrementRegister { .. }
+                        | CellRule::DecrementRegister { .. }
+                        | CellRule::SetRegister { .. }
+                        | CellRule::SerRegisterRandomMasked { .. }
+                        | CellRule::SetRegisterExpr { .. }
+                        | CellRule::MoveRegister { .. } => {}
+                        // cells may have been moved
+                        _ => self.known_cells.truncate(known_len),
+                    }
+                }
+                self.known_cells.truncate(known_len);
+            }
+            CellRule::RandomPair(pair) => {
+                self.check_rule(&pair.0, &format!("{path}.RandomPair.0"));
+                self.check_rule(&pair.1, &format!("{path}.RandomPair.1"));
+            }
+            CellRule::SymmetryX(rule) => {
+                self.check_mirrored_rule(rule, &format!("{path}.SymmetryX"));
+            }
+            CellRule::SymmetryY(rule) => {
+                self.check_mirrored_rule(rule, &format!("{path}.SymmetryY"));
+            }
+            CellRule::SymmetryDiagonal(rule) => {
+                self.check_mirrored_rule(rule, &format!("{path}.SymmetryDiagonal"));
+            }
+            CellRule::MirrorXIf { condition, rule } => {
+                self.check_condition(condition, &format!("{path}.MirrorXIf.condition"));
+                self.check_mirrored_rule(rule, &format!("{path}.MirrorXIf.rule"));
+            }
+            CellRule::MirrorYIf { condition, rule } => {
+                self.check_condition(condition, &format!("{path}.MirrorYIf.condition"));
+                self.check_mirrored_rule(rule, &format!("{path}.MirrorYIf.rule"));
+            }
+            CellRule::MirrorDiagonalIf { condition, rule } => {
+                self.check_condition(condition, &format!("{path}.MirrorDiagonalIf.condition"));
+                self.check_mirrored_rule(rule, &format!("{path}.MirrorDiagonalIf.rule"));
+            }
+            CellRule::InitCell { pos, cell_id } => {
+                self.check_pos(*pos, &format!("{path}.InitCell.pos"));
+                self.check_id(*cell_id, &format!("{path}.InitCell.cell_id"));
+            }
+            CellRule::TransformCell { pos, cell_id } => {
+                self.check_pos(*pos, &format!("{path}.TransformCell.pos"));
+                self.check_id(*cell_id, &format!("{path}.TransformCell.cell_id"));
+            }
+            CellRule::SwapWith { pos } => self.check_pos(*pos, &format!("{path}.SwapWith.pos")),
+            CellRule::LaunchParticle { pos, .. } => {
+                self.check_pos(*pos, &format!("{path}.LaunchParticle.pos"))
+            }
+            CellRule::IncrementRegister { register, pos } => {
+                self.check_pos(*pos, &format!("{path}.IncrementRegister.pos"));
+                let path = format!("{path}.IncrementRegister.register");
+                self.check_register_write(*register, *pos, None, &path);
+            }
+            CellRule::DecrementRegister { register, pos } => {
+                self.check_pos(*pos, &format!("{path}.DecrementRegister.pos"));
+                let path = format!("{path}.DecrementRegister.register");
+                self.check_register_write(*register, *pos, None, &path);
+            }
+            CellRule::SetRegister {
+                register,
+                value,
+                pos,
+            } => {
+                self.check_pos(*pos, &format!("{path}.SetRegister.pos"));
+                let path = format!("{path}.SetRegister.register");
+                self.check_register_write(*register, *pos, Some(*value), &path);
+            }
+            CellRule::SerRegisterRandomMasked {
+                register,
+                mask,
+                pos,
+            } => {
+                self.check_pos(*pos, &format!("{path}.SerRegisterRandomMasked.pos"));
+                let path = format!("{path}.SerRegisterRandomMasked.register");
+                self.check_register_write(*register, *pos, Some(*mask), &path);
+            }
+            CellRule::SetRegisterExpr {
+                register,
+                value,
+                pos,
+            } => {
+                self.check_pos(*pos, &format!("{path}.SetRegisterExpr.pos"));
+                self.check_arg(value, &format!("{path}.SetRegisterExpr.value"));
+                let path = format!("{path}.SetRegisterExpr.register");
+                self.check_register_write(*register, *pos, None, &path);
+            }
+            CellRule::MoveRegister {
+                source_register,
+                source_cell,
+                target_register,
+                target_cell,
+            } => {
+                self.check_pos(*source_cell, &format!("{path}.MoveRegister.source_cell"));
+                self.check_pos(*target_cell, &format!("{path}.MoveRegister.target_cell"));
+                self.check_register_read(
+                    *source_register,
+                    *source_cell,
+                    &format!("{path}.MoveRegister.source_register"),
+                );
+                self.check_register_write(
+                    *target_register,
+                    *target_cell,
+                    None,
+                    &format!("{path}.MoveRegister.target_register"),
+                );
+            }
+        }
+    }
+
+    fn check_conditions(&mut self, conditions: &[RuleCondition], path: &str) {
+        for (i, condition) in conditions.iter().enumerate() {
+            self.check_condition(condition, &format!("{path}[{i}]"));
+        }
+    }
+
+    fn check_condition(&mut self, condition: &RuleCondition, path: &str) {
+        match condition {
+            RuleCondition::And(conditions) => {
+                self.check_conditions(conditions, &format!("{path}.And"));
+            }
+            RuleCondition::Or(conditions) => {
+                self.check_conditions(conditions, &format!("{path}.Or"));
+            }
+            RuleCondition::Not(condition) => {
+                self.check_condition(condition, &format!("{path}.Not"));
+            }
+            RuleCondition::RelativeCell { pos, cell_id } => {
+                self.check_pos(*pos, &format!("{path}.RelativeCell.pos"));
+                self.check_id(*cell_id, &format!("{path}.RelativeCell.cell_id"));
+            }
+            RuleCondition::RelativeCellNot { pos, cell_id } => {
+                self.check_pos(*pos, &format!("{path}.RelativeCellNot.pos"));
+                self.check_id(*cell_id, &format!("{path}.RelativeCellNot.cell_id"));
+            }
+            RuleCondition::RelativeCellIn { pos, cell_id_list } => {
+                self.check_pos(*pos, &format!("{path}.RelativeCellIn.pos"));
+                self.check_ids(cell_id_list, &format!("{path}.RelativeCellIn.cell_id_list"));
+            }
+            RuleCondition::RelativeCellNotIn { pos, cell_id_list } => {
+                self.check_pos(*pos, &format!("{path}.RelativeCellNotIn.pos"));
+                self.check_ids(
+                    cell_id_list,
+                    &format!("{path}.RelativeCellNotIn.cell_id_list"),
+                );
+            }
+            RuleCondition::BinaryOp { a, b, .. } => {
+                self.check_arg(a, &format!("{path}.BinaryOp.a"));
+                self.check_arg(b, &format!("{path}.BinaryOp.b"));
+            }
+            RuleCondition::Chance {
+                numerator,
+                denominator,
+            } => self.check_chance(*numerator, *denominator, &format!("{path}.Chance")),
+            RuleCondition::Temperature { pos, value, .. } => {
+                self.check_pos(*pos, &format!("{path}.Temperature.pos"));
+                self.check_temperature(*value, &format!("{path}.Temperature.value"));
+            }
+            RuleCondition::Always => {}
+        }
+    }
+
+    fn check_arg(&mut self, arg: &ConditionArg, path: &str) {
+        match arg {
+            ConditionArg::Register { pos, register } => {
+                self.check_pos(*pos, &format!("{path}.Register.pos"));
+                self.check_register_read(*register, *pos, &format!("{path}.Register.register"));
+            }
+            ConditionArg::Value(_) => {}
+            ConditionArg::Op { a, b, .. } => {
+                self.check_arg(a, &format!("{path}.Op.a"));
+                self.check_arg(b, &format!("{path}.Op.b"));
+            }
+        }
+    }
+}
+
+/// Collect cells that must be at their positions for `condition` to hold
+fn known_cells_of(condition: &RuleCondition, known_cells: &mut Vec<(RelativePos, CellId)>) {
+    match condition {
+        RuleCondition::RelativeCell { pos, cell_id } => known_cells.push((*pos, *cell_id)),
+        RuleCondition::And(conditions) => {
+            for condition in conditions {
+                known_cells_of(condition, known_cells);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Check if rule succeeds regardless of the cell surroundings, so rules after it in
+/// [`CellRule::FirstSuccess`] are never applied.
+fn always_succeeds(rule: &CellRule) -> bool {
+    match rule {
+        CellRule::Idle
+        | CellRule::TryAll(_)
+        | CellRule::InitCell { .. }
+        | CellRule::TransformCell { .. }
+        | CellRule::SwapWith { .. }
+        | CellRule::IncrementRegister { .. }
+        | CellRule::DecrementRegister { .. }
+        | CellRule::SetRegister { .. }
+        | CellRule::SerRegisterRandomMasked { .. }
+        | CellRule::SetRegisterExpr { .. }
+        | CellRule::MoveRegister { .. } => true,
+        CellRule::If {
+            condition,
+            action,
+            else_action,
+        } => {
+            if *condition == RuleCondition::Always {
+                always_succeeds(action)
+            } else {
+                always_succeeds(action)
+                    && else_action
+                        .as_ref()
+                        .is_some_and(|else_action| always_succeeds(else_action))
+            }
+        }
+        CellRule::FirstSuccess(rules) => rules.iter().any(always_succeeds),
+        CellRule::RandomPair(pair) => always_succeeds(&pair.0) || always_succeeds(&pair.1),
+        CellRule::SymmetryX(rule)
+        | CellRule::SymmetryY(rule)
+        | CellRule::SymmetryDiagonal(rule) => always_succeeds(rule),
+        CellRule::SwapWithIds { .. }
+        | CellRule::LaunchParticle { .. }
+        | CellRule::Chance { .. }
+        | CellRule::ApplyAndContinue(_)
+        | CellRule::MirrorXIf { .. }
+        | CellRule::MirrorYIf { .. }
+        | CellRule::MirrorDiagonalIf { .. } => false,
+    }
+}
+
+/// Validate `rule` as the rule of Stone in the default template, returns severities and paths
+#[cfg(test)]
+fn validate_stone_rule(rule: CellRule) -> Vec<(DiagnosticSeverity, String)> {
+    let mut cells = default_cells().cells;
+    let stone = cells
+        .iter_mut()
+        .find(|cell| cell.label == CELL_STONE_LABEL)
+        .unwrap();
+    stone.rule = rule;
+
+    validate_cells(&cells)
+        .into_iter()
+        .map(|diagnostic| {
+            assert_eq!(diagnostic.cell, CELL_STONE_LABEL);
+            (diagnostic.severity, diagnostic.path)
+        })
+        .collect()
+}
+
+#[test]
+fn test_default_cells_have_no_diagnostics() {
+    assert_eq!(validate_cells(&default_cells().cells), vec![]);
+}
+
+#[test]
+fn test_validation_errors() {
+    let diagnostics = validate_stone_rule(CellRule::TryAll(vec![
+        CellRule::if_then(
+            RuleCondition::RelativeCell {
+                pos: RelativePos::new(0, i8::MIN),
+                cell_id: 0,
+            },
+            CellRule::InitCell {
+                pos: RelativePos::self_pos(),
+                cell_id: 100,
+            },
+        ),
+        CellRule::chance(1, 0, CellRule::set_reg_value(CELL_REGISTER_SYSTEM as u8, 1)),
+        CellRule::SetRegisterExpr {
+            register: 0,
+            value: ConditionArg::op(
+                ArithmeticOp::Add,
+                ConditionArg::value(1),
+                ConditionArg::register(CELL_REGISTERS_COUNT as u8),
+            ),
+            pos: RelativePos::self_pos(),
+        },
+    ]));
+
+    let error = |path: &str| (DiagnosticSeverity::Error, path.to_string());
+    assert_eq!(
+        diagnostics,
+        vec![
+            error("rule.TryAll[0].If.condition.RelativeCell.pos"),
+            error("rule.TryAll[0].If.action.InitCell.cell_id"),
+            error("rule.TryAll[1].Chance"),
+            error("rule.TryAll[1].Chance.rule.SetRegister.register"),
+            error("rule.TryAll[2].SetRegisterExpr.value.Op.b.Register.register"),
+        ]
+    );
+}
+
+#[test]
+fn test_validation_warnings() {
+    let diagnostics = validate_stone_rule(CellRule::FirstSuccess(vec![
+        CellRule::if_then(
+            RuleCondition::age_less(10),
+            CellRule::chance(0, 10, CellRule::Idle),
+        ),
+        CellRule::symmetry_x(CellRule::SwapWith {
+            pos: RelativePos::new(1, 0),
+        }),
+        CellRule::Idle,
+    ]));
+
+    let warning = |path: &str| (DiagnosticSeverity::Warning, path.to_string());
+    assert_eq!(
+        diagnostics,
+        vec![
+            warning("rule.FirstSuccess[0].If.condition.BinaryOp.a.Register.register"),
+            warning("rule.FirstSuccess[0].If.action.Chance"),
+            warning("rule.FirstSuccess[2]"),
+        ]
+    );
+}
+
+#[test]
+fn test_registers_of_other_cells() {
+    let cells = default_cells();
+    let fire = cells.get_cell_meta_by_label(CELL_FIRE_LABEL).unwrap();
+    let sand = cells.get_cell_meta_by_label(CELL_SAND_LABEL).unwrap();
+    let fuel = fire
+        .registers
+        .iter()
+        .find(|register| register.name == "fuel")
+        .unwrap()
+        .index;
+    let above = RelativePos::new(0, 1);
+    let set_fuel = |value| CellRule::SetRegister {
+        register: fuel,
+        value,
+        pos: above,
+    };
+
+    let diagnostics = validate_stone_rule(CellRule::TryAll(vec![
+        // the cell above may be of any material
+        set_fuel(1),
+        CellRule::if_then(
+            RuleCondition::RelativeCell {
+                pos: above,
+                cell_id: fire.id,
+            },
+            CellRule::TryAll(vec![
+                // fuel of the fire is too small for the value
+                set_fuel(5),
+                // symmetry also writes to the cell below
+                CellRule::symmetry_y(set_fuel(1)),
+            ]),
+        ),
+        CellRule::TryAll(vec![
+            CellRule::InitCell {
+                pos: above,
+                cell_id: sand.id,
+            },
+            // sand has no registers
+            set_fuel(1),
+        ]),
+    ]));
+
+    let path = "rule.TryAll";
+    assert_eq!(
+        diagnostics,
+        vec![
+            (
+                DiagnosticSeverity::Warning,
+                format!("{path}[0].SetRegister.register")
+            ),
+            (
+                DiagnosticSeverity::Error,
+                format!("{path}[1].If.action.TryAll[0].SetRegister.register")
+            ),
+            (
+                DiagnosticSeverity::Warning,
+                format!("{path}[1].If.action.TryAll[1].SymmetryY.SetRegister.register")
+            ),
+            (
+                DiagnosticSeverity::Warning,
+                format!("{path}[2].TryAll[1].SetRegister.register")
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_temperature_range_validation() {
+    let mut cells = default_cells().cells;
+    cells[1].initial_temperature = Temperature::from_degrees(-300.0);
+    cells[1].rule = CellRule::if_then(
+        RuleCondition::temperature_less(Temperature::from_degrees(2e6)),
+        CellRule::Idle,
+    );
+
+    let diagnostics: Vec<_> = validate_cells(&cells)
+        .into_iter()
+        .map(|diagnostic| (diagnostic.severity, diagnostic.path))
+        .collect();
+    assert_eq!(
+        diagnostics,
+        vec![
+            (DiagnosticSeverity::Error, "initial_temperature".to_string()),
+            (
+                DiagnosticSeverity::Error,
+                "rule.If.condition.Temperature.value".to_string()
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_nested_symmetries_too_large() {
+    let mut rule = CellRule::SwapWith {
+        pos: RelativePos::new(1, 0),
+    };
+    for _ in 0..40 {
+        rule = CellRule::SymmetryX(Box::new(rule));
+    }
+
+    // rejected without compiling it, which would never finish
+    assert_eq!(
+        validate_stone_rule(rule),
+        vec![(DiagnosticSeverity::Error, "rule".to_string())]
+    );
+}
+
+#[test]
+fn test_particle_properties_validation() {
+    let mut cells = default_cells().cells;
+    let stone = &mut cells[1];
+    stone.particle_restitution = 1.5;
+    stone.particle_friction = f32::NAN;
+    stone.particle_splash_chance = 0.5;
+
+    let diagnostics: Vec<_> = validate_cells(&cells)
+        .into_iter()
+        .map(|diagnostic| (diagnostic.severity, diagnostic.path))
+        .collect();
+    assert_eq!(
+        diagnostics,
+        vec![
+            (
+                DiagnosticSeverity::Error,
+                "particle_restitution".to_string()
+            ),
+            (DiagnosticSeverity::Error, "particle_friction".to_string()),
+            (
+                DiagnosticSeverity::Warning,
+                "particle_splash_count".to_string()
+            ),
+        ]
+    );
+}
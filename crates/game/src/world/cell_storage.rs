@@ -0,0 +1,272 @@
+use crate::*;
+
+/// Registers kept in the side table of [`CellStorage`], all except [`CELL_REGISTER_SYSTEM`]
+const SIDE_REGISTERS_COUNT: usize = CELL_REGISTERS_COUNT - 1;
+
+/// Slot of a cell without registers in `Columns::register_slots`
+const NO_SLOT: u16 = 0;
+
+/// Cells of a chunk stored as struct-of-arrays. Ids, update and age ticks, temperatures and the
+/// system register (color and age bookkeeping) are dense, the rest of the registers live in a side table
+/// and only cells with non-zero registers occupy a slot there, so chunks of cells like vacuum or
+/// stone don't pay for registers at all.
+///
+/// Cells are indexed by [`CellPos::to_index`] and read or written as whole [`Cell`] values, the
+/// layout is invisible outside of this module.
+#[derive(Debug)]
+pub struct CellStorage {
+    /// Single allocation, so the chunk itself stays small
+    columns: Box<Columns>,
+}
+
+#[derive(Debug)]
+struct Columns {
+    ids: [CellId; CHUNK_AREA],
+    last_updates: [u32; CHUNK_AREA],
+    age_ticks: [u32; CHUNK_AREA],
+    temperatures: [Temperature; CHUNK_AREA],
+    system_registers: [u32; CHUNK_AREA],
+    /// Index of the cell's registers in `registers` plus one, or [`NO_SLOT`] if they are all zero
+    register_slots: [u16; CHUNK_AREA],
+    registers: Vec<[u32; SIDE_REGISTERS_COUNT]>,
+    free_slots: Vec<u16>,
+}
+
+impl CellStorage {
+    /// Storage with all cells set to `cell`
+    pub fn new(cell: Cell) -> Self {
+        let mut storage = Self {
+            columns: Box::new(Columns {
+                ids: [cell.id; CHUNK_AREA],
+                last_updates: [cell.last_update; CHUNK_AREA],
+                age_ticks: [cell.age_tick; CHUNK_AREA],
+                temperatures: [cell.temperature; CHUNK_AREA],
+                system_registers: [cell.registers[CELL_REGISTER_SYSTEM]; CHUNK_AREA],
+                register_slots: [NO_SLOT; CHUNK_AREA],
+                registers: Vec::new(),
+                free_slots: Vec::new(),
+            }),
+        };
+
+        if side_registers(&cell).iter().any(|&value| value != 0) {
+            for index in 0..CHUNK_AREA {
+                storage.set(index, cell);
+            }
+        }
+
+        storage
+    }
+
+    #[inline(always)]
+    pub fn get(&self, index: usize) -> Cell {
+        let mut registers = [0; CELL_REGISTERS_COUNT];
+        let slot = self.columns.register_slots[index];
+        if slot != NO_SLOT {
+            registers[..SIDE_REGISTERS_COUNT]
+                .copy_from_slice(&self.columns.registers[slot as usize - 1]);
+        }
+        registers[CELL_REGISTER_SYSTEM] = self.columns.system_registers[index];
+
+        Cell {
+            id: self.columns.ids[index],
+            last_update: self.columns.last_updates[index],
+            age_tick: self.columns.age_ticks[index],
+            registers,
+            temperature: self.columns.temperatures[index],
+        }
+    }
+
+    /// Overwrite the cell, returns `false` if it's the same
+    #[inline(always)]
+    pub fn set(&mut self, index: usize, cell: Cell) -> bool {
+        let is_changed = self.columns.ids[index] != cell.id
+            || self.columns.last_updates[index] != cell.last_update
+            || self.columns.age_ticks[index] != cell.age_tick
+            || self.columns.temperatures[index] != cell.temperature
+            || self.columns.system_registers[index] != cell.registers[CELL_REGISTER_SYSTEM];
+
+        self.columns.ids[index] = cell.id;
+        self.columns.last_updates[index] = cell.last_update;
+        self.columns.age_ticks[index] = cell.age_tick;
+        self.columns.temperatures[index] = cell.temperature;
+        self.columns.system_registers[index] = cell.registers[CELL_REGISTER_SYSTEM];
+
+        self.set_side_registers(index, side_registers(&cell)) || is_changed
+    }
+
+    /// Store registers in the side table, slot is taken only while they are non-zero. Returns
+    /// `false` if registers are the same.
+    #[inline(always)]
+    fn set_side_registers(&mut self, index: usize, values: &[u32; SIDE_REGISTERS_COUNT]) -> bool {
+        let slot = self.columns.register_slots[index];
+        let is_zero = values.iter().all(|&value| value == 0);
+
+        if slot == NO_SLOT {
+            if is_zero {
+                return false;
+            }
+
+            let slot = match self.columns.free_slots.pop() {
+                Some(slot) => {
+                    self.columns.registers[slot as usize - 1] = *values;
+                    slot
+                }
+                None => {
+                    self.columns.registers.push(*values);
+                    self.columns.registers.len() as u16
+                }
+            };
+            self.columns.register_slots[index] = slot;
+            return true;
+        }
+
+        if is_zero {
+            // stored registers are never all zero, so it's a change
+            self.columns.register_slots[index] = NO_SLOT;
+            self.columns.free_slots.push(slot);
+            return true;
+        }
+
+        let stored = &mut self.columns.registers[slot as usize - 1];
+        if stored == values {
+            return false;
+        }
+        *stored = *values;
+        true
+    }
+
+    #[inline(always)]
+    pub fn swap(&mut self, a: usize, b: usize) {
+        self.columns.ids.swap(a, b);
+        self.columns.last_updates.swap(a, b);
+        self.columns.age_ticks.swap(a, b);
+        self.columns.temperatures.swap(a, b);
+        self.columns.system_registers.swap(a, b);
+        self.columns.register_slots.swap(a, b);
+    }
+
+    /// Ids of all cells, cheaper than reading whole cells when only the id is needed
+    #[inline(always)]
+    pub fn ids(&self) -> &[CellId; CHUNK_AREA] {
+        &self.columns.ids
+    }
+
+    #[inline(always)]
+    pub fn id(&self, index: usize) -> CellId {
+        self.columns.ids[index]
+    }
+
+    #[inline(always)]
+    pub fn temperature(&self, index: usize) -> Temperature {
+        self.columns.temperatures[index]
+    }
+
+    #[inline(always)]
+    pub fn set_temperature(&mut self, index: usize, temperature: Temperature) {
+        self.columns.temperatures[index] = temperature;
+    }
+
+    #[inline(always)]
+    pub fn register(&self, index: usize, register: usize) -> u32 {
+        if register == CELL_REGISTER_SYSTEM {
+            return self.columns.system_registers[index];
+        }
+
+        match self.columns.register_slots[index] {
+            NO_SLOT => 0,
+            slot => self.columns.registers[slot as usize - 1][register],
+        }
+    }
+
+    /// Replace the id of each cell with `map(id)`, cells mapped to `None` are replaced with
+    /// `empty_cell`.
+    pub fn remap_ids(&mut self, mut map: impl FnMut(CellId) -> Option<CellId>, empty_cell: Cell) {
+        for index in 0..CHUNK_AREA {
+            match map(self.columns.ids[index]) {
+                Some(id) => self.columns.ids[index] = id,
+                None => {
+                    self.set(index, empty_cell);
+                }
+            }
+        }
+    }
+
+    /// All cells in index order
+    pub fn iter(&self) -> impl Iterator<Item = Cell> + '_ {
+        (0..CHUNK_AREA).map(|index| self.get(index))
+    }
+
+    /// Amount of cells occupying a slot of the register side table
+    pub fn cells_with_registers(&self) -> usize {
+        self.columns.registers.len() - self.columns.free_slots.len()
+    }
+
+    /// Heap memory used by the storage in bytes
+    pub fn memory_usage(&self) -> usize {
+        let columns = &self.columns;
+        std::mem::size_of::<Columns>()
+            + columns.registers.capacity() * std::mem::size_of::<[u32; SIDE_REGISTERS_COUNT]>()
+            + columns.free_slots.capacity() * std::mem::size_of::<u16>()
+    }
+}
+
+/// Storages are equal if they contain the same cells, regardless of the side table layout
+impl PartialEq for CellStorage {
+    fn eq(&self, other: &Self) -> bool {
+        self.iter().eq(other.iter())
+    }
+}
+
+#[inline(always)]
+fn side_registers(cell: &Cell) -> &[u32; SIDE_REGISTERS_COUNT] {
+    const _: () = assert!(CELL_REGISTER_SYSTEM == SIDE_REGISTERS_COUNT);
+    // every cell fits into the u16 slot index
+    const _: () = assert!(CHUNK_AREA < u16::MAX as usize);
+
+    cell.registers[..SIDE_REGISTERS_COUNT].try_into().unwrap()
+}
+
+#[test]
+fn test_cell_storage_registers_side_table() {
+    let cells_template = default_cells();
+    let stone = cells_template
+        .get_cell_meta_by_label(CELL_STONE_LABEL)
+        .unwrap()
+        .init();
+
+    let mut storage = CellStorage::new(stone);
+    let empty_usage = storage.memory_usage();
+    assert_eq!(storage.cells_with_registers(), 0);
+    assert!(!storage.set(5, stone));
+
+    let mut counter = stone;
+    counter.registers[0] = 7;
+    counter.registers[CELL_REGISTER_AGE] = 3;
+    assert!(storage.set(5, counter));
+    assert!(!storage.set(5, counter));
+    assert_eq!(storage.cells_with_registers(), 1);
+    assert_eq!(storage.get(5), counter);
+    assert_eq!(storage.register(5, 0), 7);
+    assert_eq!(storage.register(6, 0), 0);
+
+    // registers move with the cell
+    storage.swap(5, 6);
+    assert_eq!(storage.get(6), counter);
+    assert_eq!(storage.get(5), stone);
+
+    // slot is freed when registers are reset and reused by the next cell
+    assert!(storage.set(6, stone));
+    assert_eq!(storage.cells_with_registers(), 0);
+    storage.set(100, counter);
+    assert_eq!(storage.cells_with_registers(), 1);
+    assert!(storage.memory_usage() > empty_usage);
+
+    // equality doesn't depend on slots
+    let mut other = CellStorage::new(stone);
+    other.set(1, counter);
+    other.set(100, counter);
+    other.set(1, stone);
+    assert!(storage == other);
+    other.set(100, stone);
+    assert!(storage != other);
+}
@@ -1,6 +1,6 @@
 use crate::*;
-use macroquad::prelude::*;
 use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 pub const CHUNK_SIZE_LOG_2: usize = 7;
 /// Size of the chunk's side. Must be a power of 2 for optimization reasons
@@ -64,56 +64,140 @@ impl ChunkPos {
 #[derive(Debug)]
 pub struct Chunk {
     pub particles: Vec<Particle>,
-    data: Box<[Cell; CHUNK_AREA]>,
+    data: CellStorage,
     next_random: Box<[u64; CHUNK_AREA]>,
-    texture: Option<Texture2D>,
-    image: Option<Image>,
-    should_update: bool,
-    should_redraw: bool,
+    /// Cells to process on the next update, see [`Chunk::update_rect`]
+    update_rect: DirtyRect,
+    /// Changes every time cells or particles are changed, see [`Chunk::generation`]
+    generation: u64,
+    /// Cells changed since `changed_since` generation, see [`Chunk::changed_rect_since`]
+    changed_rect: DirtyRect,
+    changed_since: u64,
 }
 
+/// Each chunk starts its generations from a new range, so a chunk replaced with another one at the
+/// same position (e.g. after loading) never reuses the generation of the old one.
+static NEXT_GENERATION_START: AtomicU64 = AtomicU64::new(0);
+
 impl Chunk {
-    pub fn new(cells_template: &CellsTemplate) -> Self {
+    /// Create chunk filled with the first cell of the template. `seed` is used to initialize
+    /// random values of cells, see [`chunk_seed`].
+    pub fn new(cells_template: &CellsTemplate, seed: u64) -> Self {
         let mut next_random = Box::new([0; CHUNK_AREA]);
-        for i in 0..CHUNK_AREA {
-            next_random[i] = ::rand::random();
+        for (i, value) in next_random.iter_mut().enumerate() {
+            // xorshift state must be non-zero
+            *value = mix_seed(seed, i as u64).max(1);
         }
 
+        let generation = NEXT_GENERATION_START.fetch_add(1 << 32, Ordering::Relaxed);
+
         Self {
             particles: Vec::new(),
-            texture: None,
-            image: None,
-            data: Box::new([cells_template.cells[0].init(); CHUNK_AREA]),
+            data: CellStorage::new(cells_template.cells[0].init()),
             next_random,
-            should_update: false,
-            should_redraw: false,
+            update_rect: DirtyRect::EMPTY,
+            generation,
+            changed_rect: DirtyRect::EMPTY,
+            changed_since: generation,
         }
     }
 
+    /// Some cells have to be processed on the next update
     #[inline(always)]
-    pub fn set_should_update(&mut self, should_update: bool) {
-        self.should_update = should_update;
+    pub fn should_update(&self) -> bool {
+        !self.update_rect.is_empty()
     }
 
+    /// Cells to process on the next update. Only cells near the changed ones are processed, so
+    /// the rectangle may extend beyond the chunk by [`CellsTemplate::update_radius`]. That part
+    /// is passed to the neighbor chunks when the chunk is updated.
     #[inline(always)]
-    pub fn should_update(&self) -> bool {
-        self.should_update
+    pub fn update_rect(&self) -> DirtyRect {
+        self.update_rect
+    }
+
+    #[inline(always)]
+    pub fn mark_for_update(&mut self, rect: DirtyRect) {
+        self.update_rect = self.update_rect.union(rect);
+    }
+
+    /// Process all cells of the chunk and of its neighbors on the next update
+    pub fn mark_all_for_update(&mut self) {
+        let size = CHUNK_SIZE as i16;
+        self.mark_for_update(DirtyRect {
+            min_x: -size,
+            min_y: -size,
+            max_x: size * 2,
+            max_y: size * 2,
+        });
+    }
+
+    /// Take cells to process on the current update, new ones are collected for the next one
+    #[inline(always)]
+    pub fn take_update_rect(&mut self) -> DirtyRect {
+        std::mem::take(&mut self.update_rect)
+    }
+
+    /// Chunk is not updated and has no particles
+    #[inline(always)]
+    pub fn is_idle(&self) -> bool {
+        !self.should_update() && self.particles.is_empty()
+    }
+
+    /// All cells of the chunk have given id
+    pub fn is_filled_with(&self, id: CellId) -> bool {
+        self.data.ids().iter().all(|&cell_id| cell_id == id)
+    }
+
+    /// Mark chunk as changed, so renderers know it has to be redrawn.
+    #[inline(always)]
+    pub fn mark_changed(&mut self) {
+        self.generation = self.generation.wrapping_add(1);
+        self.changed_rect = DirtyRect::CHUNK;
+    }
+
+    /// Mark single cell as changed, so renderers can redraw only part of the chunk.
+    #[inline(always)]
+    pub fn mark_cell_changed(&mut self, index: usize) {
+        let pos = CellPos::from_index(index);
+        self.generation = self.generation.wrapping_add(1);
+        self.changed_rect =
+            self.changed_rect
+                .union(DirtyRect::around(pos.x as i16, pos.y as i16, 0));
     }
 
-    /// Wether this chunk should trigger neighbor chunks update
+    /// Cells changed since the chunk had `generation`, or `None` if it's unknown and the whole
+    /// chunk has to be redrawn (e.g. `generation` is too old or belongs to another chunk).
+    pub fn changed_rect_since(&self, generation: u64) -> Option<DirtyRect> {
+        let since_start = generation.wrapping_sub(self.changed_since);
+        let current = self.generation.wrapping_sub(self.changed_since);
+
+        (since_start <= current).then_some(self.changed_rect)
+    }
+
+    /// Start collecting changed cells from the current generation
     #[inline(always)]
-    pub fn should_update_neighbor(&self) -> bool {
-        self.should_update
+    pub fn reset_changed_rect(&mut self) {
+        self.changed_rect = DirtyRect::EMPTY;
+        self.changed_since = self.generation;
     }
 
+    /// Counter changed on every visible change of the chunk. Renderers compare it with the
+    /// generation of the cached texture to decide if it has to be rebuilt.
     #[inline(always)]
-    pub fn set_should_redraw(&mut self, should_redraw: bool) {
-        self.should_redraw = should_redraw;
+    pub fn generation(&self) -> u64 {
+        self.generation
     }
 
+    /// Random state of each cell, see [`Chunk::get_random_value`]
     #[inline(always)]
-    pub fn should_redraw(&self) -> bool {
-        self.should_redraw || self.particles.len() > 0
+    pub fn random_state(&self) -> &[u64; CHUNK_AREA] {
+        &self.next_random
+    }
+
+    #[inline(always)]
+    pub fn random_state_mut(&mut self) -> &mut [u64; CHUNK_AREA] {
+        &mut self.next_random
     }
 
     /// Get next random value for specific cell
@@ -130,24 +214,41 @@ impl Chunk {
         x
     }
 
+    /// All cells of the chunk, indexed by [`CellPos::to_index`]
+    #[inline(always)]
+    pub fn cells(&self) -> &CellStorage {
+        &self.data
+    }
+
+    /// Mutable access to all cells, chunk is marked to be updated and redrawn.
+    #[inline(always)]
+    pub fn cells_mut(&mut self) -> &mut CellStorage {
+        self.mark_all_for_update();
+        self.mark_changed();
+        &mut self.data
+    }
+
     #[inline(always)]
     pub fn get_cell(&self, pos: CellPos) -> Cell {
-        self.data[pos.to_index()]
+        self.data.get(pos.to_index())
     }
 
+    /// Set cell without marking it as changed or for update
     #[inline(always)]
     pub fn set_cell(&mut self, pos: CellPos, cell: Cell) {
-        self.data[pos.to_index()] = cell;
+        self.data.set(pos.to_index(), cell);
     }
 
+    /// Set cell and mark it as changed. Returns `false` if the cell is the same. It doesn't mark
+    /// cells for update, that depends on the template (see [`CellsTemplate::update_radius`]).
     #[inline(always)]
-    pub fn set_by_index(&mut self, index: usize, cell: Cell) {
-        let target = &mut self.data[index];
-        if *target != cell {
-            *target = cell;
-            self.should_redraw = true;
-            self.should_update = true;
+    pub fn set_by_index(&mut self, index: usize, cell: Cell) -> bool {
+        if !self.data.set(index, cell) {
+            return false;
         }
+
+        self.mark_cell_changed(index);
+        true
     }
 
     #[inline(always)]
@@ -158,67 +259,168 @@ impl Chunk {
 
     #[inline(always)]
     pub fn get_by_index(&self, index: usize) -> Cell {
-        self.data[index]
+        self.data.get(index)
     }
 
+    /// Change temperature without marking the cell as changed, it's not visible
     #[inline(always)]
-    pub fn get_mut_by_index(&mut self, index: usize) -> &mut Cell {
-        &mut self.data[index]
+    pub fn set_temperature_by_index(&mut self, index: usize, temperature: Temperature) {
+        self.data.set_temperature(index, temperature);
     }
 
-    pub fn get_texture(&mut self, cells_template: &CellsTemplate) -> &Texture2D {
-        if self.texture.is_none() || self.should_redraw {
-            self.should_redraw = false;
-            let mut image = Image::gen_image_color(
-                CHUNK_SIZE as u16,
-                CHUNK_SIZE as u16,
-                Color::from_rgba(0, 0, 0, 0),
-            );
+    /// Heap memory used by cells, random state and particles of the chunk in bytes
+    pub fn memory_usage(&self) -> usize {
+        self.data.memory_usage()
+            + std::mem::size_of_val(&*self.next_random)
+            + self.particles.capacity() * std::mem::size_of::<Particle>()
+    }
 
-            for cell_index in 0..CHUNK_AREA {
-                let cell = self.get_by_index(cell_index);
-                let cell_pos = CellPos::from_index(cell_index);
+    /// Replace cell ids using `id_map` (indexed by old id) with ids of `cells_template`. Cells
+    /// mapped to `None` are replaced with the first cell of the template, particles of such cells
+    /// are removed together with their placeholders.
+    pub fn remap_cells(&mut self, id_map: &[Option<CellId>], cells_template: &CellsTemplate) {
+        let empty_cell = cells_template.cells[0].init();
+        self.data.remap_ids(|id| id_map[id as usize], empty_cell);
 
-                // let color = cell.color.;
-                // image.set_pixel(
-                //     cell_pos.x as u32,
-                //     (image.height - 1 - cell_pos.y) as u32,
-                //     color,
-                // );
-                let pixel_x = cell_pos.x as usize;
-                let pixel_y = CHUNK_SIZE - 1 - cell_pos.y as usize;
+        let data = &mut self.data;
+        self.particles.retain_mut(|particle| {
+            particle.covered_id = id_map[particle.covered_id as usize].unwrap_or(empty_cell.id);
 
-                let color = cell.color(cells_template).calculate(self, cell_index);
-                image.get_image_data_mut()[pixel_y * CHUNK_SIZE + pixel_x] = color;
+            if let Some(id) = id_map[particle.cell_id as usize] {
+                particle.cell_id = id;
+                return true;
             }
 
-            for particle in &self.particles {
-                let pixel_x = particle.in_chunk_pos.x as usize;
-                let pixel_y = particle.in_chunk_pos.y as usize;
-                debug_assert!(pixel_x < CHUNK_SIZE);
-                debug_assert!(pixel_y < CHUNK_SIZE);
-                let pixel_y = CHUNK_SIZE - 1 - pixel_y;
-                let pixel_index = pixel_y * CHUNK_SIZE + pixel_x;
-                image.get_image_data_mut()[pixel_index] = particle.color;
-                // image.get_image_data_mut()[pixel_index] = [255, 0, 255, 255];
+            if let Some(pos) = particle.get_cell_pos() {
+                let mut cell = data.get(pos.to_index());
+                if cell.id == cells_template.particle_placeholder {
+                    cell.id = particle.covered_id;
+                    data.set(pos.to_index(), cell);
+                }
             }
+            false
+        });
 
-            self.image = Some(image);
-            let image = self.image.as_ref().unwrap();
+        self.mark_all_for_update();
+        self.mark_changed();
+    }
 
-            match &self.texture {
-                Some(texture) => {
-                    texture.update(image);
-                }
-                None => {
-                    let texture = Texture2D::from_image(image);
-                    texture.set_filter(FilterMode::Nearest);
+    /// Write RGBA colors of cells and particles to `pixels`, rows go from top to bottom. Doesn't
+    /// need graphics context, so it can be used for headless snapshots.
+    pub fn write_pixels(&self, cells_template: &CellsTemplate, pixels: &mut [[u8; 4]]) {
+        self.write_pixels_in_rect(cells_template, pixels, DirtyRect::CHUNK);
+    }
 
-                    self.texture = Some(texture);
-                }
+    /// Same as [`Chunk::write_pixels`], but only pixels of cells in `rect` are written
+    pub fn write_pixels_in_rect(
+        &self,
+        cells_template: &CellsTemplate,
+        pixels: &mut [[u8; 4]],
+        rect: DirtyRect,
+    ) {
+        debug_assert_eq!(pixels.len(), CHUNK_AREA);
+        let rect = rect.intersect(DirtyRect::CHUNK);
+        if rect.is_empty() {
+            return;
+        }
+
+        for y in rect.min_y as usize..rect.max_y as usize {
+            let pixel_y = CHUNK_SIZE - 1 - y;
+            for x in rect.min_x as usize..rect.max_x as usize {
+                let index = x + y * CHUNK_SIZE;
+                let color = cells_template.get_cell_meta(self.data.id(index)).color;
+                pixels[pixel_y * CHUNK_SIZE + x] = color.calculate_from_system_register(
+                    self.data.register(index, CELL_REGISTER_SYSTEM),
+                );
             }
         }
 
-        self.texture.as_ref().unwrap()
+        for particle in &self.particles {
+            let Some(pos) = particle.get_cell_pos() else {
+                debug_assert!(false, "particle is outside of the chunk");
+                continue;
+            };
+            let pixel_x = pos.x as usize;
+            let pixel_y = pos.y as usize;
+            if !rect.contains(pixel_x as i16, pixel_y as i16) {
+                continue;
+            }
+            let pixel_y = CHUNK_SIZE - 1 - pixel_y;
+            pixels[pixel_y * CHUNK_SIZE + pixel_x] = particle.color;
+        }
     }
 }
+
+#[test]
+fn test_generation_changes_with_cells() {
+    let cells_template = default_cells();
+    let stone = cells_template
+        .get_cell_meta_by_label(CELL_STONE_LABEL)
+        .unwrap();
+
+    let mut chunk = Chunk::new(&cells_template, 0);
+    let other_chunk = Chunk::new(&cells_template, 0);
+    assert_ne!(chunk.generation(), other_chunk.generation());
+
+    let generation = chunk.generation();
+    chunk.set_by_index(0, chunk.get_by_index(0));
+    assert_eq!(chunk.generation(), generation);
+
+    chunk.set_by_index(0, stone.init());
+    assert_ne!(chunk.generation(), generation);
+}
+
+#[test]
+fn test_changed_rect_pixels() {
+    let cells_template = default_cells();
+    let stone = cells_template
+        .get_cell_meta_by_label(CELL_STONE_LABEL)
+        .unwrap();
+
+    let mut chunk = Chunk::new(&cells_template, 0);
+    let mut pixels = vec![[0; 4]; CHUNK_AREA];
+    chunk.write_pixels(&cells_template, &mut pixels);
+    let generation = chunk.generation();
+    assert_eq!(chunk.changed_rect_since(generation), Some(DirtyRect::EMPTY));
+
+    chunk.set_by_index(CellPos::new(3, 4).to_index(), stone.init());
+    chunk.set_by_index(CellPos::new(10, 2).to_index(), stone.init());
+    let rect = chunk.changed_rect_since(generation).unwrap();
+    assert_eq!(rect.area(), 8 * 3);
+
+    // writing only changed rect gives the same pixels
+    chunk.write_pixels_in_rect(&cells_template, &mut pixels, rect);
+    let mut expected = vec![[0; 4]; CHUNK_AREA];
+    chunk.write_pixels(&cells_template, &mut expected);
+    assert!(pixels == expected);
+
+    // changes before the reset are unknown
+    chunk.reset_changed_rect();
+    assert_eq!(chunk.changed_rect_since(generation), None);
+    assert_eq!(
+        chunk.changed_rect_since(chunk.generation()),
+        Some(DirtyRect::EMPTY)
+    );
+    let other_chunk = Chunk::new(&cells_template, 0);
+    assert_eq!(chunk.changed_rect_since(other_chunk.generation()), None);
+}
+
+#[test]
+fn test_chunk_without_registers_memory_usage() {
+    let cells_template = default_cells();
+    let sand = cells_template
+        .get_cell_meta_by_label(CELL_SAND_LABEL)
+        .unwrap();
+
+    let mut chunk = Chunk::new(&cells_template, 0);
+    let empty_usage = chunk.memory_usage();
+    assert!(empty_usage < CHUNK_AREA * 32, "{empty_usage}");
+
+    // only cells with registers pay for them
+    let mut cell = sand.init();
+    cell.registers[CELL_REGISTER_AGE] = 10;
+    chunk.set_by_index(0, cell);
+    assert_eq!(chunk.cells().cells_with_registers(), 1);
+    assert_eq!(chunk.get_by_index(0), cell);
+    assert!(chunk.memory_usage() - empty_usage < 1024);
+}
@@ -0,0 +1,505 @@
+use crate::*;
+use eyre::WrapErr;
+use nohash_hasher::IntMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Region file used to page chunks out of memory.
+///
+/// Chunks are appended to the file, each one with own label table (see [`write_label_table`]) so
+/// it stays valid if cells template is replaced. Space of loaded chunks is reclaimed by
+/// [`ChunkStore::compact_if_needed`] once it exceeds the size of the paged ones. The file is
+/// removed when the store is dropped.
+#[derive(Debug)]
+pub struct ChunkStore {
+    path: PathBuf,
+    file: File,
+    index: IntMap<ChunkPos, PagedChunk>,
+    /// Size of the file, including space of chunks loaded since the last compaction
+    file_len: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PagedChunk {
+    offset: u64,
+    len: u64,
+    /// Restored when the chunk is loaded with the same cells template, so paging an idle chunk
+    /// doesn't make it process its cells again
+    update_rect: DirtyRect,
+}
+
+/// Region file isn't compacted while space of loaded chunks is smaller than this
+const MIN_COMPACTED_BYTES: u64 = 1 << 20;
+
+impl ChunkStore {
+    /// Create new empty region file, existing file will be overwritten.
+    pub fn create(path: impl Into<PathBuf>) -> eyre::Result<Self> {
+        let path = path.into();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .wrap_err_with(|| format!("Failed to create region file {}", path.display()))?;
+
+        Ok(Self {
+            path,
+            file,
+            index: Default::default(),
+            file_len: 0,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Amount of paged chunks
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    pub fn contains(&self, pos: ChunkPos) -> bool {
+        self.index.contains_key(&pos)
+    }
+
+    pub fn positions(&self) -> impl Iterator<Item = ChunkPos> + '_ {
+        self.index.keys().copied()
+    }
+
+    /// Size of the region file in bytes
+    pub fn file_len(&self) -> u64 {
+        self.file_len
+    }
+
+    /// Write chunk to the end of the region file.
+    pub fn store(
+        &mut self,
+        pos: ChunkPos,
+        chunk: &Chunk,
+        cells_template: &CellsTemplate,
+    ) -> eyre::Result<()> {
+        let mut bytes = Vec::new();
+        write_label_table(&mut bytes, cells_template)?;
+        write_chunk(&mut bytes, pos, chunk)?;
+
+        let offset = self.file_len;
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.write_all(&bytes)?;
+
+        self.file_len += bytes.len() as u64;
+        self.index.insert(
+            pos,
+            PagedChunk {
+                offset,
+                len: bytes.len() as u64,
+                update_rect: chunk.update_rect(),
+            },
+        );
+
+        Ok(())
+    }
+
+    /// Read paged chunk without removing it from the store.
+    pub fn read(
+        &self,
+        pos: ChunkPos,
+        cells_template: &CellsTemplate,
+    ) -> eyre::Result<Option<Chunk>> {
+        let Some(paged) = self.index.get(&pos) else {
+            return Ok(None);
+        };
+
+        let mut file = File::open(&self.path)?;
+        file.seek(SeekFrom::Start(paged.offset))?;
+        let mut reader = BufReader::new(file);
+        let id_map = read_label_table(&mut reader, cells_template)?;
+        let (_, mut chunk) = read_chunk(&mut reader, cells_template, &id_map)
+            .wrap_err_with(|| format!("Failed to read paged chunk {pos:?}"))?;
+
+        // chunk is marked for update when read, only needed if the cells were remapped
+        let template_changed = id_map
+            .iter()
+            .enumerate()
+            .any(|(id, new_id)| *new_id != Some(id as CellId));
+        if !template_changed {
+            chunk.take_update_rect();
+            chunk.mark_for_update(paged.update_rect);
+        }
+
+        Ok(Some(chunk))
+    }
+
+    /// Read paged chunk and remove it from the store. Its space in the file is reclaimed later by
+    /// [`ChunkStore::compact_if_needed`].
+    pub fn load(
+        &mut self,
+        pos: ChunkPos,
+        cells_template: &CellsTemplate,
+    ) -> eyre::Result<Option<Chunk>> {
+        let chunk = self.read(pos, cells_template)?;
+        self.index.remove(&pos);
+
+        Ok(chunk)
+    }
+
+    /// Compact the file if space of loaded chunks exceeds the size of the paged ones.
+    pub fn compact_if_needed(&mut self) -> eyre::Result<()> {
+        let paged_bytes: u64 = self.index.values().map(|paged| paged.len).sum();
+        let free_bytes = self.file_len - paged_bytes;
+        if free_bytes > paged_bytes.max(MIN_COMPACTED_BYTES)
+            || (self.index.is_empty() && self.file_len > 0)
+        {
+            self.compact()?;
+        }
+
+        Ok(())
+    }
+
+    /// Remove space of loaded chunks from the file. Paged chunks are copied to a new file which
+    /// then replaces the old one, so the store stays unchanged if compaction fails.
+    pub fn compact(&mut self) -> eyre::Result<()> {
+        let compacted_path = self.path.with_extension("compacted");
+        let compacted = self
+            .write_compacted(&compacted_path)
+            .and_then(|compacted| {
+                std::fs::rename(&compacted_path, &self.path)?;
+                Ok(compacted)
+            })
+            .wrap_err_with(|| format!("Failed to compact region file {}", self.path.display()));
+        let (file, index, file_len) = match compacted {
+            Ok(compacted) => compacted,
+            Err(err) => {
+                let _ = std::fs::remove_file(&compacted_path);
+                return Err(err);
+            }
+        };
+
+        self.file = file;
+        self.index = index;
+        self.file_len = file_len;
+
+        Ok(())
+    }
+
+    /// Copy paged chunks to a new file, returns it with the index of the copied chunks and length.
+    fn write_compacted(
+        &mut self,
+        path: &Path,
+    ) -> eyre::Result<(File, IntMap<ChunkPos, PagedChunk>, u64)> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+
+        let mut entries: Vec<(ChunkPos, PagedChunk)> = self
+            .index
+            .iter()
+            .map(|(&pos, &paged)| (pos, paged))
+            .collect();
+        entries.sort_by_key(|(_, paged)| paged.offset);
+
+        let mut index = IntMap::default();
+        let mut file_len = 0;
+        let mut bytes = Vec::new();
+        for (pos, paged) in entries {
+            bytes.resize(paged.len as usize, 0);
+            self.file.seek(SeekFrom::Start(paged.offset))?;
+            self.file.read_exact(&mut bytes)?;
+            file.write_all(&bytes)?;
+            index.insert(
+                pos,
+                PagedChunk {
+                    offset: file_len,
+                    ..paged
+                },
+            );
+            file_len += paged.len;
+        }
+        file.flush()?;
+
+        Ok((file, index, file_len))
+    }
+}
+
+impl Drop for ChunkStore {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Policy deciding which chunks stay in memory, see [`WorldState::update_residency`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkResidency {
+    /// Chunks further than this from the focus area are paged to the [`ChunkStore`]
+    pub keep_distance: i32,
+    /// Don't change the simulation: no chunks are dropped and only idle ones are paged, they are
+    /// loaded back once their neighbors need them. Used while replays are recorded or played.
+    pub lossless: bool,
+}
+
+impl Default for ChunkResidency {
+    fn default() -> Self {
+        Self {
+            keep_distance: 4,
+            lossless: false,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ResidencyStats {
+    pub dropped: usize,
+    pub paged: usize,
+}
+
+#[cfg(test)]
+fn temp_region_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("sand-test-{}-{name}.region", std::process::id()))
+}
+
+#[test]
+fn test_chunks_paged_and_reloaded() {
+    let cells_template = default_cells();
+    let stone = cells_template
+        .get_cell_meta_by_label(CELL_STONE_LABEL)
+        .unwrap();
+
+    let mut world = WorldState::new();
+    world.set_chunk_store(ChunkStore::create(temp_region_path("paging")).unwrap());
+
+    let near_pos = GlobalCellPos::new(10, 10);
+    let far_pos = GlobalCellPos::new(CHUNK_SIZE as i32 * 20 + 3, 5);
+    world
+        .set_cell(near_pos, stone.init(), &cells_template)
+        .unwrap();
+    world
+        .set_cell(far_pos, stone.init(), &cells_template)
+        .unwrap();
+    // empty chunk
+    world
+        .ensure_chunk(ChunkPos::new(-1, 0), &cells_template)
+        .unwrap();
+    world.update_state(&cells_template).unwrap();
+    world.update_state(&cells_template).unwrap();
+
+    let residency = ChunkResidency {
+        keep_distance: 1,
+        ..Default::default()
+    };
+    let stats = world
+        .update_residency(
+            ChunkPos::new(0, 0),
+            ChunkPos::new(0, 0),
+            residency,
+            &cells_template,
+        )
+        .unwrap();
+
+    assert_eq!(stats.paged, 1);
+    assert!(world.get_chunk(far_pos.chunk).is_none());
+    assert!(world.get_chunk(ChunkPos::new(-1, 0)).is_none());
+    assert_eq!(world.paged_len(), 1);
+
+    // saves include paged chunks
+    let mut save = Vec::new();
+    write_world(&mut save, &world, &cells_template).unwrap();
+    let loaded = read_world(&mut save.as_slice(), &cells_template).unwrap();
+    let cell = loaded
+        .get_chunk(far_pos.chunk)
+        .unwrap()
+        .get_cell(far_pos.cell);
+    assert_eq!(cell.id, stone.id);
+
+    let chunk = world.ensure_chunk(far_pos.chunk, &cells_template).unwrap();
+    assert_eq!(chunk.get_cell(far_pos.cell).id, stone.id);
+    assert_eq!(world.paged_len(), 0);
+}
+
+#[test]
+fn test_paged_chunk_load_error() {
+    let cells_template = default_cells();
+    let stone = cells_template
+        .get_cell_meta_by_label(CELL_STONE_LABEL)
+        .unwrap();
+
+    let path = temp_region_path("load-error");
+    let mut world = WorldState::new();
+    world.set_chunk_store(ChunkStore::create(&path).unwrap());
+
+    let far_pos = GlobalCellPos::new(CHUNK_SIZE as i32 * 20 + 3, 5);
+    world
+        .set_cell(far_pos, stone.init(), &cells_template)
+        .unwrap();
+    let residency = ChunkResidency {
+        keep_distance: 1,
+        ..Default::default()
+    };
+    let stats = world
+        .update_residency(
+            ChunkPos::new(0, 0),
+            ChunkPos::new(0, 0),
+            residency,
+            &cells_template,
+        )
+        .unwrap();
+    assert_eq!(stats.paged, 1);
+
+    // truncated region file is reported instead of panicking, chunk stays paged
+    File::create(&path).unwrap();
+    assert!(world.ensure_chunk(far_pos.chunk, &cells_template).is_err());
+    assert!(world
+        .set_cell(far_pos, stone.init(), &cells_template)
+        .is_err());
+    assert!(world.get_chunk(far_pos.chunk).is_none());
+    assert_eq!(world.paged_len(), 1);
+}
+
+#[test]
+fn test_region_file_compacted() {
+    let cells_template = default_cells();
+    let stone = cells_template
+        .get_cell_meta_by_label(CELL_STONE_LABEL)
+        .unwrap();
+
+    let world = WorldState::new();
+    let mut stone_chunk = world.new_chunk(ChunkPos::new(0, 0), &cells_template);
+    stone_chunk.set_cell(CellPos::new(1, 2), stone.init());
+    let empty_chunk = world.new_chunk(ChunkPos::new(0, 0), &cells_template);
+
+    let mut store = ChunkStore::create(temp_region_path("compaction")).unwrap();
+    let kept_pos = ChunkPos::new(5, 5);
+    let moved_pos = ChunkPos::new(-5, 5);
+
+    for _ in 0..200 {
+        store
+            .store(moved_pos, &empty_chunk, &cells_template)
+            .unwrap();
+        if !store.contains(kept_pos) {
+            store
+                .store(kept_pos, &stone_chunk, &cells_template)
+                .unwrap();
+        }
+        store.load(moved_pos, &cells_template).unwrap().unwrap();
+        store.compact_if_needed().unwrap();
+    }
+
+    let chunk_len = store.index[&kept_pos].len;
+    assert!(store.file_len() <= MIN_COMPACTED_BYTES + chunk_len * 3);
+    assert_eq!(store.len(), 1);
+
+    let chunk = store.load(kept_pos, &cells_template).unwrap().unwrap();
+    assert_eq!(chunk.get_cell(CellPos::new(1, 2)).id, stone.id);
+    store.compact_if_needed().unwrap();
+    assert_eq!(store.file_len(), 0);
+}
+
+#[test]
+fn test_failed_compaction_keeps_chunks() {
+    let cells_template = default_cells();
+    let stone = cells_template
+        .get_cell_meta_by_label(CELL_STONE_LABEL)
+        .unwrap();
+
+    let world = WorldState::new();
+    let mut chunk = world.new_chunk(ChunkPos::new(0, 0), &cells_template);
+    chunk.set_cell(CellPos::new(1, 2), stone.init());
+
+    let path = temp_region_path("failed-compaction");
+    let mut store = ChunkStore::create(&path).unwrap();
+    let loaded_pos = ChunkPos::new(-5, 5);
+    let paged_pos = ChunkPos::new(5, 5);
+    store.store(loaded_pos, &chunk, &cells_template).unwrap();
+    store.store(paged_pos, &chunk, &cells_template).unwrap();
+    let file_len = store.file_len();
+
+    // compacted file can't be created, loading doesn't depend on the compaction
+    let compacted_path = path.with_extension("compacted");
+    std::fs::create_dir(&compacted_path).unwrap();
+    let loaded = store.load(loaded_pos, &cells_template).unwrap().unwrap();
+    assert_eq!(loaded.get_cell(CellPos::new(1, 2)).id, stone.id);
+    assert!(store.compact().is_err());
+    std::fs::remove_dir(&compacted_path).unwrap();
+
+    assert_eq!(store.file_len(), file_len);
+    let paged = store.read(paged_pos, &cells_template).unwrap().unwrap();
+    assert_eq!(paged.get_cell(CellPos::new(1, 2)).id, stone.id);
+
+    store.compact().unwrap();
+    assert_eq!(store.file_len(), file_len / 2);
+    let paged = store.load(paged_pos, &cells_template).unwrap().unwrap();
+    assert_eq!(paged.get_cell(CellPos::new(1, 2)).id, stone.id);
+}
+
+#[test]
+fn test_lossless_residency_keeps_simulation() {
+    let cells_template = default_cells();
+    let chunk = CHUNK_SIZE as i32;
+    let fill = |world: &mut WorldState, label: &str, min: (i32, i32), max: (i32, i32)| {
+        let cell_meta = cells_template.get_cell_meta_by_label(label).unwrap();
+        let (min, max) = (
+            GlobalCellPos::new(min.0, min.1),
+            GlobalCellPos::new(max.0, max.1),
+        );
+        gen_rect(world, &cells_template, min, max, cell_meta).unwrap();
+    };
+
+    let mut worlds = [0, 1].map(|_| {
+        let mut world = WorldState::with_seed(3);
+        fill(
+            &mut world,
+            CELL_STONE_LABEL,
+            (-chunk * 3, 0),
+            (chunk * 3, 4),
+        );
+        fill(
+            &mut world,
+            CELL_SAND_LABEL,
+            (chunk * 2 - 10, 10),
+            (chunk * 2 + 10, 60),
+        );
+        world
+            .ensure_chunk(ChunkPos::new(-2, 3), &cells_template)
+            .unwrap();
+        world
+    });
+    let [paged, resident] = &mut worlds;
+    paged.set_chunk_store(ChunkStore::create(temp_region_path("lossless")).unwrap());
+
+    let residency = ChunkResidency {
+        keep_distance: 0,
+        lossless: true,
+    };
+    let focus = ChunkPos::new(0, 0);
+    let mut was_paged = false;
+    for _ in 0..150 {
+        paged.update_state(&cells_template).unwrap();
+        resident.update_state(&cells_template).unwrap();
+
+        let stats = paged
+            .update_residency(focus, focus, residency, &cells_template)
+            .unwrap();
+        assert_eq!(stats.dropped, 0);
+        was_paged |= stats.paged > 0;
+    }
+    assert!(was_paged);
+
+    let positions: Vec<_> = resident.chunks().map(|(pos, _)| pos).collect();
+    for pos in positions {
+        let expected = resident.get_chunk(pos).unwrap();
+        let chunk = paged
+            .get_or_load_chunk(pos, &cells_template)
+            .unwrap()
+            .unwrap();
+        assert!(chunk.cells() == expected.cells(), "chunk {pos:?}");
+        assert_eq!(chunk.particles, expected.particles, "chunk {pos:?}");
+    }
+}
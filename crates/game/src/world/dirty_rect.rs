@@ -0,0 +1,147 @@
+use crate::*;
+
+/// Rectangle of cells in chunk coordinates, `min` is inclusive and `max` is exclusive. It may
+/// extend beyond the chunk, see [`Chunk::update_rect`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DirtyRect {
+    pub min_x: i16,
+    pub min_y: i16,
+    pub max_x: i16,
+    pub max_y: i16,
+}
+
+impl Default for DirtyRect {
+    fn default() -> Self {
+        Self::EMPTY
+    }
+}
+
+impl DirtyRect {
+    pub const EMPTY: Self = Self {
+        min_x: i16::MAX,
+        min_y: i16::MAX,
+        max_x: i16::MIN,
+        max_y: i16::MIN,
+    };
+
+    /// All cells of the chunk
+    pub const CHUNK: Self = Self {
+        min_x: 0,
+        min_y: 0,
+        max_x: CHUNK_SIZE as i16,
+        max_y: CHUNK_SIZE as i16,
+    };
+
+    /// Cells at most `radius` cells away from (`x`, `y`)
+    #[inline(always)]
+    pub fn around(x: i16, y: i16, radius: i16) -> Self {
+        Self {
+            min_x: x - radius,
+            min_y: y - radius,
+            max_x: x + radius + 1,
+            max_y: y + radius + 1,
+        }
+    }
+
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.min_x >= self.max_x || self.min_y >= self.max_y
+    }
+
+    #[inline(always)]
+    pub fn contains(&self, x: i16, y: i16) -> bool {
+        (self.min_x..self.max_x).contains(&x) && (self.min_y..self.max_y).contains(&y)
+    }
+
+    /// Smallest rectangle containing both rectangles
+    #[inline(always)]
+    pub fn union(self, other: Self) -> Self {
+        if other.is_empty() {
+            return self;
+        }
+        if self.is_empty() {
+            return other;
+        }
+
+        Self {
+            min_x: self.min_x.min(other.min_x),
+            min_y: self.min_y.min(other.min_y),
+            max_x: self.max_x.max(other.max_x),
+            max_y: self.max_y.max(other.max_y),
+        }
+    }
+
+    /// Cells contained in both rectangles, may be empty
+    #[inline(always)]
+    pub fn intersect(self, other: Self) -> Self {
+        let rect = Self {
+            min_x: self.min_x.max(other.min_x),
+            min_y: self.min_y.max(other.min_y),
+            max_x: self.max_x.min(other.max_x),
+            max_y: self.max_y.min(other.max_y),
+        };
+
+        if rect.is_empty() {
+            Self::EMPTY
+        } else {
+            rect
+        }
+    }
+
+    #[inline(always)]
+    pub fn translate(self, x: i16, y: i16) -> Self {
+        if self.is_empty() {
+            return self;
+        }
+
+        Self {
+            min_x: self.min_x + x,
+            min_y: self.min_y + y,
+            max_x: self.max_x + x,
+            max_y: self.max_y + y,
+        }
+    }
+
+    /// Amount of cells in the rectangle
+    pub fn area(&self) -> usize {
+        if self.is_empty() {
+            return 0;
+        }
+
+        (self.max_x - self.min_x) as usize * (self.max_y - self.min_y) as usize
+    }
+}
+
+#[test]
+fn test_dirty_rect() {
+    let a = DirtyRect::around(0, 5, 1);
+    assert_eq!(a.area(), 9);
+    assert!(a.contains(-1, 4));
+    assert!(!a.contains(-1, 7));
+
+    assert_eq!(DirtyRect::EMPTY.union(a), a);
+    assert_eq!(a.union(DirtyRect::EMPTY), a);
+    let b = a.union(DirtyRect::around(10, 0, 0));
+    assert_eq!(
+        b,
+        DirtyRect {
+            min_x: -1,
+            min_y: 0,
+            max_x: 11,
+            max_y: 7,
+        }
+    );
+
+    assert_eq!(
+        b.intersect(DirtyRect::CHUNK),
+        DirtyRect {
+            min_x: 0,
+            min_y: 0,
+            max_x: 11,
+            max_y: 7,
+        }
+    );
+    assert!(a.intersect(DirtyRect::around(10, 0, 0)).is_empty());
+    assert!(DirtyRect::EMPTY.translate(5, 5).is_empty());
+    assert_eq!(a.translate(1, -5), DirtyRect::around(1, 0, 1));
+}
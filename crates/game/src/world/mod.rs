@@ -1,13 +1,25 @@
 mod cell;
+mod cell_storage;
 mod chunk;
+mod chunk_store;
+mod dirty_rect;
 mod particle;
+mod random;
 mod true_mod;
 mod update_chunk;
+mod world_save;
+mod world_snapshot;
 mod world_state;
 
 pub use cell::*;
+pub use cell_storage::*;
 pub use chunk::*;
+pub use chunk_store::*;
+pub use dirty_rect::*;
 pub use particle::*;
+pub use random::*;
 pub use true_mod::*;
 pub use update_chunk::*;
+pub use world_save::*;
+pub use world_snapshot::*;
 pub use world_state::*;
@@ -1,65 +1,202 @@
 use crate::*;
-use macroquad::math::Vec2;
+use glam::Vec2;
+
+/// Farthest distance a particle moves along each axis per tick. Particle starting anywhere in a
+/// chunk stays within its neighbors, which are the only chunks available during the update (see
+/// [`ChunkUpdateContext`]).
+pub const PARTICLE_MAX_STEP: f32 = CHUNK_SIZE as f32 - 1.0;
+
+/// Particles slower than this after hitting an obstacle (cells per second) turn back into cells
+pub const PARTICLE_SETTLE_SPEED: f32 = 1.0;
+
+/// Bits of [`ParticlePos`] coordinates used for the position inside the cell
+pub const PARTICLE_POS_FRACTION_BITS: u32 = 16;
+
+/// Position of a particle relative to its chunk in fixed point, the integer part is the cell and
+/// the fraction is the position inside it. Moving the particle to another chunk changes only the
+/// integer part, so it's exact and the particle can't end up on the chunk boundary.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ParticlePos {
+    pub x: i32,
+    pub y: i32,
+}
+
+impl ParticlePos {
+    /// One cell in fixed point units
+    pub const CELL: i32 = 1 << PARTICLE_POS_FRACTION_BITS;
+
+    /// Bottom left corner of the cell
+    pub fn from_cell(pos: CellPos) -> Self {
+        Self::from_cell_cords(pos.x as i32, pos.y as i32)
+    }
+
+    pub fn from_cell_cords(x: i32, y: i32) -> Self {
+        Self {
+            x: x << PARTICLE_POS_FRACTION_BITS,
+            y: y << PARTICLE_POS_FRACTION_BITS,
+        }
+    }
+
+    /// Coordinates of the cell containing the position, may be outside of the chunk
+    #[inline(always)]
+    pub fn cell_cords(self) -> (i32, i32) {
+        (
+            self.x >> PARTICLE_POS_FRACTION_BITS,
+            self.y >> PARTICLE_POS_FRACTION_BITS,
+        )
+    }
+
+    /// Cell containing the position, `None` if it's outside of the chunk
+    #[inline(always)]
+    pub fn cell_pos(self) -> Option<CellPos> {
+        let (x, y) = self.cell_cords();
+        let range = 0..CHUNK_SIZE as i32;
+        (range.contains(&x) && range.contains(&y))
+            .then(|| CellPos::new(x as CellCord, y as CellCord))
+    }
+
+    /// Move by `offset` cells, rounded to the fixed point precision
+    #[inline(always)]
+    pub fn translate(self, offset: Vec2) -> Self {
+        let offset = (offset * Self::CELL as f32).round();
+        Self {
+            x: self.x + offset.x as i32,
+            y: self.y + offset.y as i32,
+        }
+    }
+
+    /// Move by whole cells, exact
+    #[inline(always)]
+    pub fn translate_cells(self, x: i32, y: i32) -> Self {
+        Self {
+            x: self.x + (x << PARTICLE_POS_FRACTION_BITS),
+            y: self.y + (y << PARTICLE_POS_FRACTION_BITS),
+        }
+    }
+
+    /// Position in cells
+    pub fn to_vec(self) -> Vec2 {
+        Vec2::new(self.x as f32, self.y as f32) / Self::CELL as f32
+    }
+}
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Particle {
     pub vel: Vec2,
-    /// Position of the particle inside the chunk
-    pub in_chunk_pos: Vec2,
+    /// Position of the particle inside the chunk, always within the chunk
+    pub in_chunk_pos: ParticlePos,
     pub age: u32,
+    /// Tick at which particle was last moved, so a particle moved to a chunk updated later in
+    /// the same tick is not moved twice
+    pub last_update: u32,
     pub color: [u8; 4],
     pub gravity: Vec2,
     pub cell_id: CellId,
+    /// Id of the cell replaced by [`CellsTemplate::particle_placeholder`] at the particle position
+    pub covered_id: CellId,
+    /// Fragment of a splash (see [`CellMeta::particle_splash_chance`]). Fragments disappear
+    /// instead of turning into cells and don't splash themselves, so splashes don't create matter.
+    pub is_fragment: bool,
 }
 
 impl Particle {
     /// Return particle position in the chunk. If the particle is outside the chunk, return None
     #[inline(always)]
     pub fn get_cell_pos(&self) -> Option<CellPos> {
-        if self.in_chunk_pos.x < 0.0
-            || self.in_chunk_pos.x >= CHUNK_SIZE as f32
-            || self.in_chunk_pos.y < 0.0
-            || self.in_chunk_pos.y >= CHUNK_SIZE as f32
-        {
-            return None;
-        }
+        self.in_chunk_pos.cell_pos()
+    }
 
-        let x = self.in_chunk_pos.x as CellCord;
-        let y = self.in_chunk_pos.y as CellCord;
+    /// Apply velocity and gravity to the particle, speed is limited to `max_speed`. Particles
+    /// faster than [`PARTICLE_MAX_STEP`] cells per tick keep their velocity but move only that far.
+    pub fn update_pos(&mut self, dt: f32, max_speed: f32) {
+        self.age += 1;
+        self.vel = limit_speed(self.vel, max_speed);
 
-        Some(CellPos::new(x, y))
+        let step = (self.vel * dt).clamp(
+            Vec2::splat(-PARTICLE_MAX_STEP),
+            Vec2::splat(PARTICLE_MAX_STEP),
+        );
+        self.in_chunk_pos = self.in_chunk_pos.translate(step);
+        self.vel = limit_speed(self.vel + self.gravity * dt, max_speed);
     }
 
-    /// Apply velocity and gravity to the particle
-    pub fn update_pos(&mut self, dt: f32) {
-        self.age += 1;
-        self.in_chunk_pos += self.vel * dt;
-        self.vel += self.gravity * dt;
+    /// Bounce off an obstacle along the axes with `hit_x` and `hit_y`: velocity along them is
+    /// reversed and scaled by `restitution`, velocity along the other axis is reduced by `friction`.
+    pub fn bounce(&mut self, hit_x: bool, hit_y: bool, restitution: f32, friction: f32) {
+        let response = |vel: f32, hit: bool| {
+            if hit {
+                -vel * restitution
+            } else {
+                vel * (1.0 - friction)
+            }
+        };
+        self.vel = Vec2::new(response(self.vel.x, hit_x), response(self.vel.y, hit_y));
+    }
 
-        self.validate_pos_in_update_region();
+    /// Particle is too slow to keep flying after a hit
+    pub fn should_settle(&self) -> bool {
+        self.vel.length_squared() < PARTICLE_SETTLE_SPEED * PARTICLE_SETTLE_SPEED
     }
+}
 
-    pub fn validate_pos_in_update_region(&self) {
-        let size_f = CHUNK_SIZE as f32;
-        assert!(
-            self.in_chunk_pos.x > -size_f * 0.5,
-            "Particle is moving too fast! x = {:?}",
-            self.in_chunk_pos.x
-        );
-        assert!(
-            self.in_chunk_pos.x < size_f * 1.5,
-            "Particle is moving too fast! x = {:?}",
-            self.in_chunk_pos.x
-        );
-        assert!(
-            self.in_chunk_pos.y > -size_f * 0.5,
-            "Particle is moving too fast! y = {:?}",
-            self.in_chunk_pos.y
-        );
-        assert!(
-            self.in_chunk_pos.y < size_f * 1.5,
-            "Particle is moving too fast! y = {:?}",
-            self.in_chunk_pos.y
-        );
+/// Invalid velocities (e.g. NaN after a division by zero in a template) stop the particle
+#[inline(always)]
+fn limit_speed(vel: Vec2, max_speed: f32) -> Vec2 {
+    let vel = vel.clamp_length_max(max_speed.max(0.0));
+    if vel.is_finite() {
+        vel
+    } else {
+        Vec2::ZERO
     }
 }
+
+#[test]
+fn test_particle_pos_fixed_point() {
+    let pos = ParticlePos::from_cell(CellPos::new(127, 0));
+    assert_eq!(pos.cell_pos(), Some(CellPos::new(127, 0)));
+
+    // just below the next cell is still in the same cell
+    let edge = pos.translate(Vec2::new(1.0 - 1.0 / ParticlePos::CELL as f32, 0.0));
+    assert_eq!(edge.cell_cords(), (127, 0));
+    let outside = edge.translate(Vec2::new(1.0 / ParticlePos::CELL as f32, 0.0));
+    assert_eq!(outside.cell_cords(), (128, 0));
+    assert_eq!(outside.cell_pos(), None);
+
+    // moving to the neighbor chunk keeps the position inside the cell
+    let moved = outside.translate_cells(-(CHUNK_SIZE as i32), 0);
+    assert_eq!(moved.cell_pos(), Some(CellPos::new(0, 0)));
+    assert_eq!(moved.x & (ParticlePos::CELL - 1), 0);
+
+    let below = pos.translate(Vec2::new(0.0, -0.25));
+    assert_eq!(below.cell_cords(), (127, -1));
+    assert_eq!(
+        below.translate_cells(0, CHUNK_SIZE as i32).cell_pos(),
+        Some(CellPos::new(127, 127))
+    );
+    assert_eq!(below.to_vec(), Vec2::new(127.0, -0.25));
+}
+
+#[test]
+fn test_particle_bounce() {
+    let mut particle = Particle {
+        vel: Vec2::new(8.0, -20.0),
+        in_chunk_pos: ParticlePos::default(),
+        age: 0,
+        last_update: 0,
+        color: [0; 4],
+        gravity: Vec2::ZERO,
+        cell_id: 0,
+        covered_id: 0,
+        is_fragment: false,
+    };
+
+    // hit the floor: bounce up and slide along it
+    particle.bounce(false, true, 0.5, 0.25);
+    assert_eq!(particle.vel, Vec2::new(6.0, 10.0));
+    assert!(!particle.should_settle());
+
+    // inelastic corner hit stops the particle
+    particle.bounce(true, true, 0.0, 0.25);
+    assert_eq!(particle.vel, Vec2::ZERO);
+    assert!(particle.should_settle());
+}
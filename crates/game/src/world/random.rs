@@ -0,0 +1,42 @@
+use crate::*;
+
+/// See [SplitMix64](https://prng.di.unimi.it/splitmix64.c)
+#[inline(always)]
+pub const fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Derive new seed from `seed` and `value`
+#[inline(always)]
+pub const fn mix_seed(seed: u64, value: u64) -> u64 {
+    splitmix64(seed ^ splitmix64(value))
+}
+
+/// Seed of the chunk at `pos` in the world with given seed
+#[inline(always)]
+pub const fn chunk_seed(world_seed: u64, pos: ChunkPos) -> u64 {
+    mix_seed(
+        world_seed,
+        ((pos.x as u32 as u64) << 32) | (pos.y as u32 as u64),
+    )
+}
+
+#[test]
+fn test_chunk_seed_differs_by_pos() {
+    let seeds = [
+        chunk_seed(0, ChunkPos::new(0, 0)),
+        chunk_seed(0, ChunkPos::new(1, 0)),
+        chunk_seed(0, ChunkPos::new(0, 1)),
+        chunk_seed(0, ChunkPos::new(-1, -1)),
+        chunk_seed(1, ChunkPos::new(0, 0)),
+    ];
+
+    for (i, a) in seeds.iter().enumerate() {
+        for b in &seeds[i + 1..] {
+            assert_ne!(a, b);
+        }
+    }
+}
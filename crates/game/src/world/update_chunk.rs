@@ -1,9 +1,13 @@
 use crate::*;
-use macroquad::math::Vec2;
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
 
 pub struct ChunkUpdateContext<'a> {
     pub cells_template: &'a CellsTemplate,
+    pub rule_evaluator: RuleEvaluator,
     pub current_tick: u32,
+    /// Seed of this update, see [`WorldState::update_state`]
+    pub update_seed: u64,
     pub center: Chunk,
     pub left: Chunk,
     pub right: Chunk,
@@ -16,6 +20,16 @@ pub struct ChunkUpdateContext<'a> {
     pub delta_time: f32,
 }
 
+/// How cell rules are executed, both ways give the same result
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RuleEvaluator {
+    /// Walk [`CellMeta::rule`] recursively
+    Tree,
+    /// Run [`CellMeta::program`]
+    #[default]
+    Bytecode,
+}
+
 #[derive(Default, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct RelativeTransformation {
     pub mirror_x: bool,
@@ -58,7 +72,7 @@ impl RelativeTransformation {
 }
 
 /// Cell relative position
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct RelativePos {
     pub x: i8,
     pub y: i8,
@@ -101,40 +115,228 @@ impl RelativePos {
     }
 }
 
+/// Velocity relative to the current cell in cells per second, transformed by symmetry rules like
+/// [`RelativePos`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct RelativeVelocity {
+    pub x: i16,
+    pub y: i16,
+}
+
+impl RelativeVelocity {
+    pub const fn new(x: i16, y: i16) -> Self {
+        Self { x, y }
+    }
+
+    #[inline(always)]
+    pub const fn transform(mut self, transformation: RelativeTransformation) -> Self {
+        if transformation.mirror_x {
+            self.x = -self.x;
+        }
+        if transformation.mirror_y {
+            self.y = -self.y;
+        }
+        if transformation.mirror_diagonal {
+            core::mem::swap(&mut self.x, &mut self.y);
+        }
+
+        self
+    }
+}
+
 impl<'a> ChunkUpdateContext<'a> {
     /// This function will process only central chunk, but it will also access the surrounding
     /// chunks and in some cases modify them (e.g. sand falling)
+    ///
+    /// Only cells in [`Chunk::update_rect`] are processed, cells changed during the update mark
+    /// their surroundings to be processed on the next one.
     pub fn process(&mut self) {
-        self.center.set_should_update(false);
+        self.center.reset_changed_rect();
+
+        // pass the part outside of the chunk to the neighbors, the rest is left in the center
+        let rect = self.center.take_update_rect();
+        self.mark_rect_for_update(rect);
+        let rect = self.center.take_update_rect();
+
+        self.transfer_heat(rect);
 
-        let update_order_mask = ::rand::random::<u32>();
-        let chunk_area_mask = CHUNK_AREA - 1;
+        // randomize order, for the whole chunk it's the same as iterating `index ^ mask`
+        let update_order_mask = self.update_seed as usize & (CHUNK_AREA - 1);
+        let mask_x = update_order_mask % CHUNK_SIZE;
+        let mask_y = update_order_mask / CHUNK_SIZE;
 
-        for index in 0..CHUNK_AREA {
-            // randomize index
-            let index = (index ^ update_order_mask as usize) & chunk_area_mask;
+        for row in 0..CHUNK_SIZE {
+            let y = row ^ mask_y;
+            if !(rect.min_y..rect.max_y).contains(&(y as i16)) {
+                continue;
+            }
 
-            self.update_cell(index);
+            for column in 0..CHUNK_SIZE {
+                let x = column ^ mask_x;
+                if (rect.min_x..rect.max_x).contains(&(x as i16)) {
+                    self.update_cell(x + y * CHUNK_SIZE);
+                }
+            }
         }
 
         if !self.center.particles.is_empty() {
-            self.center.set_should_redraw(true);
-            self.center.set_should_update(true);
+            self.center.mark_changed();
         }
         for particle_index in (0..self.center.particles.len()).rev() {
             self.update_particle(particle_index);
         }
+    }
+
+    /// Mark cells at most `radius` cells away from `pos` to be processed on the next update
+    #[inline(always)]
+    fn mark_for_update(&mut self, pos: AbsoluteCellPos, radius: i16) {
+        let cell_pos = pos.cell_pos();
+        let (x_offset, y_offset) = pos.side.chunk_pos_offset();
+        let rect = DirtyRect::around(
+            cell_pos.x as i16 + x_offset,
+            cell_pos.y as i16 + y_offset,
+            radius,
+        );
+
+        if rect.intersect(DirtyRect::CHUNK) == rect {
+            self.center.mark_for_update(rect);
+        } else {
+            self.mark_rect_for_update(rect);
+        }
+    }
+
+    /// Split `rect` (relative to the central chunk) between the chunks. Parts beyond the update
+    /// region are kept by the outer chunks, they pass them further when updated.
+    fn mark_rect_for_update(&mut self, rect: DirtyRect) {
+        if rect.is_empty() {
+            return;
+        }
+
+        let size = CHUNK_SIZE as i16;
+        let area = |horizontal, vertical| {
+            let (min_x, max_x) = match horizontal {
+                HorizontalSide::Left => (i16::MIN, 0),
+                HorizontalSide::Center => (0, size),
+                HorizontalSide::Right => (size, i16::MAX),
+            };
+            let (min_y, max_y) = match vertical {
+                VerticalSide::Bottom => (i16::MIN, 0),
+                VerticalSide::Center => (0, size),
+                VerticalSide::Top => (size, i16::MAX),
+            };
+
+            DirtyRect {
+                min_x,
+                min_y,
+                max_x,
+                max_y,
+            }
+        };
+
+        for horizontal in [
+            HorizontalSide::Left,
+            HorizontalSide::Center,
+            HorizontalSide::Right,
+        ] {
+            for vertical in [
+                VerticalSide::Bottom,
+                VerticalSide::Center,
+                VerticalSide::Top,
+            ] {
+                let part = rect.intersect(area(horizontal, vertical));
+                if part.is_empty() {
+                    continue;
+                }
+
+                let side = Side {
+                    horizontal,
+                    vertical,
+                };
+                let (x_offset, y_offset) = side.chunk_pos_offset();
+                self.get_chunk_mut(side)
+                    .mark_for_update(part.translate(-x_offset, -y_offset));
+            }
+        }
+    }
+
+    /// Exchange heat of every cell of `rect` with its right and top neighbors. Cells on the left
+    /// and bottom borders also exchange heat with neighbor chunks, so heat flows across chunk
+    /// borders no matter which of the chunks is updated. Temperatures of cells far from the
+    /// changed ones are settled, so they are skipped.
+    fn transfer_heat(&mut self, rect: DirtyRect) {
+        if !self.cells_template.has_heat_transfer() || rect.is_empty() {
+            return;
+        }
+
+        for y in rect.min_y as usize..rect.max_y as usize {
+            for x in rect.min_x as usize..rect.max_x as usize {
+                let index = x + y * CHUNK_SIZE;
+
+                // every pair is exchanged once, by the chunk of its left or bottom cell
+                self.exchange_heat(index, RelativePos::new(1, 0));
+                self.exchange_heat(index, RelativePos::new(0, 1));
+                if x == 0 {
+                    self.request_heat_exchange(index, RelativePos::new(-1, 0));
+                }
+                if y == 0 {
+                    self.request_heat_exchange(index, RelativePos::new(0, -1));
+                }
+            }
+        }
+    }
+
+    /// Temperatures of the cell and its neighbor after exchanging heat, `None` if they don't change
+    #[inline(always)]
+    fn exchanged_heat(
+        &self,
+        a_pos: AbsoluteCellPos,
+        b_pos: AbsoluteCellPos,
+    ) -> Option<(Temperature, Temperature)> {
+        let a_meta = self.cells_template.get_cell_meta(self.get_cell_id(a_pos));
+        let b_meta = self.cells_template.get_cell_meta(self.get_cell_id(b_pos));
+        let mut a_temperature = self.get_temperature(a_pos);
+        let mut b_temperature = self.get_temperature(b_pos);
+
+        let is_changed = exchange_heat(
+            &mut a_temperature,
+            a_meta.heat_capacity,
+            &mut b_temperature,
+            b_meta.heat_capacity,
+            a_meta.thermal_conductivity.min(b_meta.thermal_conductivity),
+        );
 
-        // If chunk updated next frame we need to update surrounding chunks
-        if self.center.should_update_neighbor() {
-            self.bottom.set_should_update(true);
-            self.top.set_should_update(true);
-            self.left.set_should_update(true);
-            self.right.set_should_update(true);
-            self.left_top.set_should_update(true);
-            self.right_top.set_should_update(true);
-            self.left_bottom.set_should_update(true);
-            self.right_bottom.set_should_update(true);
+        is_changed.then_some((a_temperature, b_temperature))
+    }
+
+    /// Both cells are marked for update if temperatures were changed, so heat keeps flowing until
+    /// temperatures are settled
+    #[inline(always)]
+    fn exchange_heat(&mut self, cell_index: usize, neighbor: RelativePos) {
+        let a_pos = AbsoluteCellPos::central(cell_index);
+        let b_pos = get_absolute_cell_pos(cell_index, neighbor);
+
+        if let Some((a_temperature, b_temperature)) = self.exchanged_heat(a_pos, b_pos) {
+            // temperature change is not a cell update, so `last_update` is kept
+            self.center
+                .set_temperature_by_index(cell_index, a_temperature);
+            self.get_chunk_mut(b_pos.side)
+                .set_temperature_by_index(b_pos.index, b_temperature);
+
+            let radius = self.cells_template.update_radius;
+            self.mark_for_update(a_pos, radius);
+            self.mark_for_update(b_pos, radius);
+        }
+    }
+
+    /// Pair with the cell of the left or bottom neighbor chunk is exchanged by that chunk, mark
+    /// the neighbor cell for update if heat would flow, so the chunk processes it even if it's idle
+    #[inline(always)]
+    fn request_heat_exchange(&mut self, cell_index: usize, neighbor: RelativePos) {
+        let a_pos = AbsoluteCellPos::central(cell_index);
+        let b_pos = get_absolute_cell_pos(cell_index, neighbor);
+
+        if self.exchanged_heat(a_pos, b_pos).is_some() {
+            self.mark_for_update(b_pos, self.cells_template.update_radius);
         }
     }
 
@@ -145,83 +347,366 @@ impl<'a> ChunkUpdateContext<'a> {
     fn update_particle(&mut self, particle_index: usize) {
         debug_assert!(particle_index < self.center.particles.len());
 
-        // TODO place technical cell placeholder on particle position to avoid conflict with other
-        // particles and cells (e.g. sand falling on top of the particle)
-
         let particle = &mut self.center.particles[particle_index];
+        if particle.last_update == self.current_tick {
+            return;
+        }
+        particle.last_update = self.current_tick;
+        let (start_x, start_y) = particle.in_chunk_pos.cell_cords();
+
+        let particle_meta = self.cells_template.get_cell_meta(particle.cell_id);
+        particle.update_pos(self.delta_time, particle_meta.particle_max_speed);
+        let mut particle = *particle;
+
+        // particles are always inside the chunk and move at most `PARTICLE_MAX_STEP` cells, so
+        // both positions are in the update region
+        let start_pos = AbsoluteCellPos::from_cords(start_x as i16, start_y as i16);
+
+        let lifetime = particle_meta.particle_lifetime;
+        if lifetime != 0 && particle.age > lifetime {
+            // faded out
+            self.center.particles.swap_remove(particle_index);
+            self.uncover_cell(start_pos, particle.covered_id);
+            return;
+        }
 
-        let start_cell_pos = particle.get_cell_pos().unwrap_or_else(|| {
-            panic!(
-                "Particle is outside of the chunk. age: {}, pos: {:?}",
-                particle.age, particle.in_chunk_pos
-            )
-        });
-
-        particle.update_pos(self.delta_time);
-
-        let mut prev_pos = AbsoluteCellPos::central(start_cell_pos.to_index());
-
-        let target_poss = AbsoluteCellPos::from_vec(particle.in_chunk_pos);
+        let (target_x, target_y) = particle.in_chunk_pos.cell_cords();
+        let target_poss = AbsoluteCellPos::from_cords(target_x as i16, target_y as i16);
 
-        if prev_pos == target_poss {
+        if start_pos == target_poss {
             // particle didn't move
             return;
         }
 
+        // free the cell, so the particle doesn't collide with its own placeholder
+        self.uncover_cell(start_pos, particle.covered_id);
+
+        let mut prev_pos = start_pos;
         while prev_pos != target_poss {
             let next_pos = prev_pos.move_towards(target_poss);
 
-            let cell = self.get_cell(next_pos);
-            let cell_meta = self.cells_template.get_cell_meta(cell.id);
+            // placeholders of other particles are not replaceable either
+            if !self.is_free_for_particle(next_pos) {
+                // stay in the last free cell, keeping the position inside the cell
+                let (prev_x, prev_y) = prev_pos.to_cord();
+                particle.in_chunk_pos = particle
+                    .in_chunk_pos
+                    .translate_cells(prev_x as i32 - target_x, prev_y as i32 - target_y);
+
+                if !self.collide_particle(&mut particle, prev_pos, next_pos, start_pos.index) {
+                    self.center.particles.swap_remove(particle_index);
+                    return;
+                }
+                break;
+            }
+
+            prev_pos = next_pos;
+        }
+
+        self.cover_cell(&mut particle, prev_pos);
 
-            let is_collided = !cell_meta.replaceable_by_particles;
+        if prev_pos.is_in_central() {
+            self.center.particles[particle_index] = particle;
+        } else {
+            // if particle is outside of the central chunk we need to move it to the new chunk
+            self.center.particles.swap_remove(particle_index);
+            self.push_particle(particle, prev_pos);
+        }
+    }
 
-            if is_collided {
-                // replace particle with cell if collided
-                let particle = self.center.particles.swap_remove(particle_index);
-                let particle_cell = self.cells_template.get_cell_meta(particle.cell_id);
-                self.set_cell(prev_pos, particle_cell.init());
+    /// Bounce the particle at `pos` off the obstacle at `obstacle_pos`, it may splash fragments
+    /// around. Returns `false` if the particle is gone: it settled into the cell at `pos` or it's a
+    /// fragment. Random values are drawn from the cell at `random_index` of the central chunk.
+    fn collide_particle(
+        &mut self,
+        particle: &mut Particle,
+        pos: AbsoluteCellPos,
+        obstacle_pos: AbsoluteCellPos,
+        random_index: usize,
+    ) -> bool {
+        if particle.is_fragment {
+            return false;
+        }
 
-                return;
+        let (x, y) = pos.to_cord();
+        let (obstacle_x, obstacle_y) = obstacle_pos.to_cord();
+        let mut hit_x = obstacle_x != x;
+        let mut hit_y = obstacle_y != y;
+        if hit_x && hit_y {
+            // diagonal move, the obstacle is a wall or a floor if only one of them is blocked,
+            // otherwise it's a corner
+            let is_wall = !self.is_free_for_particle(AbsoluteCellPos::from_cords(obstacle_x, y));
+            let is_floor = !self.is_free_for_particle(AbsoluteCellPos::from_cords(x, obstacle_y));
+            if is_wall != is_floor {
+                hit_x = is_wall;
+                hit_y = is_floor;
             }
+        }
 
-            prev_pos = next_pos;
+        let particle_meta = self.cells_template.get_cell_meta(particle.cell_id);
+        if particle_meta.particle_splash_chance > 0.0
+            && random_fraction(self.center.get_random_value(random_index))
+                < particle_meta.particle_splash_chance
+        {
+            self.splash_particle(particle, pos, hit_x, hit_y, random_index);
         }
 
-        // if particle is outside of the central chunk we need to move it to the new chunk
-        if !target_poss.is_in_central() {
-            let mut particle = self.center.particles.swap_remove(particle_index);
-            particle.in_chunk_pos -= target_poss.side.chunk_pos_offset_vec();
+        particle.bounce(
+            hit_x,
+            hit_y,
+            particle_meta.particle_restitution,
+            particle_meta.particle_friction,
+        );
 
-            if particle.get_cell_pos().is_none() {
-                // TODO fix this: some time particles has coordinates right on the chunk boundary
-                // (e.g. x = 128.0). This is temporary fix to avoid panic.
-                println!(
-                    "!!!!!!!!!! BAD PARTICLE POSITION age: {}, pos: {:?}, target_pos: {:?}",
-                    particle.age, particle.in_chunk_pos, target_poss
-                );
-                particle.in_chunk_pos = target_poss.cell_pos().to_vec();
+        if particle.should_settle() {
+            self.set_cell(pos, particle_meta.init());
+            return false;
+        }
+
+        true
+    }
+
+    /// Spawn fragments of the particle at `pos` which hit an obstacle. Fragments are reflected
+    /// off it slower and scattered, each one starts in the free cell next to `pos` it flies to.
+    fn splash_particle(
+        &mut self,
+        particle: &Particle,
+        pos: AbsoluteCellPos,
+        hit_x: bool,
+        hit_y: bool,
+        random_index: usize,
+    ) {
+        const CHUNK_SIZE_I: i16 = CHUNK_SIZE as i16;
+
+        let particle_meta = self.cells_template.get_cell_meta(particle.cell_id);
+        let (x, y) = pos.to_cord();
+
+        for _ in 0..particle_meta.particle_splash_count {
+            let random_value = self.center.get_random_value(random_index);
+            let speed = 0.25 + 0.5 * random_fraction(random_value);
+            let angle = (random_fraction(random_value >> 24) - 0.5) * std::f32::consts::FRAC_PI_2;
+
+            let mut fragment = *particle;
+            fragment.bounce(hit_x, hit_y, 1.0, 0.0);
+            fragment.vel = Vec2::from_angle(angle).rotate(fragment.vel) * speed;
+            fragment.age = 0;
+            fragment.is_fragment = true;
+
+            let direction = fragment.vel.normalize_or_zero().round();
+            let fragment_x = x + direction.x as i16;
+            let fragment_y = y + direction.y as i16;
+            let is_in_region = |cord: i16| cord > -CHUNK_SIZE_I && cord < CHUNK_SIZE_I * 2;
+            if direction == Vec2::ZERO || !is_in_region(fragment_x) || !is_in_region(fragment_y) {
+                continue;
             }
 
-            self.get_chunk_mut(target_poss.side)
-                .particles
-                .push(particle);
+            let fragment_pos = AbsoluteCellPos::from_cords(fragment_x, fragment_y);
+            if !self.is_free_for_particle(fragment_pos) {
+                continue;
+            }
+
+            // center of the cell
+            fragment.in_chunk_pos =
+                ParticlePos::from_cell_cords(fragment_x as i32, fragment_y as i32)
+                    .translate(Vec2::splat(0.5));
+            self.cover_cell(&mut fragment, fragment_pos);
+            self.push_particle(fragment, fragment_pos);
+        }
+    }
+
+    #[inline(always)]
+    fn is_free_for_particle(&self, pos: AbsoluteCellPos) -> bool {
+        self.cells_template
+            .get_cell_meta(self.get_cell_id(pos))
+            .replaceable_by_particles
+    }
+
+    /// Replace the cell at `pos` with [`CellsTemplate::particle_placeholder`], the cell is kept in
+    /// [`Particle::covered_id`]
+    fn cover_cell(&mut self, particle: &mut Particle, pos: AbsoluteCellPos) {
+        let mut placeholder = self.get_cell(pos);
+        particle.covered_id = placeholder.id;
+        placeholder.id = self.cells_template.particle_placeholder;
+        self.set_cell(pos, placeholder);
+    }
+
+    /// Add the particle to the chunk containing `pos`, the particle position is relative to the
+    /// central chunk
+    fn push_particle(&mut self, mut particle: Particle, pos: AbsoluteCellPos) {
+        let (offset_x, offset_y) = pos.side.chunk_pos_offset();
+        particle.in_chunk_pos = particle
+            .in_chunk_pos
+            .translate_cells(-offset_x as i32, -offset_y as i32);
+        debug_assert!(particle.get_cell_pos().is_some());
+
+        self.get_chunk_mut(pos.side).particles.push(particle);
+    }
+
+    /// Turn the cell at `offset` from the current one into a particle, see
+    /// [`CellRule::LaunchParticle`]
+    fn launch_particle(
+        &mut self,
+        cell_index: usize,
+        offset: RelativePos,
+        vel: RelativeVelocity,
+        spread: u16,
+    ) -> bool {
+        let pos = get_absolute_cell_pos(cell_index, offset);
+        let cell = self.get_cell(pos);
+        let cell_meta = self.cells_template.get_cell_meta(cell.id);
+        if cell_meta.replaceable_by_particles || cell.id == self.cells_template.particle_placeholder
+        {
+            return false;
+        }
+
+        let mut vel = Vec2::new(vel.x as f32, vel.y as f32);
+        if spread != 0 {
+            let random_value = self.center.get_random_value(cell_index);
+            let range = spread as u64 * 2 + 1;
+            vel.x += (random_value % range) as f32 - spread as f32;
+            vel.y += ((random_value >> 32) % range) as f32 - spread as f32;
+        }
+
+        let (x, y) = pos.to_cord();
+        let mut particle = Particle {
+            vel,
+            in_chunk_pos: ParticlePos::from_cell_cords(x as i32, y as i32),
+            age: 0,
+            // starts moving on the next tick, wherever it lands
+            last_update: self.current_tick,
+            color: cell_meta.color.calculate(cell),
+            gravity: cell_meta.particle_gravity,
+            cell_id: cell.id,
+            covered_id: 0,
+            is_fragment: false,
+        };
+
+        self.set_cell(pos, self.cells_template.cells[0].init());
+        self.cover_cell(&mut particle, pos);
+        self.push_particle(particle, pos);
+
+        true
+    }
+
+    /// Restore the cell covered by a particle placeholder, unless the placeholder was already
+    /// replaced (e.g. by a rule)
+    fn uncover_cell(&mut self, pos: AbsoluteCellPos, covered_id: CellId) {
+        let mut cell = self.get_cell(pos);
+        if cell.id == self.cells_template.particle_placeholder {
+            cell.id = covered_id;
+            self.set_cell(pos, cell);
         }
     }
 
     #[inline(always)]
     fn update_cell(&mut self, cell_index: usize) {
-        let cell = self.center.get_by_index(cell_index);
+        let mut cell = self.center.get_by_index(cell_index);
+        let cell_config = cell.meta(self.cells_template);
+
+        if cell_config.count_age && cell.update_age(self.current_tick) {
+            // don't redraw the chunk, only cells reading the age have to be processed every tick
+            self.center.set_cell(CellPos::from_index(cell_index), cell);
+            if cell_config.age_is_read {
+                self.mark_for_update(
+                    AbsoluteCellPos::central(cell_index),
+                    self.cells_template.update_radius,
+                );
+            }
+        }
+
+        if cell_config
+            .color
+            .init_cell(&mut cell, || self.center.get_random_value(cell_index))
+        {
+            self.center.set_cell(CellPos::from_index(cell_index), cell);
+            self.center.mark_cell_changed(cell_index);
+        }
+
         if cell.last_update == self.current_tick {
+            // cell was already updated this tick or created at it (e.g. at tick 0), process it on
+            // the next one
+            self.mark_for_update(AbsoluteCellPos::central(cell_index), 0);
             return;
         }
-        let cell_config = cell.meta(self.cells_template);
 
-        self.try_apply_rule(
-            &cell_config.rule,
-            cell_index,
-            RelativeTransformation::default(),
-        );
+        if self.apply_movement(cell_index, cell_config) {
+            return;
+        }
+
+        match self.rule_evaluator {
+            RuleEvaluator::Tree => {
+                self.try_apply_rule(
+                    &cell_config.rule,
+                    cell_index,
+                    RelativeTransformation::default(),
+                );
+            }
+            RuleEvaluator::Bytecode => {
+                self.run_program(&cell_config.program, cell_index);
+            }
+        }
+    }
+
+    /// Apply built-in [`CellMovement`] of the cell. Returns `true` if cell was moved.
+    #[inline(always)]
+    fn apply_movement(&mut self, cell_index: usize, cell_meta: &CellMeta) -> bool {
+        let (vertical, spread) = match cell_meta.movement {
+            CellMovement::Solid | CellMovement::Empty => return false,
+            CellMovement::Powder => (-1, false),
+            CellMovement::Liquid => (-1, true),
+            CellMovement::Gas => (1, true),
+        };
+
+        let side = if self.center.get_random_value(cell_index) & 1 == 0 {
+            1
+        } else {
+            -1
+        };
+        let density = cell_meta.density;
+
+        self.try_displace(cell_index, RelativePos::new(0, vertical), density, vertical)
+            || self.try_displace(
+                cell_index,
+                RelativePos::new(side, vertical),
+                density,
+                vertical,
+            )
+            || self.try_displace(
+                cell_index,
+                RelativePos::new(-side, vertical),
+                density,
+                vertical,
+            )
+            || spread
+                && (self.try_displace(cell_index, RelativePos::new(side, 0), density, vertical)
+                    || self.try_displace(cell_index, RelativePos::new(-side, 0), density, vertical))
+    }
+
+    /// Swap the cell with the one at `offset` if it's lighter (for falling cells, `vertical` is -1)
+    /// or heavier (for rising cells, `vertical` is 1).
+    #[inline(always)]
+    fn try_displace(
+        &mut self,
+        cell_index: usize,
+        offset: RelativePos,
+        density: i16,
+        vertical: i8,
+    ) -> bool {
+        let pos = get_absolute_cell_pos(cell_index, offset);
+        let target_meta = self.cells_template.get_cell_meta(self.get_cell_id(pos));
+
+        let can_displace = target_meta.movement.is_displaceable()
+            && if vertical < 0 {
+                target_meta.density < density
+            } else {
+                target_meta.density > density
+            };
+
+        if can_displace {
+            self.swap_cells(AbsoluteCellPos::central(cell_index), pos);
+        }
+
+        can_displace
     }
 
     fn calc_value(
@@ -234,7 +719,143 @@ impl<'a> ChunkUpdateContext<'a> {
             ConditionArg::Value(v) => *v,
             ConditionArg::Register { pos, register } => {
                 let pos = get_absolute_cell_pos(cell_index, pos.transform(transformation));
-                self.get_cell(pos).registers[*register as usize]
+                self.get_register(pos, *register as usize)
+            }
+            ConditionArg::Op { op, a, b } => op.apply(
+                self.calc_value(a, cell_index, transformation),
+                self.calc_value(b, cell_index, transformation),
+            ),
+        }
+    }
+
+    #[inline(always)]
+    fn calc_operand(&self, program: &RuleProgram, operand: Operand, cell_index: usize) -> u32 {
+        match operand {
+            Operand::Value(value) => value,
+            Operand::Register { pos, register } => {
+                let pos = get_absolute_cell_pos(cell_index, pos);
+                self.get_register(pos, register as usize)
+            }
+            Operand::Expr(index) => self.calc_value(
+                &program.exprs[index as usize],
+                cell_index,
+                RelativeTransformation::identity(),
+            ),
+        }
+    }
+
+    /// Bytecode counterpart of [`ChunkUpdateContext::try_apply_rule`]
+    fn run_program(&mut self, program: &RuleProgram, cell_index: usize) -> bool {
+        let mut address = 0;
+        let mut flag = false;
+
+        loop {
+            let op = program.ops[address];
+            address += 1;
+
+            match op {
+                RuleOp::Jump(target) => address = target as usize,
+                RuleOp::JumpIfFalse(target) => {
+                    if !flag {
+                        address = target as usize;
+                    }
+                }
+                RuleOp::JumpIfRandomOdd(target) => {
+                    if self.center.get_random_value(cell_index) & 1 != 0 {
+                        address = target as usize;
+                    }
+                }
+                RuleOp::Return(result) => return result,
+                RuleOp::CellIs { pos, cell_id } => {
+                    let pos = get_absolute_cell_pos(cell_index, pos);
+                    flag = self.get_cell_id(pos) == cell_id;
+                }
+                RuleOp::CellIn { pos, list } => {
+                    let pos = get_absolute_cell_pos(cell_index, pos);
+                    flag = program.id_lists[list as usize].contains(&self.get_cell_id(pos));
+                }
+                RuleOp::Compare { op, a, b } => {
+                    let a = self.calc_operand(program, a, cell_index);
+                    let b = self.calc_operand(program, b, cell_index);
+                    flag = op.apply(a, b);
+                }
+                RuleOp::Chance {
+                    numerator,
+                    denominator,
+                } => flag = self.roll_chance(cell_index, numerator, denominator),
+                RuleOp::Temperature { pos, op, value } => {
+                    let pos = get_absolute_cell_pos(cell_index, pos);
+                    flag = op.apply(self.get_temperature(pos), value);
+                }
+                RuleOp::SwapWithIds { pos, list } => {
+                    let pos = get_absolute_cell_pos(cell_index, pos);
+                    flag = program.id_lists[list as usize].contains(&self.get_cell_id(pos));
+                    if flag {
+                        self.swap_cells(AbsoluteCellPos::central(cell_index), pos);
+                    }
+                }
+                RuleOp::InitCell { pos, cell_id } => {
+                    let pos = get_absolute_cell_pos(cell_index, pos);
+                    self.set_cell(pos, Cell::new(self.cells_template, cell_id));
+                }
+                RuleOp::TransformCell { pos, cell_id } => {
+                    let pos = get_absolute_cell_pos(cell_index, pos);
+                    let mut cell = Cell::new(self.cells_template, cell_id);
+                    cell.temperature = self.get_temperature(pos);
+                    self.set_cell(pos, cell);
+                }
+                RuleOp::SwapWith { pos } => {
+                    let pos = get_absolute_cell_pos(cell_index, pos);
+                    self.swap_cells(AbsoluteCellPos::central(cell_index), pos);
+                }
+                RuleOp::LaunchParticle { pos, vel, spread } => {
+                    flag = self.launch_particle(cell_index, pos, vel, spread);
+                }
+                RuleOp::IncrementRegister { pos, register } => {
+                    let pos = get_absolute_cell_pos(cell_index, pos);
+                    self.update_register(pos, register as usize, |value| value.wrapping_add(1));
+                }
+                RuleOp::DecrementRegister { pos, register } => {
+                    let pos = get_absolute_cell_pos(cell_index, pos);
+                    self.update_register(pos, register as usize, |value| value.wrapping_sub(1));
+                }
+                RuleOp::SetRegister {
+                    pos,
+                    register,
+                    value,
+                } => {
+                    let pos = get_absolute_cell_pos(cell_index, pos);
+                    self.update_register(pos, register as usize, |_| value);
+                }
+                RuleOp::SetRegisterRandomMasked {
+                    pos,
+                    register,
+                    mask,
+                } => {
+                    let pos = get_absolute_cell_pos(cell_index, pos);
+                    let value = self.center.get_random_value(cell_index) as u32 & mask;
+                    self.update_register(pos, register as usize, |_| value);
+                }
+                RuleOp::SetRegisterExpr {
+                    pos,
+                    register,
+                    value,
+                } => {
+                    let value = self.calc_operand(program, value, cell_index);
+                    let pos = get_absolute_cell_pos(cell_index, pos);
+                    self.update_register(pos, register as usize, |_| value);
+                }
+                RuleOp::MoveRegister {
+                    source_pos,
+                    source_register,
+                    target_pos,
+                    target_register,
+                } => {
+                    let source_pos = get_absolute_cell_pos(cell_index, source_pos);
+                    let target_pos = get_absolute_cell_pos(cell_index, target_pos);
+                    let value = self.get_register(source_pos, source_register as usize);
+                    self.update_register(target_pos, target_register as usize, |_| value);
+                }
             }
         }
     }
@@ -280,6 +901,14 @@ impl<'a> ChunkUpdateContext<'a> {
                     self.apply_rule_pair(cell_index, rule_b, transformation, rule_a, transformation)
                 }
             }
+            CellRule::Chance {
+                numerator,
+                denominator,
+                rule,
+            } => {
+                self.roll_chance(cell_index, *numerator, *denominator)
+                    && self.try_apply_rule(rule, cell_index, transformation)
+            }
             CellRule::ApplyAndContinue(rule) => {
                 self.try_apply_rule(rule, cell_index, transformation);
                 false
@@ -367,7 +996,15 @@ impl<'a> ChunkUpdateContext<'a> {
             }
             CellRule::InitCell { pos, cell_id } => {
                 let pos = get_absolute_cell_pos(cell_index, pos.transform(transformation));
-                self.set_cell(pos, Cell::new(&self.cells_template, *cell_id));
+                self.set_cell(pos, Cell::new(self.cells_template, *cell_id));
+
+                true
+            }
+            CellRule::TransformCell { pos, cell_id } => {
+                let pos = get_absolute_cell_pos(cell_index, pos.transform(transformation));
+                let mut cell = Cell::new(self.cells_template, *cell_id);
+                cell.temperature = self.get_temperature(pos);
+                self.set_cell(pos, cell);
 
                 true
             }
@@ -377,6 +1014,12 @@ impl<'a> ChunkUpdateContext<'a> {
 
                 true
             }
+            CellRule::LaunchParticle { pos, vel, spread } => self.launch_particle(
+                cell_index,
+                pos.transform(transformation),
+                vel.transform(transformation),
+                *spread,
+            ),
             CellRule::IncrementRegister { register, pos } => {
                 let register_index = *register as usize;
                 assert!(
@@ -384,9 +1027,7 @@ impl<'a> ChunkUpdateContext<'a> {
                     "Register out of bounds"
                 );
                 let pos = get_absolute_cell_pos(cell_index, pos.transform(transformation));
-                let mut cell = self.get_cell(pos);
-                cell.registers[register_index] = cell.registers[register_index].wrapping_add(1);
-                self.set_cell(pos, cell);
+                self.update_register(pos, register_index, |value| value.wrapping_add(1));
 
                 true
             }
@@ -397,9 +1038,7 @@ impl<'a> ChunkUpdateContext<'a> {
                     "Register out of bounds"
                 );
                 let pos = get_absolute_cell_pos(cell_index, pos.transform(transformation));
-                let mut cell = self.get_cell(pos);
-                cell.registers[register_index] = cell.registers[register_index].wrapping_sub(1);
-                self.set_cell(pos, cell);
+                self.update_register(pos, register_index, |value| value.wrapping_sub(1));
 
                 true
             }
@@ -414,9 +1053,7 @@ impl<'a> ChunkUpdateContext<'a> {
                     "Register out of bounds"
                 );
                 let pos = get_absolute_cell_pos(cell_index, pos.transform(transformation));
-                let mut cell = self.get_cell(pos);
-                cell.registers[register_index] = *value;
-                self.set_cell(pos, cell);
+                self.update_register(pos, register_index, |_| *value);
 
                 true
             }
@@ -442,13 +1079,8 @@ impl<'a> ChunkUpdateContext<'a> {
                 let target_pos =
                     get_absolute_cell_pos(cell_index, target_cell.transform(transformation));
 
-                let source_cell = self.get_cell(source_pos);
-                let mut target_cell = self.get_cell(target_pos);
-
-                target_cell.registers[target_register_index] =
-                    source_cell.registers[source_register_index];
-
-                self.set_cell(target_pos, target_cell);
+                let value = self.get_register(source_pos, source_register_index);
+                self.update_register(target_pos, target_register_index, |_| value);
 
                 true
             }
@@ -464,10 +1096,25 @@ impl<'a> ChunkUpdateContext<'a> {
                 );
 
                 let pos = get_absolute_cell_pos(cell_index, pos.transform(transformation));
-                let mut cell = self.get_cell(pos);
-                cell.registers[register_index] =
-                    self.center.get_random_value(cell_index) as u32 & *mask;
-                self.set_cell(pos, cell);
+                let value = self.center.get_random_value(cell_index) as u32 & *mask;
+                self.update_register(pos, register_index, |_| value);
+
+                true
+            }
+            CellRule::SetRegisterExpr {
+                register,
+                value,
+                pos,
+            } => {
+                let register_index = *register as usize;
+                assert!(
+                    register_index < CELL_REGISTERS_COUNT,
+                    "Register out of bounds"
+                );
+
+                let value = self.calc_value(value, cell_index, transformation);
+                let pos = get_absolute_cell_pos(cell_index, pos.transform(transformation));
+                self.update_register(pos, register_index, |_| value);
 
                 true
             }
@@ -511,8 +1158,21 @@ impl<'a> ChunkUpdateContext<'a> {
         self.try_apply_rule(b, cell_index, b_transformation)
     }
 
+    /// Returns `true` with probability `numerator / denominator`
+    #[inline(always)]
+    fn roll_chance(&mut self, cell_index: usize, numerator: u32, denominator: u32) -> bool {
+        let random_value = self.center.get_random_value(cell_index);
+        let success = random_value % (denominator as u64).max(1) < numerator as u64;
+        if !success {
+            // the process is still pending, so try again on the next tick
+            self.mark_for_update(AbsoluteCellPos::central(cell_index), 0);
+        }
+
+        success
+    }
+
     fn check_condition(
-        &self,
+        &mut self,
         condition: &RuleCondition,
         cell_index: usize,
         transformation: RelativeTransformation,
@@ -520,23 +1180,23 @@ impl<'a> ChunkUpdateContext<'a> {
         match condition {
             RuleCondition::RelativeCell { pos, cell_id } => {
                 let pos = get_absolute_cell_pos(cell_index, pos.transform(transformation));
-                let cell = self.get_cell(pos);
-                cell.id == *cell_id
+                let id = self.get_cell_id(pos);
+                id == *cell_id
             }
             RuleCondition::RelativeCellNot { pos, cell_id } => {
                 let pos = get_absolute_cell_pos(cell_index, pos.transform(transformation));
-                let cell = self.get_cell(pos);
-                cell.id != *cell_id
+                let id = self.get_cell_id(pos);
+                id != *cell_id
             }
             RuleCondition::RelativeCellIn { pos, cell_id_list } => {
                 let pos = get_absolute_cell_pos(cell_index, pos.transform(transformation));
-                let cell = self.get_cell(pos);
-                cell_id_list.contains(&cell.id)
+                let id = self.get_cell_id(pos);
+                cell_id_list.contains(&id)
             }
             RuleCondition::RelativeCellNotIn { pos, cell_id_list } => {
                 let pos = get_absolute_cell_pos(cell_index, pos.transform(transformation));
-                let cell = self.get_cell(pos);
-                !cell_id_list.contains(&cell.id)
+                let id = self.get_cell_id(pos);
+                !cell_id_list.contains(&id)
             }
             RuleCondition::And(conditions) => conditions
                 .iter()
@@ -551,14 +1211,15 @@ impl<'a> ChunkUpdateContext<'a> {
                 let value_a = self.calc_value(a, cell_index, transformation);
                 let value_b = self.calc_value(b, cell_index, transformation);
 
-                match op {
-                    ConditionBinaryOp::Eq => value_a == value_b,
-                    ConditionBinaryOp::NotEq => value_a != value_b,
-                    ConditionBinaryOp::Less => value_a < value_b,
-                    ConditionBinaryOp::LessEq => value_a <= value_b,
-                    ConditionBinaryOp::Greater => value_a > value_b,
-                    ConditionBinaryOp::GreaterEq => value_a >= value_b,
-                }
+                op.apply(value_a, value_b)
+            }
+            RuleCondition::Chance {
+                numerator,
+                denominator,
+            } => self.roll_chance(cell_index, *numerator, *denominator),
+            RuleCondition::Temperature { pos, op, value } => {
+                let pos = get_absolute_cell_pos(cell_index, pos.transform(transformation));
+                op.apply(self.get_temperature(pos), *value)
             }
             RuleCondition::Always => true,
         }
@@ -599,17 +1260,56 @@ impl<'a> ChunkUpdateContext<'a> {
         self.get_chunk(pos.side).get_by_index(pos.index)
     }
 
+    #[inline(always)]
+    fn get_cell_id(&self, pos: AbsoluteCellPos) -> CellId {
+        self.get_chunk(pos.side).cells().id(pos.index)
+    }
+
+    #[inline(always)]
+    fn get_temperature(&self, pos: AbsoluteCellPos) -> Temperature {
+        self.get_chunk(pos.side).cells().temperature(pos.index)
+    }
+
+    #[inline(always)]
+    fn get_register(&self, pos: AbsoluteCellPos, register: usize) -> u32 {
+        self.get_chunk(pos.side)
+            .cells()
+            .register(pos.index, register)
+    }
+
     #[inline(always)]
     fn set_cell(&mut self, pos: AbsoluteCellPos, mut cell: Cell) {
         // mark cells as updated
         cell.last_update = self.current_tick;
-        self.get_chunk_mut(pos.side).set_by_index(pos.index, cell);
+        if self.get_chunk_mut(pos.side).set_by_index(pos.index, cell) {
+            self.mark_for_update(pos, self.cells_template.update_radius);
+        }
+    }
+
+    /// Write register of the cell, the value is wrapped to the register type declared by the cell
+    /// (see [`CellMeta::register_mask`])
+    #[inline(always)]
+    fn update_register(
+        &mut self,
+        pos: AbsoluteCellPos,
+        register: usize,
+        update: impl FnOnce(u32) -> u32,
+    ) {
+        let mut cell = self.get_cell(pos);
+        let mask = cell.meta(self.cells_template).register_mask(register as u8);
+        cell.registers[register] = update(cell.registers[register]) & mask;
+        self.set_cell(pos, cell);
     }
 
+    /// Swap two cells, cells occupied by particles are never moved
     #[inline(always)]
     fn swap_cells(&mut self, a: AbsoluteCellPos, b: AbsoluteCellPos) {
         let cell_a = self.get_cell(a);
         let cell_b = self.get_cell(b);
+        let placeholder = self.cells_template.particle_placeholder;
+        if cell_a.id == placeholder || cell_b.id == placeholder {
+            return;
+        }
 
         self.set_cell(a, cell_b);
         self.set_cell(b, cell_a);
@@ -651,11 +1351,6 @@ impl Side {
 
         (x_offset, y_offset)
     }
-
-    fn chunk_pos_offset_vec(self) -> Vec2 {
-        let (x_offset, y_offset) = self.chunk_pos_offset();
-        Vec2::new(x_offset as f32, y_offset as f32)
-    }
 }
 
 /// Absolute cell position in the update region
@@ -686,22 +1381,6 @@ impl AbsoluteCellPos {
         self.side.horizontal == HorizontalSide::Center && self.side.vertical == VerticalSide::Center
     }
 
-    #[inline(always)]
-    fn from_vec(pos: Vec2) -> Self {
-        let x = if pos.x < 0.0 {
-            pos.x.floor() as i16
-        } else {
-            pos.x as i16
-        };
-        let y = if pos.y < 0.0 {
-            pos.y.floor() as i16
-        } else {
-            pos.y as i16
-        };
-
-        Self::from_cords(x, y)
-    }
-
     #[inline(always)]
     fn from_cords(x: i16, y: i16) -> Self {
         const CHUNK_SIZE_I: i16 = CHUNK_SIZE as i16;
@@ -773,6 +1452,12 @@ impl AbsoluteCellPos {
     }
 }
 
+/// Value in `0..1` from the low 24 bits of a random value
+#[inline(always)]
+fn random_fraction(random_value: u64) -> f32 {
+    (random_value & 0xFF_FFFF) as f32 / (1 << 24) as f32
+}
+
 fn get_absolute_cell_pos(cell_index: usize, dst_relative_pos: RelativePos) -> AbsoluteCellPos {
     let cell_pos = CellPos::from_index(cell_index);
 
@@ -869,3 +1554,1146 @@ fn move_towards(value: i16, target: i16) -> i16 {
         value
     }
 }
+
+#[cfg(test)]
+fn age_test_template() -> CellsTemplate {
+    parse_cells_template(
+        r#"(cells: [
+            (label: "Vacuum", color: Plain((0, 0, 0, 0))),
+            (
+                label: "Sand",
+                color: Plain((1, 1, 1, 255)),
+                count_age: true,
+                rule: SwapWithIds(pos: (x: 0, y: -1), match_ids: ["Vacuum"]),
+            ),
+            (
+                label: "Ember",
+                color: Plain((2, 2, 2, 255)),
+                count_age: true,
+                rule: If(
+                    condition: BinaryOp(
+                        op: GreaterEq,
+                        a: Register(pos: (x: 0, y: 0), register: 12),
+                        b: Value(5),
+                    ),
+                    action: InitCell(pos: (x: 0, y: 0), cell_id: "Sand"),
+                    else_action: None,
+                ),
+            ),
+        ])"#,
+    )
+    .unwrap()
+}
+
+#[test]
+fn test_age_counted_across_chunk_borders() {
+    let template = age_test_template();
+    let sand = template.get_cell_meta_by_label("Sand").unwrap();
+
+    let mut world = WorldState::new();
+    // falls through the bottom border of chunk (-1, 0) into chunk (-1, -1)
+    world
+        .set_cell(GlobalCellPos::new(-1, 3), sand.init(), &template)
+        .unwrap();
+
+    for _ in 0..20 {
+        world.update_state(&template).unwrap();
+    }
+
+    // new cells have `last_update` equal to 0, so sand doesn't move on the first tick
+    let pos = GlobalCellPos::new(-1, 3 - 19);
+    let cell = world.get_chunk(pos.chunk).unwrap().get_cell(pos.cell);
+    assert_eq!(cell.id, sand.id);
+    // age is counted starting from the first update
+    assert_eq!(cell.registers[CELL_REGISTER_AGE], 19);
+}
+
+#[test]
+fn test_age_reset_on_init_cell() {
+    let template = age_test_template();
+    let ember = template.get_cell_meta_by_label("Ember").unwrap();
+    let sand = template.get_cell_meta_by_label("Sand").unwrap();
+
+    let mut world = WorldState::new();
+    let pos = GlobalCellPos::new(CHUNK_SIZE as i32 - 1, 0);
+    world.set_cell(pos, ember.init(), &template).unwrap();
+    // keep sand from falling
+    world
+        .set_cell(pos + RelativePos::down(), ember.init(), &template)
+        .unwrap();
+
+    for _ in 0..6 {
+        world.update_state(&template).unwrap();
+    }
+
+    let cell = world.get_chunk(pos.chunk).unwrap().get_cell(pos.cell);
+    assert_eq!(cell.id, sand.id);
+    assert_eq!(cell.registers[CELL_REGISTER_AGE], 0);
+}
+
+#[test]
+fn test_heat_melts_ice_across_chunk_border() {
+    let template = parse_cells_template(
+        r#"(cells: [
+            (label: "Vacuum", color: Plain((0, 0, 0, 0))),
+            (label: "Stone", color: Plain((1, 1, 1, 255)), thermal_conductivity: 128),
+            (
+                label: "Ice",
+                color: Plain((2, 2, 2, 255)),
+                initial_temperature: -10.0,
+                thermal_conductivity: 128,
+                rule: If(
+                    condition: Temperature(pos: (x: 0, y: 0), op: GreaterEq, value: 0.0),
+                    action: TransformCell(pos: (x: 0, y: 0), cell_id: "Water"),
+                    else_action: None,
+                ),
+            ),
+            (label: "Water", color: Plain((3, 3, 3, 255)), thermal_conductivity: 128),
+        ])"#,
+    )
+    .unwrap();
+    let stone = template.get_cell_meta_by_label("Stone").unwrap();
+    let ice = template.get_cell_meta_by_label("Ice").unwrap();
+    let water = template.get_cell_meta_by_label("Water").unwrap();
+
+    let mut world = WorldState::new();
+    // ice in chunk (0, 0) touches hot stone in chunk (1, 0)
+    let ice_pos = GlobalCellPos::new(CHUNK_SIZE as i32 - 1, 5);
+    let stone_pos = GlobalCellPos::new(CHUNK_SIZE as i32, 5);
+    let mut hot_stone = stone.init();
+    hot_stone.temperature = Temperature::from_degrees_int(50);
+    world.set_cell(ice_pos, ice.init(), &template).unwrap();
+    world.set_cell(stone_pos, hot_stone, &template).unwrap();
+
+    for _ in 0..20 {
+        world.update_state(&template).unwrap();
+    }
+
+    let get_cell = |pos: GlobalCellPos| world.get_chunk(pos.chunk).unwrap().get_cell(pos.cell);
+    let melted = get_cell(ice_pos);
+    let stone = get_cell(stone_pos);
+    assert_eq!(melted.id, water.id);
+    // temperature is kept by `TransformCell` and both cells are close to the equilibrium
+    assert!((melted.temperature.degrees() - 20.0).abs() < 1.0);
+    assert!((stone.temperature.degrees() - 20.0).abs() < 1.0);
+}
+
+#[test]
+fn test_heat_exchanged_once_across_chunk_border() {
+    let template = parse_cells_template(
+        r#"(cells: [
+            (label: "Vacuum", color: Plain((0, 0, 0, 0)), thermal_conductivity: 0),
+            (label: "Stone", color: Plain((1, 1, 1, 255)), thermal_conductivity: 32),
+        ])"#,
+    )
+    .unwrap();
+    let stone = template.get_cell_meta_by_label("Stone").unwrap();
+    let chunk = CHUNK_SIZE as i32;
+
+    // pairs of hot and cold stone inside of the chunk, across the x and the y border
+    let pairs = [
+        (GlobalCellPos::new(10, 50), GlobalCellPos::new(11, 50)),
+        (
+            GlobalCellPos::new(chunk - 1, 5),
+            GlobalCellPos::new(chunk, 5),
+        ),
+        (
+            GlobalCellPos::new(50, chunk - 1),
+            GlobalCellPos::new(50, chunk),
+        ),
+    ];
+    let mut world = WorldState::new();
+    for (hot_pos, cold_pos) in pairs {
+        let mut hot = stone.init();
+        hot.temperature = Temperature::from_degrees_int(100);
+        world.set_cell(hot_pos, hot, &template).unwrap();
+        world.set_cell(cold_pos, stone.init(), &template).unwrap();
+    }
+
+    for _ in 0..4 {
+        world.update_state(&template).unwrap();
+    }
+
+    let temperature = |pos: GlobalCellPos| {
+        let cell = world.get_chunk(pos.chunk).unwrap().get_cell(pos.cell);
+        cell.temperature
+    };
+    let (inner_hot, inner_cold) = pairs[0];
+    assert!(temperature(inner_hot) < Temperature::from_degrees_int(100));
+    for (hot_pos, cold_pos) in pairs {
+        assert_eq!(temperature(hot_pos), temperature(inner_hot), "{hot_pos:?}");
+        assert_eq!(
+            temperature(cold_pos),
+            temperature(inner_cold),
+            "{cold_pos:?}"
+        );
+    }
+}
+
+#[test]
+fn test_register_writes_wrapped_to_type() {
+    let template = parse_cells_template(
+        r#"(cells: [
+            (label: "Vacuum", color: Plain((0, 0, 0, 0))),
+            (
+                label: "Counter",
+                color: Plain((1, 1, 1, 255)),
+                registers: [
+                    (name: "ticks", ty: Uint(32)),
+                    (name: "flag", ty: Bool),
+                    (name: "down", ty: Uint(3)),
+                    (name: "copy", ty: Uint(2)),
+                ],
+                rule: TryAll([
+                    IncrementRegister(register: "ticks", pos: (x: 0, y: 0)),
+                    IncrementRegister(register: "flag", pos: (x: 0, y: 0)),
+                    DecrementRegister(register: "down", pos: (x: 0, y: 0)),
+                    SetRegisterExpr(
+                        register: "copy",
+                        value: Register(pos: (x: 0, y: 0), register: "ticks"),
+                        pos: (x: 0, y: 0),
+                    ),
+                ]),
+            ),
+        ])"#,
+    )
+    .unwrap();
+    let counter = template.get_cell_meta_by_label("Counter").unwrap();
+    let register = |name: &str| {
+        let register = counter
+            .registers
+            .iter()
+            .find(|meta| meta.name == name)
+            .unwrap();
+        register.index as usize
+    };
+
+    for evaluator in [RuleEvaluator::Tree, RuleEvaluator::Bytecode] {
+        let mut world = WorldState::new();
+        world.set_rule_evaluator(evaluator);
+        let pos = GlobalCellPos::new(5, 5);
+        world.set_cell(pos, counter.init(), &template).unwrap();
+        for _ in 0..21 {
+            world.update_state(&template).unwrap();
+        }
+
+        let registers = world
+            .get_chunk(pos.chunk)
+            .unwrap()
+            .get_cell(pos.cell)
+            .registers;
+        let ticks = registers[register("ticks")];
+        assert_eq!(ticks, 20, "{evaluator:?}");
+        assert_eq!(registers[register("flag")], ticks % 2, "{evaluator:?}");
+        assert_eq!(
+            registers[register("down")],
+            (8 - ticks % 8) % 8,
+            "{evaluator:?}"
+        );
+        assert_eq!(registers[register("copy")], ticks % 4, "{evaluator:?}");
+    }
+}
+
+#[test]
+fn test_cells_sorted_by_density() {
+    let template = parse_cells_template(
+        r#"(cells: [
+            (label: "Vacuum", color: Plain((0, 0, 0, 0)), movement: Empty),
+            (label: "Stone", color: Plain((1, 1, 1, 255))),
+            (label: "Sand", color: Plain((2, 2, 2, 255)), movement: Powder, density: 1600),
+            (label: "Water", color: Plain((3, 3, 3, 255)), movement: Liquid, density: 1000),
+            (label: "Oil", color: Plain((4, 4, 4, 255)), movement: Liquid, density: 800),
+            (label: "Gas", color: Plain((5, 5, 5, 255)), movement: Gas, density: -10),
+        ])"#,
+    )
+    .unwrap();
+    let get_id = |label: &str| template.get_cell_meta_by_label(label).unwrap().id;
+    let set = |world: &mut WorldState, x: i32, y: i32, label: &str| {
+        let cell = template.get_cell_meta_by_label(label).unwrap().init();
+        world
+            .set_cell(GlobalCellPos::new(x, y), cell, &template)
+            .unwrap();
+    };
+
+    // closed tube one cell wide, filled in reversed order
+    let mut world = WorldState::new();
+    for y in 0..=6 {
+        set(&mut world, -1, y, "Stone");
+        set(&mut world, 1, y, "Stone");
+    }
+    set(&mut world, 0, 0, "Stone");
+    set(&mut world, 0, 6, "Stone");
+    set(&mut world, 0, 1, "Gas");
+    set(&mut world, 0, 2, "Oil");
+    set(&mut world, 0, 3, "Water");
+    set(&mut world, 0, 4, "Sand");
+
+    for _ in 0..20 {
+        world.update_state(&template).unwrap();
+    }
+
+    let column: Vec<_> = (1..=5)
+        .map(|y| {
+            let pos = GlobalCellPos::new(0, y);
+            world.get_chunk(pos.chunk).unwrap().get_cell(pos.cell).id
+        })
+        .collect();
+    let expected: Vec<_> = ["Sand", "Water", "Oil", "Vacuum", "Gas"]
+        .into_iter()
+        .map(get_id)
+        .collect();
+    assert_eq!(column, expected);
+}
+
+#[test]
+fn test_fire_burns_wood_into_ash() {
+    let template = default_cells();
+    let get_id = |label: &str| template.get_cell_meta_by_label(label).unwrap().id;
+    let set = |world: &mut WorldState, x: i32, y: i32, label: &str| {
+        let cell = template.get_cell_meta_by_label(label).unwrap().init();
+        world
+            .set_cell(GlobalCellPos::new(x, y), cell, &template)
+            .unwrap();
+    };
+
+    // block of wood on the stone floor, lit from the side
+    let mut world = WorldState::with_seed(3);
+    for x in -16..20 {
+        set(&mut world, x, 0, CELL_STONE_LABEL);
+    }
+    for y in 1..5 {
+        for x in 0..4 {
+            set(&mut world, x, y, CELL_WOOD_LABEL);
+        }
+    }
+    set(&mut world, -1, 1, CELL_FIRE_LABEL);
+
+    for _ in 0..800 {
+        world.update_state(&template).unwrap();
+    }
+
+    let mut counts = std::collections::HashMap::new();
+    for y in 1..CHUNK_SIZE as i32 {
+        for x in -(CHUNK_SIZE as i32)..CHUNK_SIZE as i32 {
+            let pos = GlobalCellPos::new(x, y);
+            let id = world.get_chunk(pos.chunk).unwrap().get_cell(pos.cell).id;
+            *counts.entry(id).or_insert(0) += 1;
+        }
+    }
+    assert_eq!(counts.get(&get_id(CELL_WOOD_LABEL)), None);
+    assert_eq!(counts.get(&get_id(CELL_FIRE_LABEL)), None);
+    assert_eq!(counts.get(&get_id(CELL_ASH_LABEL)), Some(&16));
+}
+
+#[test]
+fn test_chance_rule_odds() {
+    let template = parse_cells_template(
+        r#"(cells: [
+            (label: "Vacuum", color: Plain((0, 0, 0, 0))),
+            (
+                label: "Quarter",
+                color: Plain((1, 1, 1, 255)),
+                rule: Chance(
+                    numerator: 1,
+                    denominator: 4,
+                    rule: InitCell(pos: (x: 0, y: 0), cell_id: "Done"),
+                ),
+            ),
+            (
+                label: "Rare",
+                color: Plain((2, 2, 2, 255)),
+                rule: If(
+                    condition: Chance(numerator: 1, denominator: 300),
+                    action: InitCell(pos: (x: 0, y: 0), cell_id: "Done"),
+                    else_action: None,
+                ),
+            ),
+            (label: "Done", color: Plain((3, 3, 3, 255))),
+        ])"#,
+    )
+    .unwrap();
+    let get_meta = |label: &str| template.get_cell_meta_by_label(label).unwrap();
+    let done_id = get_meta("Done").id;
+
+    // chunk full of cells, each converted with 1/4 chance per tick
+    let mut world = WorldState::with_seed(1);
+    gen_rect(
+        &mut world,
+        &template,
+        GlobalCellPos::new(0, 0),
+        GlobalCellPos::new(CHUNK_SIZE as i32, CHUNK_SIZE as i32),
+        get_meta("Quarter"),
+    )
+    .unwrap();
+    // cells created at tick 0 are processed from the next one
+    world.update_state(&template).unwrap();
+    world.update_state(&template).unwrap();
+    let chunk = world.get_chunk(ChunkPos::new(0, 0)).unwrap();
+    let done = chunk
+        .cells()
+        .iter()
+        .filter(|cell| cell.id == done_id)
+        .count();
+    let expected = CHUNK_AREA / 4;
+    assert!(done.abs_diff(expected) < expected / 10, "{done}");
+
+    // failed rolls keep the chunk updated until the rare process happens
+    let mut world = WorldState::with_seed(1);
+    let pos = GlobalCellPos::new(3, 3);
+    world
+        .set_cell(pos, get_meta("Rare").init(), &template)
+        .unwrap();
+    for _ in 0..3000 {
+        world.update_state(&template).unwrap();
+    }
+    let chunk = world.get_chunk(pos.chunk).unwrap();
+    assert_eq!(chunk.get_cell(pos.cell).id, done_id);
+}
+
+#[test]
+fn test_register_expressions_spread_amount() {
+    // tanks move half of the difference of amounts in register 0 to the neighbor
+    let template = parse_cells_template(
+        r#"(cells: [
+            (label: "Vacuum", color: Plain((0, 0, 0, 0))),
+            (
+                label: "Tank",
+                color: Plain((1, 1, 1, 255)),
+                rule: SymmetryX(If(
+                    condition: And([
+                        RelativeCell(pos: (x: 1, y: 0), cell_id: "Tank"),
+                        BinaryOp(
+                            op: Greater,
+                            a: Op(
+                                op: Sub,
+                                a: Register(pos: (x: 0, y: 0), register: 0),
+                                b: Register(pos: (x: 1, y: 0), register: 0),
+                            ),
+                            b: Value(1),
+                        ),
+                    ]),
+                    action: TryAll([
+                        SetRegisterExpr(
+                            register: 1,
+                            value: Op(
+                                op: Div,
+                                a: Op(
+                                    op: Sub,
+                                    a: Register(pos: (x: 0, y: 0), register: 0),
+                                    b: Register(pos: (x: 1, y: 0), register: 0),
+                                ),
+                                b: Value(2),
+                            ),
+                            pos: (x: 0, y: 0),
+                        ),
+                        SetRegisterExpr(
+                            register: 0,
+                            value: Op(
+                                op: Add,
+                                a: Register(pos: (x: 1, y: 0), register: 0),
+                                b: Register(pos: (x: 0, y: 0), register: 1),
+                            ),
+                            pos: (x: 1, y: 0),
+                        ),
+                        SetRegisterExpr(
+                            register: 0,
+                            value: Op(
+                                op: Sub,
+                                a: Register(pos: (x: 0, y: 0), register: 0),
+                                b: Register(pos: (x: 0, y: 0), register: 1),
+                            ),
+                            pos: (x: 0, y: 0),
+                        ),
+                    ]),
+                    else_action: None,
+                )),
+            ),
+        ])"#,
+    )
+    .unwrap();
+    let tank = template.get_cell_meta_by_label("Tank").unwrap();
+
+    // row of tanks across the chunk border, all of the amount is in the first one
+    let mut world = WorldState::new();
+    let positions: Vec<_> = (-3..3).map(|x| GlobalCellPos::new(x, 0)).collect();
+    for &pos in &positions {
+        world.set_cell(pos, tank.init(), &template).unwrap();
+    }
+    let mut full_tank = tank.init();
+    full_tank.registers[0] = 600;
+    world.set_cell(positions[0], full_tank, &template).unwrap();
+
+    for _ in 0..200 {
+        world.update_state(&template).unwrap();
+    }
+
+    let amounts: Vec<u32> = positions
+        .iter()
+        .map(|pos| {
+            world
+                .get_chunk(pos.chunk)
+                .unwrap()
+                .get_cell(pos.cell)
+                .registers[0]
+        })
+        .collect();
+    assert_eq!(amounts.iter().sum::<u32>(), 600, "{amounts:?}");
+    for amount in &amounts {
+        assert!(amount.abs_diff(100) <= 5, "{amounts:?}");
+    }
+}
+
+#[test]
+fn test_only_cells_near_changes_are_updated() {
+    let template = parse_cells_template(
+        r#"(cells: [
+            (label: "Vacuum", color: Plain((0, 0, 0, 0)), movement: Empty),
+            (label: "Stone", color: Plain((1, 1, 1, 255))),
+            (label: "Sand", color: Plain((2, 2, 2, 255)), movement: Powder, density: 1500),
+            (
+                label: "Decay",
+                color: Plain((3, 3, 3, 255)),
+                rule: InitCell(pos: (x: 0, y: 0), cell_id: "Vacuum"),
+            ),
+        ])"#,
+    )
+    .unwrap();
+    let get_id = |label: &str| template.get_cell_meta_by_label(label).unwrap().id;
+    let init = |label: &str| template.get_cell_meta_by_label(label).unwrap().init();
+    assert_eq!(template.update_radius, 1);
+
+    // decaying cell placed without marking it for update is never processed
+    let mut world = WorldState::with_seed(1);
+    let chunk_pos = ChunkPos::new(0, 0);
+    let mut chunk = world.take_chunk(chunk_pos, &template).unwrap();
+    let decay_pos = CellPos::new(100, 100);
+    chunk.set_cell(decay_pos, init("Decay"));
+    world.set_chunk(chunk_pos, chunk);
+
+    for x in 4..7 {
+        world
+            .set_cell(GlobalCellPos::new(x, 1), init("Stone"), &template)
+            .unwrap();
+    }
+    world
+        .set_cell(GlobalCellPos::new(5, 10), init("Sand"), &template)
+        .unwrap();
+    let chunk = world.get_chunk(chunk_pos).unwrap();
+    assert_eq!(
+        chunk.update_rect(),
+        DirtyRect {
+            min_x: 3,
+            min_y: 0,
+            max_x: 8,
+            max_y: 12,
+        }
+    );
+
+    for _ in 0..20 {
+        world.update_state(&template).unwrap();
+    }
+    let chunk = world.get_chunk(chunk_pos).unwrap();
+    assert_eq!(chunk.get_cell(CellPos::new(5, 2)).id, get_id("Sand"));
+    assert_eq!(chunk.get_cell(decay_pos).id, get_id("Decay"));
+    assert!(world.chunks().all(|(_, chunk)| chunk.is_idle()));
+
+    // change next to it wakes it up
+    world
+        .set_cell(GlobalCellPos::new(101, 100), init("Stone"), &template)
+        .unwrap();
+    world.update_state(&template).unwrap();
+    let chunk = world.get_chunk(chunk_pos).unwrap();
+    assert_eq!(chunk.get_cell(decay_pos).id, get_id("Vacuum"));
+}
+
+#[test]
+fn test_settled_default_sand_goes_idle() {
+    let template = default_cells();
+    let meta = |label: &str| template.get_cell_meta_by_label(label).unwrap();
+    // shipped sand counts age, but doesn't read it
+    assert!(meta(CELL_SAND_LABEL).count_age && !meta(CELL_SAND_LABEL).age_is_read);
+    assert!(meta(CELL_SMOKE_LABEL).age_is_read);
+
+    let mut world = WorldState::with_seed(1);
+    for x in 0..60 {
+        world
+            .set_cell(
+                GlobalCellPos::new(x, 0),
+                meta(CELL_STONE_LABEL).init(),
+                &template,
+            )
+            .unwrap();
+    }
+    for x in 28..32 {
+        for y in 10..30 {
+            world
+                .set_cell(
+                    GlobalCellPos::new(x, y),
+                    meta(CELL_SAND_LABEL).init(),
+                    &template,
+                )
+                .unwrap();
+        }
+    }
+
+    let mut ticks = 0;
+    while !world.chunks().all(|(_, chunk)| chunk.is_idle()) {
+        world.update_state(&template).unwrap();
+        ticks += 1;
+        assert!(ticks < 200, "sand pile never settles");
+    }
+
+    // age of idle cells catches up when they are processed again
+    let chunk = world.get_chunk(ChunkPos::new(0, 0)).unwrap();
+    let top = (1..CHUNK_SIZE as CellCord)
+        .map(|y| CellPos::new(30, y))
+        .take_while(|&pos| chunk.get_cell(pos).id == meta(CELL_SAND_LABEL).id)
+        .last()
+        .unwrap();
+    let age = chunk.get_cell(top).registers[CELL_REGISTER_AGE];
+    world
+        .set_cell(
+            GlobalCellPos::new(30, top.y as i32 + 1),
+            meta(CELL_STONE_LABEL).init(),
+            &template,
+        )
+        .unwrap();
+    world.update_state(&template).unwrap();
+    world.update_state(&template).unwrap();
+    let chunk = world.get_chunk(ChunkPos::new(0, 0)).unwrap();
+    let new_age = chunk.get_cell(top).registers[CELL_REGISTER_AGE];
+    // counted from the first update of the cell
+    assert!(new_age > age, "{new_age} {age}");
+    assert_eq!(new_age, world.current_tick() - 2);
+}
+
+#[test]
+fn test_age_of_long_idle_cell() {
+    let template = default_cells();
+    let meta = |label: &str| template.get_cell_meta_by_label(label).unwrap();
+
+    let mut world = WorldState::with_seed(1);
+    for x in 0..20 {
+        world
+            .set_cell(
+                GlobalCellPos::new(x, 0),
+                meta(CELL_STONE_LABEL).init(),
+                &template,
+            )
+            .unwrap();
+    }
+    let sand_pos = GlobalCellPos::new(10, 1);
+    world
+        .set_cell(sand_pos, meta(CELL_SAND_LABEL).init(), &template)
+        .unwrap();
+
+    // more ticks than fit the lower 16 bits
+    for _ in 0..70_000 {
+        world.update_state(&template).unwrap();
+    }
+    assert!(world.chunks().all(|(_, chunk)| chunk.is_idle()));
+
+    world
+        .set_cell(
+            GlobalCellPos::new(10, 2),
+            meta(CELL_STONE_LABEL).init(),
+            &template,
+        )
+        .unwrap();
+    world.update_state(&template).unwrap();
+    world.update_state(&template).unwrap();
+    let chunk = world.get_chunk(sand_pos.chunk).unwrap();
+    let cell = chunk.get_cell(sand_pos.cell);
+    assert_eq!(cell.id, meta(CELL_SAND_LABEL).id);
+    assert_eq!(cell.registers[CELL_REGISTER_AGE], world.current_tick() - 2);
+}
+
+#[test]
+fn test_update_rect_crosses_chunk_corner() {
+    let template = default_cells();
+    let sand = template.get_cell_meta_by_label(CELL_SAND_LABEL).unwrap();
+
+    // sand grain falling diagonally through the corner of four chunks
+    let mut world = WorldState::with_seed(1);
+    for x in -3..3 {
+        world
+            .set_cell(
+                GlobalCellPos::new(x, -3),
+                template
+                    .get_cell_meta_by_label(CELL_STONE_LABEL)
+                    .unwrap()
+                    .init(),
+                &template,
+            )
+            .unwrap();
+    }
+    world
+        .set_cell(GlobalCellPos::new(0, 0), sand.init(), &template)
+        .unwrap();
+
+    // the part outside of the chunk is passed to neighbors when the chunk is updated
+    let chunk = world.get_chunk(ChunkPos::new(0, 0)).unwrap();
+    assert_eq!(chunk.update_rect(), DirtyRect::around(0, 0, 1));
+
+    for _ in 0..50 {
+        world.update_state(&template).unwrap();
+    }
+    let pos = GlobalCellPos::new(0, -2);
+    let chunk = world.get_chunk(pos.chunk).unwrap();
+    assert_eq!(chunk.get_cell(pos.cell).id, sand.id);
+
+    // sand counts age, so only cells around the grain are still updated, split between chunks
+    let rects: Vec<_> = world
+        .chunks()
+        .map(|(pos, chunk)| (pos, chunk.update_rect()))
+        .filter(|(_, rect)| !rect.is_empty())
+        .collect();
+    assert!(rects.len() <= 2, "{rects:?}");
+    assert!(
+        rects.iter().map(|(_, rect)| rect.area()).sum::<usize>() <= 9,
+        "{rects:?}"
+    );
+}
+
+#[test]
+fn test_fast_particles_cross_several_chunks() {
+    let template = parse_cells_template(
+        r#"(cells: [
+            (label: "Vacuum", color: Plain((0, 0, 0, 0)), replaceable_by_particles: true),
+            (label: "Spark", color: Plain((255, 200, 0, 255)), particle_gravity: (0, 0),
+                particle_max_speed: 1e9),
+            (label: "Sand", color: Plain((200, 200, 0, 255))),
+        ])"#,
+    )
+    .unwrap();
+    let spark = template.get_cell_meta_by_label("Spark").unwrap();
+    let sand = template.get_cell_meta_by_label("Sand").unwrap();
+
+    // particles can't overlap, so each one starts at its own cell
+    let mut world = WorldState::with_seed(1);
+    let velocities = [
+        (5, Vec2::new(1e9, 0.0)),
+        (15, Vec2::new(-1e9, 1e9)),
+        (25, Vec2::new(f32::INFINITY, 0.0)),
+        (35, Vec2::new(f32::NAN, 1.0)),
+    ];
+    for (y, vel) in velocities {
+        world
+            .add_particle(GlobalCellPos::new(5, y), vel, spark, &template)
+            .unwrap();
+    }
+    // default terminal velocity applies to sand
+    world
+        .add_particle(
+            GlobalCellPos::new(50, 45),
+            Vec2::new(0.0, -1e9),
+            sand,
+            &template,
+        )
+        .unwrap();
+
+    let ticks = 5;
+    for _ in 0..ticks {
+        world.update_state(&template).unwrap();
+    }
+
+    let mut particles: Vec<(Vec2, CellId)> = world
+        .chunks()
+        .flat_map(|(pos, chunk)| {
+            let offset = Vec2::new(pos.x as f32, pos.y as f32) * CHUNK_SIZE as f32;
+            chunk
+                .particles
+                .iter()
+                .map(move |particle| (particle.in_chunk_pos.to_vec() + offset, particle.cell_id))
+        })
+        .collect();
+    particles.sort_by(|a, b| a.0.x.total_cmp(&b.0.x));
+    assert_eq!(particles.len(), velocities.len() + 1);
+
+    // fastest particles move at most `PARTICLE_MAX_STEP` per tick along each axis
+    let max_distance = PARTICLE_MAX_STEP * ticks as f32;
+    let (left, _) = particles[0];
+    assert_eq!(left, Vec2::new(5.0 - max_distance, 15.0 + max_distance));
+    let (right, _) = particles[particles.len() - 1];
+    assert_eq!(right, Vec2::new(5.0 + max_distance, 5.0));
+
+    let (sand_pos, _) = particles.iter().find(|(_, id)| *id == sand.id).unwrap();
+    let sand_distance = 45.0 - sand_pos.y;
+    assert!(sand_distance > 0.0 && sand_distance <= 500.0 * UPDATE_DELTA_TIME * ticks as f32);
+}
+
+#[test]
+fn test_particles_cross_chunk_edges_and_corners_exactly() {
+    let template = parse_cells_template(
+        r#"(cells: [
+            (label: "Vacuum", color: Plain((0, 0, 0, 0)), replaceable_by_particles: true),
+            (label: "Spark", color: Plain((255, 200, 0, 255)), particle_gravity: (0, 0),
+                particle_max_speed: 1e9),
+        ])"#,
+    )
+    .unwrap();
+    let spark = template.get_cell_meta_by_label("Spark").unwrap().id;
+    let last_cell = CHUNK_SIZE as i32 - 1;
+    let ticks = 3;
+
+    let mut particles_checked = 0;
+    for round in 0..16u64 {
+        for (dx, dy) in [
+            (-1, -1),
+            (0, -1),
+            (1, -1),
+            (-1, 0),
+            (1, 0),
+            (-1, 1),
+            (0, 1),
+            (1, 1),
+        ] {
+            let mut random_index = 0;
+            let seed = mix_seed(round, (dx + 1 + (dy + 1) * 3) as u64);
+            let mut random = |max: u64| {
+                random_index += 1;
+                mix_seed(seed, random_index) % max
+            };
+
+            // particles start near the edge or the corner they move to, lanes are far enough apart
+            // to never meet
+            let mut starts = Vec::new();
+            if dx == 0 || dy == 0 {
+                for lane in 0..CHUNK_SIZE as i32 / 2 {
+                    starts.push((random(24) as i32, lane * 2));
+                }
+            } else {
+                for lane in -6..=6 {
+                    let along = random(24) as i32;
+                    starts.push((along + (-4 * lane).max(0), along + (4 * lane).max(0)));
+                }
+            }
+
+            let mut world = WorldState::with_seed(round);
+            let last_update = world.current_tick().wrapping_sub(1);
+            let mut expected = Vec::new();
+            for (index, (distance, lane)) in starts.into_iter().enumerate() {
+                // corners start at given distances from both edges
+                let (distance_x, distance_y) = if dx == 0 {
+                    (lane, distance)
+                } else {
+                    (distance, lane)
+                };
+                let x = if dx > 0 {
+                    last_cell - distance_x
+                } else {
+                    distance_x
+                };
+                let y = if dy > 0 {
+                    last_cell - distance_y
+                } else {
+                    distance_y
+                };
+
+                // fractions on both ends of the cell are the most likely to break
+                let mut fraction = || match random(3) {
+                    0 => 0,
+                    1 => ParticlePos::CELL - 1,
+                    _ => random(ParticlePos::CELL as u64) as i32,
+                };
+                let mut pos = ParticlePos::from_cell_cords(x, y);
+                pos.x += fraction();
+                pos.y += fraction();
+
+                let step = 0.5 + random(126_500) as f32 / 1000.0;
+                let vel = Vec2::new(dx as f32, dy as f32) * step / UPDATE_DELTA_TIME;
+                let delta = ParticlePos::default().translate(vel * UPDATE_DELTA_TIME);
+                expected.push((
+                    pos.x as i64 + delta.x as i64 * ticks,
+                    pos.y as i64 + delta.y as i64 * ticks,
+                ));
+
+                let placeholder = Cell::new(&template, template.particle_placeholder);
+                world
+                    .set_cell(GlobalCellPos::new(x, y), placeholder, &template)
+                    .unwrap();
+                world
+                    .ensure_chunk(ChunkPos::new(0, 0), &template)
+                    .unwrap()
+                    .particles
+                    .push(Particle {
+                        vel,
+                        in_chunk_pos: pos,
+                        age: 0,
+                        last_update,
+                        color: (index as u32).to_le_bytes(),
+                        gravity: Vec2::ZERO,
+                        cell_id: spark,
+                        covered_id: 0,
+                        is_fragment: false,
+                    });
+            }
+
+            for _ in 0..ticks {
+                world.update_state(&template).unwrap();
+            }
+
+            let chunk_size = (CHUNK_SIZE as i64) << PARTICLE_POS_FRACTION_BITS;
+            let mut found = vec![false; expected.len()];
+            let mut crossed = false;
+            for (chunk_pos, chunk) in world.chunks() {
+                for particle in &chunk.particles {
+                    assert!(particle.get_cell_pos().is_some(), "{particle:?}");
+                    let index = u32::from_le_bytes(particle.color) as usize;
+                    let pos = (
+                        chunk_pos.x as i64 * chunk_size + particle.in_chunk_pos.x as i64,
+                        chunk_pos.y as i64 * chunk_size + particle.in_chunk_pos.y as i64,
+                    );
+                    assert_eq!(
+                        pos, expected[index],
+                        "particle {index}, direction {dx} {dy}"
+                    );
+                    assert!(!found[index]);
+                    found[index] = true;
+                    crossed |= chunk_pos == ChunkPos::new(dx, dy);
+                }
+            }
+            assert!(found.iter().all(|&found| found));
+            assert!(crossed, "no particle crossed to {dx} {dy}");
+            particles_checked += found.len();
+        }
+    }
+
+    assert!(particles_checked > 4000);
+}
+
+#[test]
+fn test_particles_occupy_placeholder_cells() {
+    let template = parse_cells_template(
+        r#"(cells: [
+            (label: "Vacuum", color: Plain((0, 0, 0, 0)), replaceable_by_particles: true,
+                movement: Empty),
+            (label: "Spark", color: Plain((255, 200, 0, 255)), particle_gravity: (0, 0)),
+            (label: "Drop", color: Plain((0, 0, 255, 255)),
+                rule: SwapWithIds(pos: (x: 0, y: -1), match_ids: ["Vacuum"])),
+            (label: "Swapper", color: Plain((0, 255, 0, 255)),
+                rule: SwapWith(pos: (x: 0, y: -1))),
+        ])"#,
+    )
+    .unwrap();
+    let get_id = |label: &str| template.get_cell_meta_by_label(label).unwrap().id;
+    let spark = template.get_cell_meta_by_label("Spark").unwrap();
+
+    let mut world = WorldState::with_seed(1);
+    let mut set_cell = |x, y, label| {
+        let cell = Cell::new(&template, get_id(label));
+        world
+            .set_cell(GlobalCellPos::new(x, y), cell, &template)
+            .unwrap();
+    };
+    set_cell(5, 8, "Drop");
+    set_cell(10, 6, "Swapper");
+
+    // hovering particles under the cells, the last one flies into the second one
+    world
+        .add_particle(GlobalCellPos::new(5, 5), Vec2::ZERO, spark, &template)
+        .unwrap();
+    world
+        .add_particle(GlobalCellPos::new(10, 5), Vec2::ZERO, spark, &template)
+        .unwrap();
+    world
+        .add_particle(GlobalCellPos::new(10, 5), Vec2::ZERO, spark, &template)
+        .unwrap();
+    world
+        .add_particle(
+            GlobalCellPos::new(20, 5),
+            Vec2::new(-100.0, 0.0),
+            spark,
+            &template,
+        )
+        .unwrap();
+
+    for _ in 0..10 {
+        world.update_state(&template).unwrap();
+    }
+
+    let chunk = world.get_chunk(ChunkPos::new(0, 0)).unwrap();
+    let get_cell_id = |x, y| chunk.get_cell(CellPos::new(x, y)).id;
+    let placeholder = template.particle_placeholder;
+
+    // cells can't move into particles
+    assert_eq!(get_cell_id(5, 6), get_id("Drop"));
+    assert_eq!(get_cell_id(5, 5), placeholder);
+    assert_eq!(get_cell_id(10, 6), get_id("Swapper"));
+    assert_eq!(get_cell_id(10, 5), placeholder);
+
+    // particles collide with each other, the moving one leaves no placeholders behind
+    assert_eq!(get_cell_id(11, 5), spark.id);
+    assert_eq!(chunk.particles.len(), 2);
+    let placeholders = chunk
+        .cells()
+        .ids()
+        .iter()
+        .filter(|&&id| id == placeholder)
+        .count();
+    assert_eq!(placeholders, 2);
+}
+
+#[test]
+fn test_particle_materials_bounce_slide_splash_and_fade() {
+    let template = parse_cells_template(
+        r#"(cells: [
+            (label: "Vacuum", color: Plain((0, 0, 0, 0)), replaceable_by_particles: true,
+                movement: Empty),
+            (label: "Stone", color: Plain((128, 128, 128, 255))),
+            (label: "Ball", color: Plain((255, 0, 0, 255)), particle_restitution: 0.8,
+                particle_friction: 0.0),
+            (label: "Spark", color: Plain((255, 200, 0, 255)), particle_gravity: (0, 0),
+                particle_lifetime: 5),
+            (label: "Drop", color: Plain((0, 0, 255, 255)), particle_splash_chance: 1.0,
+                particle_splash_count: 4),
+            (label: "Sand", color: Plain((200, 200, 0, 255)), particle_friction: 0.2),
+            (label: "Clay", color: Plain((150, 80, 0, 255))),
+        ])"#,
+    )
+    .unwrap();
+    let meta = |label: &str| template.get_cell_meta_by_label(label).unwrap();
+
+    let mut world = WorldState::with_seed(1);
+    let stone = Cell::new(&template, meta("Stone").id);
+    for x in -(CHUNK_SIZE as i32)..CHUNK_SIZE as i32 * 2 {
+        world
+            .set_cell(GlobalCellPos::new(x, 0), stone, &template)
+            .unwrap();
+    }
+
+    let mut add_particle = |x, y, vel, label| {
+        world
+            .add_particle(GlobalCellPos::new(x, y), vel, meta(label), &template)
+            .unwrap();
+    };
+    add_particle(10, 20, Vec2::new(0.0, -40.0), "Ball");
+    add_particle(30, 20, Vec2::new(10.0, 0.0), "Spark");
+    add_particle(60, 10, Vec2::new(0.0, -20.0), "Drop");
+    add_particle(-100, 10, Vec2::new(40.0, -20.0), "Sand");
+    add_particle(150, 10, Vec2::new(40.0, -20.0), "Clay");
+
+    let particles = |world: &WorldState, label: &str| -> Vec<Particle> {
+        world
+            .chunks()
+            .flat_map(|(_, chunk)| chunk.particles.iter().copied())
+            .filter(|particle| particle.cell_id == meta(label).id)
+            .collect()
+    };
+    let cells = |world: &WorldState, id: CellId| -> Vec<i32> {
+        world
+            .chunks()
+            .flat_map(|(pos, chunk)| {
+                (0..CHUNK_AREA)
+                    .filter(move |&index| chunk.cells().id(index) == id)
+                    .map(move |index| pos.x * CHUNK_SIZE as i32 + (index % CHUNK_SIZE) as i32)
+            })
+            .collect()
+    };
+
+    let mut ball_bounced = false;
+    let mut max_fragments = 0;
+    for _ in 0..300 {
+        world.update_state(&template).unwrap();
+        ball_bounced |= particles(&world, "Ball")
+            .iter()
+            .any(|ball| ball.vel.y > 1.0);
+        max_fragments = max_fragments.max(particles(&world, "Drop").len().saturating_sub(1));
+    }
+
+    assert!(ball_bounced);
+    assert_eq!(
+        particles(&world, "Ball").len() + cells(&world, meta("Ball").id).len(),
+        1
+    );
+
+    // sparks fade out without leaving cells or placeholders
+    assert!(particles(&world, "Spark").is_empty());
+    assert!(cells(&world, meta("Spark").id).is_empty());
+
+    // fragments disappear, only the drop itself settles
+    assert!(max_fragments > 0);
+    assert!(particles(&world, "Drop").is_empty());
+    assert_eq!(cells(&world, meta("Drop").id).len(), 1);
+
+    // sand slides farther than clay stopping where it lands
+    let sand_distance = cells(&world, meta("Sand").id)[0] + 100;
+    let clay_distance = cells(&world, meta("Clay").id)[0] - 150;
+    assert!(
+        sand_distance > clay_distance + 5,
+        "sand {sand_distance}, clay {clay_distance}"
+    );
+
+    let placeholders = cells(&world, template.particle_placeholder).len();
+    let particles_amount: usize = world.chunks().map(|(_, chunk)| chunk.particles.len()).sum();
+    assert_eq!(placeholders, particles_amount);
+}
+
+#[test]
+fn test_launched_particles_land_in_their_chunks() {
+    let template = parse_cells_template(
+        r#"(cells: [
+            (label: "Vacuum", color: Plain((0, 0, 0, 0)), replaceable_by_particles: true,
+                movement: Empty),
+            (label: "Water", color: Plain((0, 0, 255, 255))),
+            (label: "Cannon", color: Plain((100, 100, 100, 255)), rule: TryAll([
+                LaunchParticle(pos: (x: -1, y: -1), vel: (x: -30, y: -30)),
+                LaunchParticle(pos: (x: 0, y: 1), vel: (x: 0, y: 50), spread: 5),
+                // only the mirrored position has water
+                SymmetryX(LaunchParticle(pos: (x: 1, y: 0), vel: (x: 40, y: 0))),
+                // empty cells are not launched
+                LaunchParticle(pos: (x: 1, y: 1), vel: (x: 10, y: 10)),
+            ])),
+        ])"#,
+    )
+    .unwrap();
+    let meta = |label: &str| template.get_cell_meta_by_label(label).unwrap();
+
+    let mut world = WorldState::with_seed(1);
+    let mut set_cell = |x, y, label| {
+        world
+            .set_cell(GlobalCellPos::new(x, y), meta(label).init(), &template)
+            .unwrap();
+    };
+    set_cell(0, 0, "Cannon");
+    set_cell(-1, -1, "Water");
+    set_cell(0, 1, "Water");
+    set_cell(-1, 0, "Water");
+
+    // cells set at the current tick are updated on the next one
+    world.update_state(&template).unwrap();
+    world.update_state(&template).unwrap();
+
+    let particle_at = |world: &WorldState, chunk: ChunkPos, cell: CellPos| -> Particle {
+        let chunk = world.get_chunk(chunk).unwrap();
+        assert_eq!(chunk.get_cell(cell).id, template.particle_placeholder);
+        assert_eq!(chunk.particles.len(), 1, "chunk {chunk:?}");
+        chunk.particles[0]
+    };
+
+    // particles don't move on the tick they are launched
+    let corner = particle_at(&world, ChunkPos::new(-1, -1), CellPos::new(127, 127));
+    assert_eq!(corner.vel, Vec2::new(-30.0, -30.0));
+    assert_eq!(corner.covered_id, meta("Vacuum").id);
+
+    let up = particle_at(&world, ChunkPos::new(0, 0), CellPos::new(0, 1));
+    assert!((-5.0..=5.0).contains(&up.vel.x) && (45.0..=55.0).contains(&up.vel.y));
+
+    let left = particle_at(&world, ChunkPos::new(-1, 0), CellPos::new(127, 0));
+    assert_eq!(left.vel, Vec2::new(-40.0, 0.0));
+    assert_eq!(left.cell_id, meta("Water").id);
+
+    // each particle stays in the chunk containing it and occupies its cell
+    for _ in 0..10 {
+        world.update_state(&template).unwrap();
+    }
+    let mut particles_amount = 0;
+    for (_, chunk) in world.chunks() {
+        for particle in &chunk.particles {
+            let pos = particle.get_cell_pos().unwrap();
+            assert_eq!(chunk.get_cell(pos).id, template.particle_placeholder);
+            particles_amount += 1;
+        }
+    }
+    assert_eq!(particles_amount, 3);
+}
@@ -0,0 +1,443 @@
+use crate::*;
+use eyre::{bail, ensure, WrapErr};
+use glam::Vec2;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+/// First bytes of every save file
+pub const SAVE_MAGIC: [u8; 8] = *b"SANDSAVE";
+/// Version of the save format, increment on every format change
+pub const SAVE_VERSION: u32 = 7;
+
+/// Size of a cell in the save: id, last update, age tick, registers and temperature
+const SAVED_CELL_SIZE: usize = (CELL_REGISTERS_COUNT + 4) * 4;
+/// Limits of the label table, sizes are read from the file before allocating
+const MAX_SAVED_LABELS: u32 = 1 << 16;
+const MAX_SAVED_LABEL_LEN: u32 = 1024;
+
+/// Save format (all numbers are little endian):
+///
+/// - [`SAVE_MAGIC`], version (u32), current tick (u32), seed (u64), world random state (u64)
+/// - label table: amount (u32), then for each cell id length (u32) and UTF-8 label
+/// - chunks: amount (u32), then for each chunk see [`write_chunk`]
+///
+/// Cell ids in the save refer to the label table, so it can be loaded with any template.
+pub fn write_world(
+    writer: &mut impl Write,
+    world: &WorldState,
+    cells_template: &CellsTemplate,
+) -> eyre::Result<()> {
+    writer.write_all(&SAVE_MAGIC)?;
+    write_u32(writer, SAVE_VERSION)?;
+    write_u32(writer, world.current_tick())?;
+    write_u64(writer, world.seed())?;
+    write_u64(writer, world.random_state())?;
+
+    write_label_table(writer, cells_template)?;
+
+    let mut chunks: Vec<_> = world.chunks().collect();
+    chunks.sort_by_key(|(pos, _)| *pos);
+
+    let mut paged_chunks: Vec<_> = world.paged_positions().collect();
+    paged_chunks.sort();
+
+    write_u32(writer, (chunks.len() + paged_chunks.len()) as u32)?;
+    for (pos, chunk) in chunks {
+        write_chunk(writer, pos, chunk)?;
+    }
+    for pos in paged_chunks {
+        let chunk = world.read_paged_chunk(pos, cells_template)?;
+        write_chunk(writer, pos, &chunk)?;
+    }
+
+    Ok(())
+}
+
+/// Read world saved by [`write_world`]. Cells are matched with `cells_template` by label, cells
+/// missing in the template are replaced with the first cell of it (Vacuum).
+pub fn read_world(
+    reader: &mut impl Read,
+    cells_template: &CellsTemplate,
+) -> eyre::Result<WorldState> {
+    let mut magic = [0; SAVE_MAGIC.len()];
+    reader.read_exact(&mut magic)?;
+    ensure!(magic == SAVE_MAGIC, "Not a world save file");
+
+    let version = read_u32(reader)?;
+    if version != SAVE_VERSION {
+        bail!("Unsupported save version {version}, expected {SAVE_VERSION}");
+    }
+
+    let current_tick = read_u32(reader)?;
+    let mut world = WorldState::with_seed(read_u64(reader)?);
+    world.set_current_tick(current_tick);
+    world.set_random_state(read_u64(reader)?);
+
+    let id_map = read_label_table(reader, cells_template)?;
+
+    let chunks_amount = read_u32(reader)?;
+    for _ in 0..chunks_amount {
+        let (pos, chunk) = read_chunk(reader, cells_template, &id_map)
+            .wrap_err_with(|| format!("Failed to read chunk {}", world.len()))?;
+        world.set_chunk(pos, chunk);
+    }
+
+    Ok(world)
+}
+
+pub fn save_world(
+    path: impl AsRef<Path>,
+    world: &WorldState,
+    cells_template: &CellsTemplate,
+) -> eyre::Result<()> {
+    let path = path.as_ref();
+    let file =
+        File::create(path).wrap_err_with(|| format!("Failed to create {}", path.display()))?;
+    let mut writer = BufWriter::new(file);
+
+    write_world(&mut writer, world, cells_template)
+        .and_then(|_| Ok(writer.flush()?))
+        .wrap_err_with(|| format!("Failed to save world to {}", path.display()))
+}
+
+pub fn load_world(
+    path: impl AsRef<Path>,
+    cells_template: &CellsTemplate,
+) -> eyre::Result<WorldState> {
+    let path = path.as_ref();
+    let file = File::open(path).wrap_err_with(|| format!("Failed to open {}", path.display()))?;
+
+    read_world(&mut BufReader::new(file), cells_template)
+        .wrap_err_with(|| format!("Failed to load world from {}", path.display()))
+}
+
+pub fn write_label_table(
+    writer: &mut impl Write,
+    cells_template: &CellsTemplate,
+) -> eyre::Result<()> {
+    ensure!(
+        cells_template.cells.len() <= MAX_SAVED_LABELS as usize,
+        "Too many cells, at most {MAX_SAVED_LABELS} can be saved"
+    );
+    write_u32(writer, cells_template.cells.len() as u32)?;
+    for cell in &cells_template.cells {
+        ensure!(
+            cell.label.len() <= MAX_SAVED_LABEL_LEN as usize,
+            "Label {:?} is too long, at most {MAX_SAVED_LABEL_LEN} bytes can be saved",
+            cell.label
+        );
+        write_u32(writer, cell.label.len() as u32)?;
+        writer.write_all(cell.label.as_bytes())?;
+    }
+
+    Ok(())
+}
+
+/// Read label table and map saved ids to the ids of `cells_template`.
+pub fn read_label_table(
+    reader: &mut impl Read,
+    cells_template: &CellsTemplate,
+) -> eyre::Result<Vec<Option<CellId>>> {
+    let labels_amount = read_u32(reader)?;
+    ensure!(
+        labels_amount <= MAX_SAVED_LABELS,
+        "Label table has {labels_amount} labels, at most {MAX_SAVED_LABELS} are allowed"
+    );
+    let mut id_map = Vec::with_capacity(labels_amount as usize);
+
+    for _ in 0..labels_amount {
+        let label_len = read_u32(reader)?;
+        ensure!(
+            label_len <= MAX_SAVED_LABEL_LEN,
+            "Label is {label_len} bytes long, at most {MAX_SAVED_LABEL_LEN} are allowed"
+        );
+        let mut label = vec![0; label_len as usize];
+        reader.read_exact(&mut label)?;
+        let label = String::from_utf8(label).wrap_err("Invalid cell label")?;
+
+        id_map.push(
+            cells_template
+                .get_cell_meta_by_label(&label)
+                .map(|cell| cell.id),
+        );
+    }
+
+    Ok(id_map)
+}
+
+/// Chunk format: position (2 x i32), cells (id, last update, age tick and registers as u32,
+/// temperature as i32), random state of cells (u64), particles amount (u32) and particles.
+pub fn write_chunk(writer: &mut impl Write, pos: ChunkPos, chunk: &Chunk) -> eyre::Result<()> {
+    write_i32(writer, pos.x)?;
+    write_i32(writer, pos.y)?;
+
+    let mut buffer = Vec::with_capacity(CHUNK_AREA * SAVED_CELL_SIZE);
+    for cell in chunk.cells().iter() {
+        buffer.extend_from_slice(&cell.id.to_le_bytes());
+        buffer.extend_from_slice(&cell.last_update.to_le_bytes());
+        buffer.extend_from_slice(&cell.age_tick.to_le_bytes());
+        for register in cell.registers {
+            buffer.extend_from_slice(&register.to_le_bytes());
+        }
+        buffer.extend_from_slice(&cell.temperature.0.to_le_bytes());
+    }
+    for random_value in chunk.random_state() {
+        buffer.extend_from_slice(&random_value.to_le_bytes());
+    }
+    writer.write_all(&buffer)?;
+
+    write_u32(writer, chunk.particles.len() as u32)?;
+    for particle in &chunk.particles {
+        write_vec2(writer, particle.vel)?;
+        write_i32(writer, particle.in_chunk_pos.x)?;
+        write_i32(writer, particle.in_chunk_pos.y)?;
+        write_u32(writer, particle.age)?;
+        write_u32(writer, particle.last_update)?;
+        writer.write_all(&particle.color)?;
+        write_vec2(writer, particle.gravity)?;
+        write_u32(writer, particle.cell_id)?;
+        write_u32(writer, particle.covered_id)?;
+        writer.write_all(&[particle.is_fragment as u8])?;
+    }
+
+    Ok(())
+}
+
+/// Read chunk written by [`write_chunk`], `id_map` is returned by [`read_label_table`].
+pub fn read_chunk(
+    reader: &mut impl Read,
+    cells_template: &CellsTemplate,
+    id_map: &[Option<CellId>],
+) -> eyre::Result<(ChunkPos, Chunk)> {
+    let pos = ChunkPos::new(read_i32(reader)?, read_i32(reader)?);
+
+    // random state is overwritten below, so seed doesn't matter
+    let mut chunk = Chunk::new(cells_template, 0);
+
+    let mut buffer = vec![0; CHUNK_AREA * SAVED_CELL_SIZE];
+    reader.read_exact(&mut buffer)?;
+    let mut values = buffer
+        .chunks_exact(4)
+        .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()));
+
+    let cells = chunk.cells_mut();
+    for index in 0..CHUNK_AREA {
+        let mut cell = Cell {
+            id: values.next().unwrap(),
+            last_update: values.next().unwrap(),
+            age_tick: values.next().unwrap(),
+            ..Default::default()
+        };
+        for register in &mut cell.registers {
+            *register = values.next().unwrap();
+        }
+        cell.temperature = Temperature(values.next().unwrap() as i32);
+
+        ensure!(
+            (cell.id as usize) < id_map.len(),
+            "Cell id {} is missing in the label table",
+            cell.id
+        );
+        cells.set(index, cell);
+    }
+
+    let mut buffer = vec![0; CHUNK_AREA * 8];
+    reader.read_exact(&mut buffer)?;
+    for (random_value, bytes) in chunk
+        .random_state_mut()
+        .iter_mut()
+        .zip(buffer.chunks_exact(8))
+    {
+        *random_value = u64::from_le_bytes(bytes.try_into().unwrap());
+    }
+
+    let particles_amount = read_u32(reader)?;
+    for _ in 0..particles_amount {
+        let vel = read_vec2(reader)?;
+        let in_chunk_pos = ParticlePos {
+            x: read_i32(reader)?,
+            y: read_i32(reader)?,
+        };
+        let age = read_u32(reader)?;
+        let last_update = read_u32(reader)?;
+        let mut color = [0; 4];
+        reader.read_exact(&mut color)?;
+        let gravity = read_vec2(reader)?;
+        let cell_id = read_u32(reader)?;
+        let covered_id = read_u32(reader)?;
+        let mut is_fragment = [0];
+        reader.read_exact(&mut is_fragment)?;
+        let is_fragment = is_fragment[0] != 0;
+
+        ensure!(
+            (cell_id as usize) < id_map.len() && (covered_id as usize) < id_map.len(),
+            "Particle cell id {cell_id} or {covered_id} is missing in the label table"
+        );
+        ensure!(
+            in_chunk_pos.cell_pos().is_some(),
+            "Particle at {in_chunk_pos:?} is outside of the chunk"
+        );
+
+        chunk.particles.push(Particle {
+            vel,
+            in_chunk_pos,
+            age,
+            last_update,
+            color,
+            gravity,
+            cell_id,
+            covered_id,
+            is_fragment,
+        });
+    }
+
+    chunk.remap_cells(id_map, cells_template);
+
+    Ok((pos, chunk))
+}
+
+fn write_u32(writer: &mut impl Write, value: u32) -> eyre::Result<()> {
+    writer.write_all(&value.to_le_bytes())?;
+    Ok(())
+}
+
+fn write_u64(writer: &mut impl Write, value: u64) -> eyre::Result<()> {
+    writer.write_all(&value.to_le_bytes())?;
+    Ok(())
+}
+
+fn write_i32(writer: &mut impl Write, value: i32) -> eyre::Result<()> {
+    writer.write_all(&value.to_le_bytes())?;
+    Ok(())
+}
+
+fn write_vec2(writer: &mut impl Write, value: Vec2) -> eyre::Result<()> {
+    writer.write_all(&value.x.to_le_bytes())?;
+    writer.write_all(&value.y.to_le_bytes())?;
+    Ok(())
+}
+
+fn read_bytes<const N: usize>(reader: &mut impl Read) -> eyre::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_u32(reader: &mut impl Read) -> eyre::Result<u32> {
+    Ok(u32::from_le_bytes(read_bytes(reader)?))
+}
+
+fn read_u64(reader: &mut impl Read) -> eyre::Result<u64> {
+    Ok(u64::from_le_bytes(read_bytes(reader)?))
+}
+
+fn read_i32(reader: &mut impl Read) -> eyre::Result<i32> {
+    Ok(i32::from_le_bytes(read_bytes(reader)?))
+}
+
+fn read_vec2(reader: &mut impl Read) -> eyre::Result<Vec2> {
+    let x = f32::from_le_bytes(read_bytes(reader)?);
+    let y = f32::from_le_bytes(read_bytes(reader)?);
+    Ok(Vec2::new(x, y))
+}
+
+#[test]
+fn test_world_save_round_trip_with_other_template() {
+    let cells_template = default_cells();
+    let sand = cells_template
+        .get_cell_meta_by_label(CELL_SAND_LABEL)
+        .unwrap();
+    let stone = cells_template
+        .get_cell_meta_by_label(CELL_STONE_LABEL)
+        .unwrap();
+    let water = cells_template
+        .get_cell_meta_by_label(CELL_WATER_LABEL)
+        .unwrap();
+
+    let mut world = WorldState::new();
+    let sand_pos = GlobalCellPos::new(-130, 7);
+    let stone_pos = GlobalCellPos::new(5, -300);
+    let mut sand_cell = sand.init();
+    sand_cell.registers[3] = 0xdead_beef;
+    world
+        .set_cell(sand_pos, sand_cell, &cells_template)
+        .unwrap();
+    world
+        .set_cell(stone_pos, stone.init(), &cells_template)
+        .unwrap();
+    world
+        .add_particle(
+            GlobalCellPos::new(-130, 20),
+            Vec2::new(1.5, -2.0),
+            water,
+            &cells_template,
+        )
+        .unwrap();
+    world.update_state(&cells_template).unwrap();
+
+    let mut save = Vec::new();
+    write_world(&mut save, &world, &cells_template).unwrap();
+
+    // same cells in different order and without stone
+    let other_template = parse_cells_template(
+        r#"(cells: [
+            (label: "Vacuum", color: Plain((0, 0, 0, 0))),
+            (label: "Water", color: Plain((0, 0, 255, 255))),
+            (label: "Sand", color: Plain((255, 255, 0, 255))),
+        ])"#,
+    )
+    .unwrap();
+
+    let loaded = read_world(&mut save.as_slice(), &other_template).unwrap();
+
+    assert_eq!(loaded.current_tick(), world.current_tick());
+    assert_eq!(loaded.len(), world.len());
+
+    let other_sand = other_template
+        .get_cell_meta_by_label(CELL_SAND_LABEL)
+        .unwrap();
+    let loaded_sand = loaded
+        .get_chunk(sand_pos.chunk)
+        .unwrap()
+        .get_cell(sand_pos.cell);
+    assert_eq!(loaded_sand.id, other_sand.id);
+    assert_eq!(loaded_sand.registers[3], 0xdead_beef);
+
+    let loaded_stone = loaded
+        .get_chunk(stone_pos.chunk)
+        .unwrap()
+        .get_cell(stone_pos.cell);
+    assert_eq!(loaded_stone, other_template.cells[0].init());
+
+    let other_water = other_template
+        .get_cell_meta_by_label(CELL_WATER_LABEL)
+        .unwrap();
+    let particles: Vec<_> = loaded
+        .chunks()
+        .flat_map(|(_, chunk)| &chunk.particles)
+        .collect();
+    let saved_particles: Vec<_> = world
+        .chunks()
+        .flat_map(|(_, chunk)| &chunk.particles)
+        .collect();
+    assert_eq!(particles.len(), 1);
+    assert_eq!(particles[0].cell_id, other_water.id);
+    assert_eq!(particles[0].in_chunk_pos, saved_particles[0].in_chunk_pos);
+}
+
+#[test]
+fn test_label_table_sizes_limited() {
+    let cells_template = default_cells();
+
+    // sizes are checked before allocating, so corrupted files don't abort on allocation failure
+    let corrupted = [0xff; 16];
+    let error = read_label_table(&mut corrupted.as_slice(), &cells_template).unwrap_err();
+    assert!(error.to_string().contains("labels"), "{error}");
+
+    let mut long_label = Vec::new();
+    write_u32(&mut long_label, 1).unwrap();
+    write_u32(&mut long_label, u32::MAX).unwrap();
+    let error = read_label_table(&mut long_label.as_slice(), &cells_template).unwrap_err();
+    assert!(error.to_string().contains("bytes long"), "{error}");
+}
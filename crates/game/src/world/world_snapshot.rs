@@ -0,0 +1,115 @@
+use crate::*;
+use eyre::{ensure, WrapErr};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+/// RGBA image of the whole world, rendered without graphics context.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorldSnapshot {
+    pub width: usize,
+    pub height: usize,
+    /// Chunk in the left bottom corner of the image
+    pub min_chunk: ChunkPos,
+    /// Rows go from top to bottom, missing chunks are transparent
+    pub pixels: Vec<[u8; 4]>,
+}
+
+impl WorldSnapshot {
+    /// Render all loaded and paged chunks.
+    pub fn capture(world: &WorldState, cells_template: &CellsTemplate) -> eyre::Result<Self> {
+        let positions: Vec<_> = world
+            .chunks()
+            .map(|(pos, _)| pos)
+            .chain(world.paged_positions())
+            .collect();
+        ensure!(!positions.is_empty(), "World is empty");
+
+        let min_chunk = ChunkPos::new(
+            positions.iter().map(|pos| pos.x).min().unwrap(),
+            positions.iter().map(|pos| pos.y).min().unwrap(),
+        );
+        let max_chunk = ChunkPos::new(
+            positions.iter().map(|pos| pos.x).max().unwrap(),
+            positions.iter().map(|pos| pos.y).max().unwrap(),
+        );
+
+        let width = (max_chunk.x - min_chunk.x + 1) as usize * CHUNK_SIZE;
+        let height = (max_chunk.y - min_chunk.y + 1) as usize * CHUNK_SIZE;
+        let mut snapshot = Self {
+            width,
+            height,
+            min_chunk,
+            pixels: vec![[0; 4]; width * height],
+        };
+
+        let mut chunk_pixels = vec![[0; 4]; CHUNK_AREA];
+        for (pos, chunk) in world.chunks() {
+            chunk.write_pixels(cells_template, &mut chunk_pixels);
+            snapshot.put_chunk(pos, max_chunk.y, &chunk_pixels);
+        }
+        for pos in world.paged_positions() {
+            let chunk = world.read_paged_chunk(pos, cells_template)?;
+            chunk.write_pixels(cells_template, &mut chunk_pixels);
+            snapshot.put_chunk(pos, max_chunk.y, &chunk_pixels);
+        }
+
+        Ok(snapshot)
+    }
+
+    fn put_chunk(&mut self, pos: ChunkPos, max_chunk_y: i32, chunk_pixels: &[[u8; 4]]) {
+        let start_x = (pos.x - self.min_chunk.x) as usize * CHUNK_SIZE;
+        let start_y = (max_chunk_y - pos.y) as usize * CHUNK_SIZE;
+
+        for (row_index, row) in chunk_pixels.chunks_exact(CHUNK_SIZE).enumerate() {
+            let start = (start_y + row_index) * self.width + start_x;
+            self.pixels[start..start + CHUNK_SIZE].copy_from_slice(row);
+        }
+    }
+
+    pub fn save_png(&self, path: impl AsRef<Path>) -> eyre::Result<()> {
+        let path = path.as_ref();
+        let file = File::create(path)
+            .wrap_err_with(|| format!("Failed to create snapshot {}", path.display()))?;
+
+        let mut encoder =
+            png::Encoder::new(BufWriter::new(file), self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(self.pixels.as_flattened())?;
+        writer.finish()?;
+
+        Ok(())
+    }
+}
+
+#[test]
+fn test_snapshot_places_chunks() {
+    let cells_template = default_cells();
+    let stone = cells_template
+        .get_cell_meta_by_label(CELL_STONE_LABEL)
+        .unwrap();
+
+    let mut world = WorldState::with_seed(0);
+    // left bottom cell of the lower chunk and right top cell of the upper one
+    world
+        .set_cell(GlobalCellPos::new(0, 0), stone.init(), &cells_template)
+        .unwrap();
+    let top_right = GlobalCellPos::new(CHUNK_SIZE as i32 * 2 - 1, CHUNK_SIZE as i32 * 2 - 1);
+    world
+        .set_cell(top_right, stone.init(), &cells_template)
+        .unwrap();
+
+    let snapshot = WorldSnapshot::capture(&world, &cells_template).unwrap();
+    assert_eq!(snapshot.width, CHUNK_SIZE * 2);
+    assert_eq!(snapshot.height, CHUNK_SIZE * 2);
+
+    let stone_color = stone.color.calculate(stone.init());
+    let last_row = snapshot.height - 1;
+    assert_eq!(snapshot.pixels[last_row * snapshot.width], stone_color);
+    assert_eq!(snapshot.pixels[snapshot.width - 1], stone_color);
+    // missing chunk is transparent
+    assert_eq!(snapshot.pixels[0], [0; 4]);
+}
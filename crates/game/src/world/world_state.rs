@@ -1,5 +1,5 @@
 use crate::*;
-use macroquad::math::Vec2;
+use glam::Vec2;
 use nohash_hasher::IntMap;
 use rayon::iter::{IntoParallelRefMutIterator, ParallelIterator};
 
@@ -9,42 +9,257 @@ pub const UPDATE_DELTA_TIME: f32 = 1.0 / 20.0;
 pub struct WorldState {
     chunks: IntMap<ChunkPos, Chunk>,
     current_tick: u32,
+    /// Every random decision of the simulation is derived from this seed
+    seed: u64,
+    /// State of the random generator used outside of chunk updates (e.g. particle velocity)
+    random_state: u64,
+    /// Storage for chunks paged out of memory, see [`WorldState::update_residency`]
+    chunk_store: Option<ChunkStore>,
+    rule_evaluator: RuleEvaluator,
+}
+
+impl Default for WorldState {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl WorldState {
+    /// Create world with random seed
     pub fn new() -> Self {
+        Self::with_seed(::rand::random())
+    }
+
+    pub fn with_seed(seed: u64) -> Self {
         Self {
             chunks: Default::default(),
             current_tick: 0,
+            seed,
+            random_state: seed,
+            chunk_store: None,
+            rule_evaluator: RuleEvaluator::default(),
         }
     }
 
+    #[inline(always)]
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// State of the world random generator, see [`WorldState::next_random`]
+    pub fn random_state(&self) -> u64 {
+        self.random_state
+    }
+
+    pub fn set_random_state(&mut self, random_state: u64) {
+        self.random_state = random_state;
+    }
+
+    /// Next value of the world random generator, derived from the world seed
+    pub fn next_random(&mut self) -> u64 {
+        self.random_state = splitmix64(self.random_state);
+        self.random_state
+    }
+
+    /// Create empty chunk for the given position
+    pub fn new_chunk(&self, pos: ChunkPos, cells_template: &CellsTemplate) -> Chunk {
+        Chunk::new(cells_template, chunk_seed(self.seed, pos))
+    }
+
+    #[inline(always)]
+    pub fn rule_evaluator(&self) -> RuleEvaluator {
+        self.rule_evaluator
+    }
+
+    pub fn set_rule_evaluator(&mut self, rule_evaluator: RuleEvaluator) {
+        self.rule_evaluator = rule_evaluator;
+    }
+
+    #[inline(always)]
+    pub fn current_tick(&self) -> u32 {
+        self.current_tick
+    }
+
+    pub fn set_current_tick(&mut self, current_tick: u32) {
+        self.current_tick = current_tick;
+    }
+
+    pub fn chunks(&self) -> impl Iterator<Item = (ChunkPos, &Chunk)> {
+        self.chunks.iter().map(|(&pos, chunk)| (pos, chunk))
+    }
+
     pub fn len(&self) -> usize {
         self.chunks.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    /// Heap memory used by loaded chunks in bytes, see [`Chunk::memory_usage`]
+    pub fn memory_usage(&self) -> usize {
+        self.chunks.values().map(Chunk::memory_usage).sum()
+    }
+
+    /// Amount of loaded cells with registers in the side table, see [`CellStorage`]
+    pub fn cells_with_registers(&self) -> usize {
+        self.chunks
+            .values()
+            .map(|chunk| chunk.cells().cells_with_registers())
+            .sum()
+    }
+
     #[inline(always)]
     pub fn get_chunk(&self, pos: ChunkPos) -> Option<&Chunk> {
         self.chunks.get(&pos)
     }
 
-    pub fn ensure_chunk(&mut self, pos: ChunkPos, cells_template: &CellsTemplate) -> &mut Chunk {
-        self.chunks
-            .entry(pos)
-            .or_insert_with(|| Chunk::new(cells_template))
+    pub fn set_chunk_store(&mut self, chunk_store: ChunkStore) {
+        self.chunk_store = Some(chunk_store);
     }
 
-    pub fn take_chunk(&mut self, pos: ChunkPos, cells_template: &CellsTemplate) -> Chunk {
-        self.chunks
-            .remove(&pos)
-            .unwrap_or_else(|| Chunk::new(cells_template))
+    /// Amount of chunks paged to the [`ChunkStore`]
+    pub fn paged_len(&self) -> usize {
+        self.chunk_store.as_ref().map_or(0, |store| store.len())
+    }
+
+    pub fn paged_positions(&self) -> impl Iterator<Item = ChunkPos> + '_ {
+        self.chunk_store.iter().flat_map(|store| store.positions())
+    }
+
+    /// Read paged chunk without loading it back into the world.
+    pub fn read_paged_chunk(
+        &self,
+        pos: ChunkPos,
+        cells_template: &CellsTemplate,
+    ) -> eyre::Result<Chunk> {
+        self.chunk_store
+            .as_ref()
+            .and_then(|store| store.read(pos, cells_template).transpose())
+            .unwrap_or_else(|| Err(eyre::eyre!("Chunk {pos:?} is not paged")))
+    }
+
+    /// Load chunk from the [`ChunkStore`] if it was paged out.
+    fn load_paged_chunk(
+        &mut self,
+        pos: ChunkPos,
+        cells_template: &CellsTemplate,
+    ) -> eyre::Result<Option<Chunk>> {
+        match &mut self.chunk_store {
+            Some(store) if store.contains(pos) => store.load(pos, cells_template),
+            _ => Ok(None),
+        }
+    }
+
+    /// Get resident chunk or load it if it was paged out. Does not create new chunks.
+    pub fn get_or_load_chunk(
+        &mut self,
+        pos: ChunkPos,
+        cells_template: &CellsTemplate,
+    ) -> eyre::Result<Option<&mut Chunk>> {
+        if !self.chunks.contains_key(&pos) {
+            let Some(chunk) = self.load_paged_chunk(pos, cells_template)? else {
+                return Ok(None);
+            };
+            self.chunks.insert(pos, chunk);
+        }
+
+        Ok(self.chunks.get_mut(&pos))
+    }
+
+    /// Get resident chunk, load it if it was paged out or create new one.
+    pub fn ensure_chunk(
+        &mut self,
+        pos: ChunkPos,
+        cells_template: &CellsTemplate,
+    ) -> eyre::Result<&mut Chunk> {
+        if !self.chunks.contains_key(&pos) {
+            let chunk = match self.load_paged_chunk(pos, cells_template)? {
+                Some(chunk) => chunk,
+                None => self.new_chunk(pos, cells_template),
+            };
+            self.chunks.insert(pos, chunk);
+        }
+
+        Ok(self.chunks.get_mut(&pos).unwrap())
+    }
+
+    /// Remove chunk from the world, paged chunk is loaded and missing one is created.
+    pub fn take_chunk(
+        &mut self,
+        pos: ChunkPos,
+        cells_template: &CellsTemplate,
+    ) -> eyre::Result<Chunk> {
+        if let Some(chunk) = self.chunks.remove(&pos) {
+            return Ok(chunk);
+        }
+
+        Ok(match self.load_paged_chunk(pos, cells_template)? {
+            Some(chunk) => chunk,
+            None => self.new_chunk(pos, cells_template),
+        })
+    }
+
+    /// Drop idle empty chunks and page chunks outside of the focus area (expanded by
+    /// [`ChunkResidency::keep_distance`]) to the [`ChunkStore`] if it's set. Paged chunks aren't
+    /// simulated, see [`ChunkResidency::lossless`]. Region file is compacted when needed.
+    pub fn update_residency(
+        &mut self,
+        focus_min: ChunkPos,
+        focus_max: ChunkPos,
+        residency: ChunkResidency,
+        cells_template: &CellsTemplate,
+    ) -> eyre::Result<ResidencyStats> {
+        let mut stats = ResidencyStats::default();
+        let empty_id = cells_template.cells[0].id;
+
+        let is_far = |pos: ChunkPos| {
+            pos.x < focus_min.x - residency.keep_distance
+                || pos.x > focus_max.x + residency.keep_distance
+                || pos.y < focus_min.y - residency.keep_distance
+                || pos.y > focus_max.y + residency.keep_distance
+        };
+
+        let positions: Vec<ChunkPos> = self.chunks.keys().copied().collect();
+        for pos in positions {
+            let chunk = &self.chunks[&pos];
+
+            if residency.lossless && !chunk.is_idle() {
+                continue;
+            }
+
+            // dropped chunk is created again with the initial state, e.g. temperature
+            if !residency.lossless && chunk.is_idle() && chunk.is_filled_with(empty_id) {
+                self.chunks.remove(&pos);
+                stats.dropped += 1;
+                continue;
+            }
+
+            if let Some(store) = &mut self.chunk_store {
+                if is_far(pos) {
+                    // chunk stays in memory if it can't be written
+                    store.store(pos, chunk, cells_template)?;
+                    self.chunks.remove(&pos);
+                    stats.paged += 1;
+                }
+            }
+        }
+
+        // space of loaded chunks is reclaimed here, so a failure doesn't lose the loaded chunk
+        if let Some(store) = &mut self.chunk_store {
+            store.compact_if_needed()?;
+        }
+
+        Ok(stats)
     }
 
     pub fn set_chunk(&mut self, pos: ChunkPos, chunk: Chunk) -> Option<Chunk> {
         self.chunks.insert(pos, chunk)
     }
 
-    pub fn update_state(&mut self, cells_template: &CellsTemplate) -> usize {
+    /// Simulate one tick, returns the number of updated chunks. Fails only if a paged chunk can't
+    /// be loaded back, the tick isn't simulated then.
+    pub fn update_state(&mut self, cells_template: &CellsTemplate) -> eyre::Result<usize> {
         const GROUP_SIZE: usize = 3;
         let mut update_groups: [Vec<ChunkPos>; GROUP_SIZE * GROUP_SIZE] = Default::default();
         let mut updates_count = 0usize;
@@ -57,7 +272,7 @@ impl WorldState {
                 .chunks
                 .iter()
                 .filter_map(|(&pos, chunk)| {
-                    if chunk.should_update()
+                    if !chunk.is_idle()
                         && true_mod(pos.x, 3) == x_rem
                         && true_mod(pos.y, 3) == y_rem
                     {
@@ -67,10 +282,26 @@ impl WorldState {
                     }
                 })
                 .collect();
+            // chunks order must not depend on the map layout to keep the simulation deterministic
+            chunk_group.sort();
 
             updates_count += chunk_group.len();
         }
 
+        // load paged neighbors first, so no chunk is taken out of the world if it fails
+        for &chunk_pos in update_groups.iter().flatten() {
+            for y in -1..=1 {
+                for x in -1..=1 {
+                    let pos = ChunkPos::new(chunk_pos.x + x, chunk_pos.y + y);
+                    if !self.chunks.contains_key(&pos) {
+                        if let Some(chunk) = self.load_paged_chunk(pos, cells_template)? {
+                            self.chunks.insert(pos, chunk);
+                        }
+                    }
+                }
+            }
+        }
+
         for group in update_groups {
             let mut update_contexts =
                 Vec::<(ChunkUpdateContext, ChunkPos)>::with_capacity(group.len());
@@ -79,16 +310,21 @@ impl WorldState {
                 update_contexts.push((
                     ChunkUpdateContext {
                         cells_template,
+                        rule_evaluator: self.rule_evaluator,
                         current_tick: self.current_tick,
-                        center: self.take_chunk(chunk_pos, cells_template),
-                        left: self.take_chunk(chunk_pos.left(), cells_template),
-                        right: self.take_chunk(chunk_pos.right(), cells_template),
-                        top: self.take_chunk(chunk_pos.top(), cells_template),
-                        bottom: self.take_chunk(chunk_pos.bottom(), cells_template),
-                        left_top: self.take_chunk(chunk_pos.left_top(), cells_template),
-                        right_top: self.take_chunk(chunk_pos.right_top(), cells_template),
-                        left_bottom: self.take_chunk(chunk_pos.left_bottom(), cells_template),
-                        right_bottom: self.take_chunk(chunk_pos.right_bottom(), cells_template),
+                        update_seed: mix_seed(
+                            chunk_seed(self.seed, chunk_pos),
+                            self.current_tick as u64,
+                        ),
+                        center: self.take_chunk(chunk_pos, cells_template)?,
+                        left: self.take_chunk(chunk_pos.left(), cells_template)?,
+                        right: self.take_chunk(chunk_pos.right(), cells_template)?,
+                        top: self.take_chunk(chunk_pos.top(), cells_template)?,
+                        bottom: self.take_chunk(chunk_pos.bottom(), cells_template)?,
+                        left_top: self.take_chunk(chunk_pos.left_top(), cells_template)?,
+                        right_top: self.take_chunk(chunk_pos.right_top(), cells_template)?,
+                        left_bottom: self.take_chunk(chunk_pos.left_bottom(), cells_template)?,
+                        right_bottom: self.take_chunk(chunk_pos.right_bottom(), cells_template)?,
                         delta_time: UPDATE_DELTA_TIME,
                     },
                     chunk_pos,
@@ -116,15 +352,44 @@ impl WorldState {
 
         self.current_tick += 1;
 
-        updates_count
+        Ok(updates_count)
     }
 
-    pub fn set_cell(&mut self, pos: GlobalCellPos, cell: Cell, cells_template: &CellsTemplate) {
-        let chunk = self.ensure_chunk(pos.chunk, cells_template);
+    /// Switch world from `old_template` to `new_template`, cells are matched by label. Cells that
+    /// are missing in the new template are replaced with the first cell of it (Vacuum).
+    pub fn remap_cells(&mut self, old_template: &CellsTemplate, new_template: &CellsTemplate) {
+        let id_map: Vec<Option<CellId>> = old_template
+            .cells
+            .iter()
+            .map(|cell| {
+                new_template
+                    .get_cell_meta_by_label(&cell.label)
+                    .map(|cell| cell.id)
+            })
+            .collect();
+
+        self.chunks
+            .par_iter_mut()
+            .for_each(|(_, chunk)| chunk.remap_cells(&id_map, new_template));
+    }
+
+    pub fn set_cell(
+        &mut self,
+        pos: GlobalCellPos,
+        cell: Cell,
+        cells_template: &CellsTemplate,
+    ) -> eyre::Result<()> {
+        let chunk = self.ensure_chunk(pos.chunk, cells_template)?;
         chunk.set_cell(pos.cell, cell);
 
-        chunk.set_should_update(true);
-        chunk.set_should_redraw(true);
+        chunk.mark_for_update(DirtyRect::around(
+            pos.cell.x as i16,
+            pos.cell.y as i16,
+            cells_template.update_radius,
+        ));
+        chunk.mark_cell_changed(pos.cell.to_index());
+
+        Ok(())
     }
 
     pub fn add_particle_rand_vel(
@@ -132,42 +397,172 @@ impl WorldState {
         pos: GlobalCellPos,
         cell_meta: &CellMeta,
         cells_template: &CellsTemplate,
-    ) {
+    ) -> eyre::Result<()> {
+        let random_value = self.next_random();
         let vel = Vec2::new(
-            rand::random::<f32>() * 2.0 - 1.0,
-            rand::random::<f32>() * 2.0 - 1.0,
+            (random_value as u32) as f32 / u32::MAX as f32 * 2.0 - 1.0,
+            ((random_value >> 32) as u32) as f32 / u32::MAX as f32 * 2.0 - 1.0,
         ) * 10.0;
 
-        self.add_particle(pos, vel, cell_meta, cells_template);
+        self.add_particle(pos, vel, cell_meta, cells_template)
     }
 
+    /// Launch a particle of `cell_meta` from `pos`. Particles can't overlap cells, so nothing is
+    /// spawned if the cell at `pos` isn't replaceable by particles.
     pub fn add_particle(
         &mut self,
         pos: GlobalCellPos,
         vel: Vec2,
         cell_meta: &CellMeta,
         cells_template: &CellsTemplate,
-    ) {
+    ) -> eyre::Result<()> {
         if cell_meta.replaceable_by_particles {
             // if vacuum or something just spawn as a cell
-            self.set_cell(pos, cell_meta.init(), cells_template);
+            return self.set_cell(pos, cell_meta.init(), cells_template);
+        }
 
-            return;
+        let mut placeholder = self
+            .ensure_chunk(pos.chunk, cells_template)?
+            .get_cell(pos.cell);
+        let covered_id = placeholder.id;
+        if !cells_template
+            .get_cell_meta(covered_id)
+            .replaceable_by_particles
+        {
+            return Ok(());
         }
+        placeholder.id = cells_template.particle_placeholder;
+        self.set_cell(pos, placeholder, cells_template)?;
 
-        let chunk = self.ensure_chunk(pos.chunk, cells_template);
-        let in_chunk_pos = pos.cell.to_vec();
-        let color = cell_meta.color.calculate(chunk, pos.cell.to_index());
+        let mut cell = cell_meta.init();
+        cell_meta.color.init_cell(&mut cell, || self.next_random());
+        let color = cell_meta.color.calculate(cell);
+
+        let last_update = self.current_tick.wrapping_sub(1);
+        let chunk = self.ensure_chunk(pos.chunk, cells_template)?;
+        let in_chunk_pos = ParticlePos::from_cell(pos.cell);
 
         let particle = Particle {
             cell_id: cell_meta.id,
             color,
             age: 0,
+            // moved on the next update, even if it's at the current tick
+            last_update,
             gravity: cell_meta.particle_gravity,
             in_chunk_pos,
             vel,
+            covered_id,
+            is_fragment: false,
         };
 
         chunk.particles.push(particle);
+
+        Ok(())
+    }
+}
+
+#[test]
+fn test_remap_cells_by_label() {
+    let old_template = parse_cells_template(
+        r#"(cells: [
+            (label: "Vacuum", color: Plain((0, 0, 0, 0))),
+            (label: "Stone", color: Plain((1, 1, 1, 255))),
+            (label: "Sand", color: Plain((2, 2, 2, 255))),
+        ])"#,
+    )
+    .unwrap();
+    let new_template = parse_cells_template(
+        r#"(cells: [
+            (label: "Vacuum", color: Plain((0, 0, 0, 0))),
+            (label: "Sand", color: Plain((2, 2, 2, 255))),
+        ])"#,
+    )
+    .unwrap();
+
+    let stone = old_template.get_cell_meta_by_label("Stone").unwrap();
+    let sand = old_template.get_cell_meta_by_label("Sand").unwrap();
+
+    let mut world = WorldState::new();
+    let stone_pos = GlobalCellPos::new(-3, 5);
+    let sand_pos = GlobalCellPos::new(200, -1);
+    let mut sand_cell = sand.init();
+    sand_cell.registers[0] = 42;
+    world
+        .set_cell(stone_pos, stone.init(), &old_template)
+        .unwrap();
+    world.set_cell(sand_pos, sand_cell, &old_template).unwrap();
+
+    world.remap_cells(&old_template, &new_template);
+
+    let new_sand_id = new_template.get_cell_meta_by_label("Sand").unwrap().id;
+    let stone_cell = world
+        .get_chunk(stone_pos.chunk)
+        .unwrap()
+        .get_cell(stone_pos.cell);
+    let sand_cell = world
+        .get_chunk(sand_pos.chunk)
+        .unwrap()
+        .get_cell(sand_pos.cell);
+    assert_eq!(stone_cell, new_template.cells[0].init());
+    assert_eq!(sand_cell.id, new_sand_id);
+    assert_eq!(sand_cell.registers[0], 42);
+}
+
+#[cfg(test)]
+fn run_seeded_world(seed: u64, ticks: u32) -> Vec<u8> {
+    let cells_template = default_cells();
+    let sand = cells_template
+        .get_cell_meta_by_label(CELL_SAND_LABEL)
+        .unwrap();
+    let water = cells_template
+        .get_cell_meta_by_label(CELL_WATER_LABEL)
+        .unwrap();
+    let stone = cells_template
+        .get_cell_meta_by_label(CELL_STONE_LABEL)
+        .unwrap();
+
+    let mut world = WorldState::with_seed(seed);
+    gen_rect(
+        &mut world,
+        &cells_template,
+        GlobalCellPos::new(-200, -2),
+        GlobalCellPos::new(200, 0),
+        stone,
+    )
+    .unwrap();
+    gen_rect(
+        &mut world,
+        &cells_template,
+        GlobalCellPos::new(-20, 5),
+        GlobalCellPos::new(20, 60),
+        sand,
+    )
+    .unwrap();
+    gen_rect(
+        &mut world,
+        &cells_template,
+        GlobalCellPos::new(-150, 5),
+        GlobalCellPos::new(-100, 40),
+        water,
+    )
+    .unwrap();
+    for x in 0..10 {
+        world
+            .add_particle_rand_vel(GlobalCellPos::new(x * 7, 100), water, &cells_template)
+            .unwrap();
+    }
+
+    for _ in 0..ticks {
+        world.update_state(&cells_template).unwrap();
     }
+
+    let mut save = Vec::new();
+    write_world(&mut save, &world, &cells_template).unwrap();
+    save
+}
+
+#[test]
+fn test_same_seed_gives_same_world() {
+    assert!(run_seeded_world(7, 60) == run_seeded_world(7, 60));
+    assert!(run_seeded_world(7, 60) != run_seeded_world(8, 60));
 }